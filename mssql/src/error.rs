@@ -7,6 +7,8 @@ pub enum Error {
     PoolInitTimeout,
     #[error("连接检测超时")]
     PoolReuseTimeout,
+    #[error("连接池已关闭")]
+    PoolClosed,
     #[error("连接池错误, {0:?}")]
     PoolError(#[from] crate::pool::Error),
     #[error(transparent)]
@@ -1,6 +1,11 @@
 use crate::{Connection, Result};
 use bb8::{Builder, CustomizeConnection, ErrorSink, ManageConnection};
-use std::time;
+use futures::channel::oneshot;
+use std::{
+    future::Future, pin::Pin, sync::{
+        atomic::{AtomicBool, Ordering}, Arc
+    }, time
+};
 use tokio::time::timeout;
 
 mod wrap;
@@ -9,16 +14,53 @@ pub(crate) use wrap::Error;
 pub(crate) type RawConnection = <wrap::ConnectionManager as ManageConnection>::Connection;
 
 pub type PooledConnection<'pool> = bb8::PooledConnection<'pool, ConnectionManager>;
-pub use bb8::Executor;
+
+/// 异步运行时执行器,用于在指定运行时中建立连接
+pub trait Executor: Send + Sync + 'static {
+    fn execute(&self, fut: Pin<Box<dyn Future<Output = ()> + Send>>);
+}
 
 /// 连接池
 #[derive(Clone)]
-pub struct Pool(bb8::Pool<ConnectionManager>);
+pub struct Pool(bb8::Pool<ConnectionManager>, Arc<AtomicBool>);
 
 impl Pool {
     pub fn builder() -> PoolBuilder { PoolBuilder(Default::default()) }
-    /// 获取数据库连接
-    pub async fn get(&self) -> Result<PooledConnection<'_>> { Ok(self.0.get().await?) }
+    /// 获取数据库连接,连接池关闭后返回[`PoolClosed`](crate::Error::PoolClosed)
+    pub async fn get(&self) -> Result<PooledConnection<'_>> {
+        if self.is_closed() {
+            return Err(crate::Error::PoolClosed);
+        }
+        Ok(self.0.get().await?)
+    }
+    /// 连接池是否已关闭
+    pub fn is_closed(&self) -> bool { self.1.load(Ordering::Acquire) }
+    /// 关闭连接池
+    ///
+    /// 标记关闭后所有句柄(包括其他克隆)的`get`均不再借出连接,并等待已借出的连接归还(最长`wait`),
+    /// 空闲连接在最后一个句柄释放时断开
+    ///
+    /// # Returns
+    ///
+    /// 所有连接是否已归还
+    pub async fn close(self, wait: time::Duration) -> bool {
+        self.1.store(true, Ordering::Release);
+        let deadline = tokio::time::Instant::now() + wait;
+        loop {
+            let state = self.0.state();
+            if state.connections == state.idle_connections {
+                return true;
+            }
+            if tokio::time::Instant::now() >= deadline {
+                warn!(
+                    "db pool close timeout, connections: {}, idle: {}",
+                    state.connections, state.idle_connections
+                );
+                return false;
+            }
+            tokio::time::sleep(time::Duration::from_millis(100)).await;
+        }
+    }
 }
 
 /// 连接池构造器
@@ -59,7 +101,8 @@ impl PoolBuilder {
                 .error_sink(Box::new(PoolErrorSink))
                 .connection_customizer(Box::new(PoolConnectionCustomizer))
                 .build(mgr)
-                .await?
+                .await?,
+            Default::default()
         ))
     }
     /// 构造连接池并建立连接
@@ -78,20 +121,22 @@ impl PoolBuilder {
     /// ## 原因
     ///
     /// `bb8`使用`tokio::spawn`后台任务的方式进行连接建立,而`tokio::net::TcpStream`会注册到当前运行时的事件驱动监听器中(`reactor event driver`),
-    /// 如果此时建立连接的运行时与使用连接的运行时不同,并且建立连接的运行时早于使用连接的运行时销毁将会触发错误
+    /// 如果此时建立连接的运行时与使用连接的运行时不同,并且建立连接的运行时早于使用连接的运行时销毁将会触发错误。
+    /// 指定执行器后连接均在执行器所在的运行时中建立
     pub async fn connect_with_executor(
         self,
         conn_str: impl AsRef<str>,
         executor: impl Executor
     ) -> Result<Pool> {
-        let mgr = ConnectionManager::build(conn_str.as_ref())?;
+        let mgr = ConnectionManager::build(conn_str.as_ref())?.with_executor(executor);
         Ok(Pool(
             self.0
                 .test_on_check_out(true)
                 .error_sink(Box::new(PoolErrorSink))
                 .connection_customizer(Box::new(PoolConnectionCustomizer))
-                .build_with_executor(mgr, executor)
-                .await?
+                .build(mgr)
+                .await?,
+            Default::default()
         ))
     }
     /// 仅构造连接池，不建立连接
@@ -102,7 +147,8 @@ impl PoolBuilder {
                 .test_on_check_out(true)
                 .error_sink(Box::new(PoolErrorSink))
                 .connection_customizer(Box::new(PoolConnectionCustomizer))
-                .build_unchecked(mgr)
+                .build_unchecked(mgr),
+            Default::default()
         ))
     }
     /// 仅构造连接池，不建立连接
@@ -121,25 +167,43 @@ impl PoolBuilder {
     /// ## 原因
     ///
     /// `bb8`使用`tokio::spawn`后台任务的方式进行连接建立,而`tokio::net::TcpStream`会注册到当前运行时的事件驱动监听器中(`reactor event driver`),
-    /// 如果此时建立连接的运行时与使用连接的运行时不同,并且建立连接的运行时早于使用连接的运行时销毁将会触发错误
+    /// 如果此时建立连接的运行时与使用连接的运行时不同,并且建立连接的运行时早于使用连接的运行时销毁将会触发错误。
+    /// 指定执行器后连接均在执行器所在的运行时中建立
     pub fn build_with_executor(self, conn_str: impl AsRef<str>, executor: impl Executor) -> Result<Pool> {
-        let mgr = ConnectionManager::build(conn_str.as_ref())?;
+        let mgr = ConnectionManager::build(conn_str.as_ref())?.with_executor(executor);
         Ok(Pool(
             self.0
                 .test_on_check_out(true)
                 .error_sink(Box::new(PoolErrorSink))
                 .connection_customizer(Box::new(PoolConnectionCustomizer))
-                .build_unchecked_with_executor(mgr, executor)
+                .build_unchecked(mgr),
+            Default::default()
         ))
     }
 }
 
 /// 连接池管理容器
-pub struct ConnectionManager(wrap::ConnectionManager);
+pub struct ConnectionManager(Arc<wrap::ConnectionManager>, Option<Arc<dyn Executor>>);
 
 impl ConnectionManager {
     fn build(conn_string: &str) -> Result<ConnectionManager> {
-        Ok(ConnectionManager(wrap::ConnectionManager::build(conn_string)?))
+        Ok(ConnectionManager(Arc::new(wrap::ConnectionManager::build(conn_string)?), None))
+    }
+    fn with_executor(mut self, executor: impl Executor) -> ConnectionManager {
+        self.1 = Some(Arc::new(executor));
+        self
+    }
+    /// 建立原始连接,指定执行器时在执行器所在的运行时中建立
+    async fn connect_raw(&self) -> Result<RawConnection> {
+        let Some(executor) = self.1.as_ref() else {
+            return self.0.connect().await.map_err(crate::Error::PoolError);
+        };
+        let (tx, rx) = oneshot::channel();
+        let mgr = self.0.clone();
+        executor.execute(Box::pin(async move {
+            let _ = tx.send(mgr.connect().await);
+        }));
+        rx.await.map_err(|_| crate::Error::custom("建立连接的任务已取消"))?.map_err(crate::Error::PoolError)
     }
 }
 
@@ -149,7 +213,7 @@ impl ManageConnection for ConnectionManager {
     type Error = crate::Error;
 
     async fn connect(&self) -> Result<Self::Connection> {
-        let conn = self.0.connect().await.map_err(crate::Error::PoolError)?;
+        let conn = self.connect_raw().await?;
         Ok(Connection::new(conn, self.0.config()))
     }
 
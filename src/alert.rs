@@ -0,0 +1,111 @@
+//!
+//! 管理员告警
+//!
+//! 定期汇总无法送达的记录(手机号无效或未匹配到钉钉账号),按执行人发送到群机器人
+//!
+
+use crate::{
+    config::AlertConfig, dingtalk::DDWebhookRobot, http::HttpClient, outbox::{Outbox, Undeliverable}, shutdown::Shutdown, Error, Result
+};
+use tokio::time;
+
+/// 每次汇总的最大记录数
+const ALERT_BATCH: u32 = 1000;
+/// 告警中列出的最大人数
+const MAX_LINES: usize = 50;
+
+/// 管理员告警
+pub struct Alerter {
+    outbox: Outbox,
+    robot: DDWebhookRobot,
+    cfg: AlertConfig
+}
+
+impl Alerter {
+    pub fn new(outbox: Outbox, http: HttpClient, cfg: AlertConfig) -> Result<Alerter> {
+        if cfg.webhook.is_empty() {
+            return Err(Error::config("未配置告警群机器人webhook"));
+        }
+        Ok(Alerter {
+            outbox,
+            robot: DDWebhookRobot::new(http, cfg.webhook.clone(), cfg.secret.clone()),
+            cfg
+        })
+    }
+
+    /// 定期发送告警直到收到停机信号
+    pub async fn run(&self, mut shutdown: Shutdown) {
+        let mut ticker = time::interval(self.cfg.interval());
+        ticker.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+        //跳过启动时的第一次触发
+        ticker.tick().await;
+        loop {
+            tokio::select! {
+                _ = shutdown.wait() => break,
+                _ = ticker.tick() => {}
+            }
+            match self.alert().await {
+                Ok(0) => {},
+                Ok(count) => info!("alerted {} undeliverable records", count),
+                Err(e) => warn!("send undeliverable alert error: {}", e)
+            }
+        }
+    }
+
+    /// 汇总并发送告警,返回告警的记录数
+    pub async fn alert(&self) -> Result<usize> {
+        let rows = self.outbox.take_undeliverable(ALERT_BATCH).await?;
+        if rows.is_empty() {
+            return Ok(0);
+        }
+        let (title, text) = render(&rows);
+        if let Err(e) = self.robot.send_markdown(&title, &text).await {
+            let ids: Vec<i64> = rows.iter().map(|v| v.id).collect();
+            self.outbox.reset_alerted(&ids).await?;
+            return Err(e);
+        }
+        Ok(rows.len())
+    }
+}
+
+/// 按执行人与手机号汇总的记录
+struct Group<'a> {
+    exeuser: &'a str,
+    userphone: &'a str,
+    count: usize,
+    /// 第一条记录的流程号与原因
+    flownumber: &'a str,
+    reason: &'a str
+}
+
+/// 生成告警标题与markdown内容,按执行人与手机号汇总
+pub fn render(rows: &[Undeliverable]) -> (String, String) {
+    let mut groups: Vec<Group> = vec![];
+    for row in rows {
+        match groups.iter_mut().find(|v| v.exeuser == row.exeuser && v.userphone == row.userphone) {
+            Some(group) => group.count += 1,
+            None => {
+                groups.push(Group {
+                    exeuser: &row.exeuser,
+                    userphone: &row.userphone,
+                    count: 1,
+                    flownumber: &row.flownumber,
+                    reason: row.errmsg.as_deref().unwrap_or_default()
+                });
+            }
+        }
+    }
+    let title = format!("钉钉消息无法送达({}人)", groups.len());
+    let mut text =
+        format!("### {}\n\n以下人员的手机号无效或未匹配到钉钉账号,共{}条消息未送达:\n\n", title, rows.len());
+    for group in groups.iter().take(MAX_LINES) {
+        text.push_str(&format!(
+            "- **{}** {}: {}条, 如{}, {}\n",
+            group.exeuser, group.userphone, group.count, group.flownumber, group.reason
+        ));
+    }
+    if groups.len() > MAX_LINES {
+        text.push_str(&format!("\n...等{}人\n", groups.len()));
+    }
+    (title, text)
+}
@@ -0,0 +1,314 @@
+//!
+//! 钉钉应用注册表
+//!
+//! 每个应用有独立的凭证、token缓存与机器人,消息按`robotcode`或来源公司/数据库路由到应用。
+//! 旧版接口与新版接口使用不同的access_token,分别缓存
+//!
+
+use crate::{
+    audit, config::AppConfig, dingtalk::{
+        self, ApiVersion, DDApi, DDApiToken, DDMediaUpload, DDToken, DDUserGet, DDUserInfo, DDUserid
+    }, phone, Error, Result, User
+};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap}, future::Future, hash::{Hash, Hasher}, sync::Arc
+};
+use tokio::{sync::Mutex, task::JoinSet, time};
+
+/// token提前过期时间(sec),避免临界时使用过期token
+const TOKEN_EXPIRE_AHEAD: u64 = 300;
+
+/// media_id缓存时间(sec),钉钉临时媒体文件有效期为3天
+const MEDIA_CACHE_TTL: u64 = 2 * 24 * 3600;
+
+/// 缓存的token
+struct CachedToken {
+    access_token: String,
+    expires_at: time::Instant
+}
+
+/// 媒体文件缓存键: (媒体类型, 内容哈希, 长度)
+type MediaKey = (String, u64, usize);
+
+/// 缓存的media_id
+struct CachedMedia {
+    media_id: String,
+    expires_at: time::Instant
+}
+
+/// 钉钉应用
+pub struct DDApp {
+    name: String,
+    api: DDApi,
+    token: DDToken,
+    /// 新版接口token
+    api_token: DDApiToken,
+    /// 校验机器人回调签名
+    appsecret: String,
+    /// 机器人编码,第一个为默认机器人
+    robots: Vec<String>,
+    /// 路由的来源公司/数据库
+    sources: Vec<String>,
+    /// 应用AgentId(工作通知)
+    agent_id: Option<i64>,
+    cache: Mutex<Option<CachedToken>>,
+    /// 新版接口token缓存
+    api_cache: Mutex<Option<CachedToken>>,
+    /// 已上传的媒体文件
+    media: Mutex<HashMap<MediaKey, CachedMedia>>
+}
+
+impl DDApp {
+    pub fn new(api: &DDApi, cfg: &AppConfig) -> DDApp {
+        //NOTE 企业内部应用的机器人编码默认与appkey相同
+        let robots = if cfg.robots.is_empty() {
+            vec![cfg.appkey.clone()]
+        } else {
+            cfg.robots.clone()
+        };
+        DDApp {
+            name: cfg.name.clone(),
+            api: api.clone(),
+            token: DDToken::new(api, cfg.appkey.clone(), cfg.appsecret.clone()),
+            api_token: DDApiToken::new(api, cfg.appkey.clone(), cfg.appsecret.clone()),
+            appsecret: cfg.appsecret.clone(),
+            robots,
+            sources: cfg.sources.clone(),
+            agent_id: cfg.agent_id,
+            cache: Mutex::new(None),
+            api_cache: Mutex::new(None),
+            media: Mutex::new(HashMap::new())
+        }
+    }
+    /// 应用名称
+    pub fn name(&self) -> &str { &self.name }
+    /// 钉钉接口地址
+    pub fn api(&self) -> &DDApi { &self.api }
+    /// 应用AgentId,未配置时不能发送工作通知
+    pub fn agent_id(&self) -> Option<i64> { self.agent_id }
+    /// 默认机器人编码
+    pub fn default_robot(&self) -> &str { &self.robots[0] }
+    /// 是否拥有指定机器人
+    pub fn has_robot(&self, robotcode: &str) -> bool { self.robots.iter().any(|v| v == robotcode) }
+    /// 是否路由指定来源
+    pub fn has_source(&self, source: &str) -> bool {
+        self.sources.iter().any(|v| v.eq_ignore_ascii_case(source))
+    }
+    /// 校验机器人回调签名(`timestamp`/`sign`请求头)
+    pub fn verify_sign(&self, timestamp: &str, sign: &str) -> bool {
+        dingtalk::verify_webhook_sign(&self.appsecret, timestamp, sign)
+    }
+    /// 获取旧版接口的access_token,过期前复用缓存
+    pub async fn access_token(&self) -> Result<String> { self.token(ApiVersion::Legacy).await }
+    /// 获取指定版本接口的access_token,过期前复用缓存
+    pub async fn token(&self, version: ApiVersion) -> Result<String> {
+        let mut cache = self.cache(version).lock().await;
+        if let Some(cached) = cache.as_ref() {
+            if cached.expires_at > time::Instant::now() {
+                return Ok(cached.access_token.clone());
+            }
+        }
+        let (access_token, expires_in) = match version {
+            ApiVersion::Legacy => {
+                let rv = self.token.get_token().await?;
+                (rv.access_token, rv.expires_in)
+            },
+            ApiVersion::V1 => {
+                let rv = self.api_token.get_token().await?;
+                (rv.access_token, rv.expire_in)
+            }
+        };
+        debug!("[{}] {} access_token refreshed, expires_in: {}s", self.name, version.as_str(), expires_in);
+        let ttl = expires_in.saturating_sub(TOKEN_EXPIRE_AHEAD);
+        *cache = Some(CachedToken {
+            access_token: access_token.clone(),
+            expires_at: time::Instant::now() + time::Duration::from_secs(ttl)
+        });
+        Ok(access_token)
+    }
+    fn cache(&self, version: ApiVersion) -> &Mutex<Option<CachedToken>> {
+        match version {
+            ApiVersion::Legacy => &self.cache,
+            ApiVersion::V1 => &self.api_cache
+        }
+    }
+    /// 清除指定版本接口缓存的token(如token失效时)
+    pub async fn invalidate_token(&self, version: ApiVersion) { *self.cache(version).lock().await = None; }
+    /// 使用指定版本接口的access_token调用接口,token失效时重新获取并重试一次
+    pub async fn call<T, F, Fut>(&self, version: ApiVersion, f: F) -> Result<T>
+    where
+        F: Fn(String) -> Fut,
+        Fut: Future<Output = Result<T>>
+    {
+        let rv = f(self.token(version).await?).await;
+        match rv {
+            Err(e) if e.is_token_invalid() => {
+                warn!("[{}] {} access_token invalid, retry: {}", self.name, version.as_str(), e);
+                self.invalidate_token(version).await;
+                f(self.token(version).await?).await
+            },
+            rv => rv
+        }
+    }
+    /// 并发解析多个手机号的userid,最多`parallelism`个并发请求
+    ///
+    /// 结果与`mobiles`顺序一致,无效的手机号不调用接口,均无效时不获取token
+    pub async fn resolve_userids(&self, mobiles: &[&str], parallelism: usize) -> Result<Vec<Result<String>>> {
+        let normalized: Vec<Result<String>> = mobiles.iter().map(|mobile| phone::normalize(mobile)).collect();
+        let valid: Vec<String> = normalized.iter().filter_map(|rv| rv.as_ref().ok().cloned()).collect();
+        if valid.is_empty() {
+            return Ok(normalized);
+        }
+        let mut userids = self
+            .call(ApiVersion::Legacy, |access_token| self.lookup_userids(&valid, parallelism, access_token))
+            .await?
+            .into_iter();
+        Ok(normalized.into_iter().map(|rv| rv.and_then(|_| userids.next().unwrap())).collect())
+    }
+    //`mobiles`为已规范化的手机号,任一手机号返回token失效时整批返回该错误,由`call`重新获取token后重试
+    async fn lookup_userids(
+        &self,
+        mobiles: &[String],
+        parallelism: usize,
+        access_token: String
+    ) -> Result<Vec<Result<String>>> {
+        let ctx = audit::context();
+        let mut results: Vec<Option<Result<String>>> = mobiles.iter().map(|_| None).collect();
+        let mut tasks = JoinSet::new();
+        for (idx, mobile) in mobiles.iter().enumerate() {
+            if tasks.len() >= parallelism.max(1) {
+                if let Some(Ok((idx, rv))) = tasks.join_next().await {
+                    results[idx] = Some(rv);
+                }
+            }
+            let scope = ctx.with_phone(mobile);
+            let userid = DDUserid::new(&self.api, access_token.clone(), mobile.clone());
+            tasks.spawn(audit::scope(scope, async move { (idx, userid.get_userid().await) }));
+        }
+        while let Some(rv) = tasks.join_next().await {
+            if let Ok((idx, rv)) = rv {
+                results[idx] = Some(rv);
+            }
+        }
+        let mut results: Vec<Result<String>> = results
+            .into_iter()
+            .map(|v| v.unwrap_or_else(|| Err(Error::custom("解析userid任务异常"))))
+            .collect();
+        if let Some(idx) = results.iter().position(|rv| rv.as_ref().is_err_and(Error::is_token_invalid)) {
+            return Err(results.swap_remove(idx).unwrap_err());
+        }
+        Ok(results)
+    }
+    /// 按userid查询通讯录中的用户详情
+    pub async fn user_info(&self, userid: &str) -> Result<DDUserInfo> {
+        self.call(ApiVersion::Legacy, |access_token| {
+            async move { DDUserGet::new(&self.api, access_token, userid.to_owned()).get_user().await }
+        })
+        .await
+    }
+    /// 上传媒体文件,相同内容复用缓存的media_id
+    pub async fn upload_media(&self, media_type: &str, file_name: &str, data: Vec<u8>) -> Result<String> {
+        let mut hasher = DefaultHasher::new();
+        data.hash(&mut hasher);
+        let key = (media_type.to_owned(), hasher.finish(), data.len());
+        let now = time::Instant::now();
+        {
+            let mut media = self.media.lock().await;
+            media.retain(|_, cached| cached.expires_at > now);
+            if let Some(cached) = media.get(&key) {
+                return Ok(cached.media_id.clone());
+            }
+        }
+        let media_id = self
+            .call(ApiVersion::Legacy, |access_token| {
+                let upload = DDMediaUpload::new(
+                    &self.api,
+                    access_token,
+                    media_type.to_owned(),
+                    file_name.to_owned(),
+                    data.clone()
+                );
+                async move { upload.upload().await }
+            })
+            .await?;
+        debug!("[{}] media uploaded: {}, media_id: {}", self.name, file_name, media_id);
+        self.media.lock().await.insert(key, CachedMedia {
+            media_id: media_id.clone(),
+            expires_at: now + time::Duration::from_secs(MEDIA_CACHE_TTL)
+        });
+        Ok(media_id)
+    }
+}
+
+/// 钉钉应用注册表
+#[derive(Clone)]
+pub struct AppRegistry {
+    apps: Vec<Arc<DDApp>>,
+    /// 默认应用下标
+    default: Option<usize>
+}
+
+impl AppRegistry {
+    pub fn new(api: &DDApi, cfgs: &[AppConfig]) -> Result<AppRegistry> {
+        if cfgs.is_empty() {
+            return Err(Error::config("未配置钉钉应用"));
+        }
+        let mut default = None;
+        for (idx, cfg) in cfgs.iter().enumerate() {
+            if cfgs[..idx].iter().any(|v| v.name == cfg.name) {
+                return Err(Error::config(format!("钉钉应用名称重复: {}", cfg.name)));
+            }
+            if cfg.default {
+                if default.is_some() {
+                    return Err(Error::config("只能指定一个默认钉钉应用"));
+                }
+                default = Some(idx);
+            }
+        }
+        //只有一个应用时作为默认应用
+        if cfgs.len() == 1 {
+            default = Some(0);
+        }
+        Ok(AppRegistry {
+            apps: cfgs.iter().map(|cfg| Arc::new(DDApp::new(api, cfg))).collect(),
+            default
+        })
+    }
+    /// 所有应用
+    pub fn apps(&self) -> &[Arc<DDApp>] { &self.apps }
+    /// 按名称查找应用
+    pub fn get(&self, name: &str) -> Option<&Arc<DDApp>> { self.apps.iter().find(|app| app.name == name) }
+    /// 按机器人编码查找应用
+    pub fn by_robot(&self, robotcode: &str) -> Option<&Arc<DDApp>> {
+        self.apps.iter().find(|app| app.has_robot(robotcode))
+    }
+    /// 路由消息
+    ///
+    /// 优先级: 机器人编码 > 来源公司/数据库 > 默认应用
+    ///
+    /// # Returns
+    ///
+    /// 返回应用及发送使用的机器人编码
+    pub fn route(&self, user: &User) -> Result<(Arc<DDApp>, String)> {
+        if !user.robotcode.is_empty() {
+            if let Some(app) = self.apps.iter().find(|app| app.has_robot(&user.robotcode)) {
+                return Ok((app.clone(), user.robotcode.clone()));
+            }
+        }
+        let app = self.by_source(user.source.as_deref()).ok_or_else(|| {
+            Error::custom(format!(
+                "无法路由到钉钉应用, robotcode: {}, source: {}",
+                user.robotcode,
+                user.source.as_deref().unwrap_or_default()
+            ))
+        })?;
+        Ok((app.clone(), app.default_robot().to_owned()))
+    }
+    /// 按来源公司/数据库查找应用,未匹配时返回默认应用
+    pub fn by_source(&self, source: Option<&str>) -> Option<&Arc<DDApp>> {
+        source
+            .filter(|v| !v.is_empty())
+            .and_then(|source| self.apps.iter().find(|app| app.has_source(source)))
+            .or_else(|| self.default.map(|idx| &self.apps[idx]))
+    }
+}
@@ -0,0 +1,564 @@
+//!
+//! 聊天审批
+//!
+//! 接收机器人消息回调(HTTP模式),支持的指令:
+//!
+//! - `同意 <流程号> [意见]`
+//! - `驳回 <流程号> <原因>`
+//! - `待办 [页码]` 按通讯录中发送人的手机号查询待办流程,以markdown分页回复
+//!
+//! 回调按`robotCode`对应应用的appsecret校验签名,校验发送人为流程的当前审批人后
+//! 在事务中执行配置的SQL写回ERP流程表,并回复处理结果
+//!
+//! 互动卡片(`interactiveCard`)的同意/驳回按钮回调按`card_secret`校验签名,
+//! 处理后更新卡片参数`status`(`approved`/`rejected`/`closed`)、`operator`、`message`,
+//! 所有接收人的卡片同步显示处理结果
+//!
+
+use crate::{
+    app::DDApp, card, config::{ApprovalConfig, LinkTarget}, dingtalk::{self, ApiVersion, DDCardData, DDInteractiveCardUpdate}, outbox::Outbox, sender::Delivery, Error, Result
+};
+use arc_swap::ArcSwap;
+use mssql::Sql;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::{
+    collections::HashMap, sync::Arc, time::{SystemTime, UNIX_EPOCH}
+};
+
+/// 解析审批人userid的并发数
+const RESOLVE_CONCURRENCY: usize = 4;
+
+/// 指令说明
+pub const USAGE: &str = "支持的指令:\n同意 <流程号> [意见]\n驳回 <流程号> <原因>\n待办 [页码]";
+
+/// 机器人收到的消息(回调请求体)
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RobotMessage {
+    #[serde(default)]
+    pub msgtype: String,
+    #[serde(default)]
+    pub text: Option<RobotText>,
+    /// 发送人userid
+    #[serde(default)]
+    pub sender_staff_id: String,
+    #[serde(default)]
+    pub sender_nick: String,
+    #[serde(default)]
+    pub robot_code: String,
+    /// 1: 单聊, 2: 群聊
+    #[serde(default)]
+    pub conversation_type: String
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RobotText {
+    #[serde(default)]
+    pub content: String
+}
+
+impl RobotMessage {
+    /// 文本内容
+    pub fn content(&self) -> &str { self.text.as_ref().map(|v| v.content.trim()).unwrap_or_default() }
+}
+
+/// 审批指令
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// 同意
+    Approve {
+        flownumber: String,
+        comment: String
+    },
+    /// 驳回
+    Reject {
+        flownumber: String,
+        reason: String
+    },
+    /// 查询待办,页码从1开始
+    Todo {
+        page: u32
+    }
+}
+
+impl Command {
+    /// 解析指令,不是支持的指令时返回`None`
+    pub fn parse(text: &str) -> Result<Option<Command>> {
+        let mut parts = text.split_whitespace();
+        let approve = match parts.next() {
+            Some("同意" | "approve") => true,
+            Some("驳回" | "reject") => false,
+            Some("待办" | "todo") => {
+                let page = match parts.next() {
+                    Some(v) => {
+                        v.parse()
+                            .ok()
+                            .filter(|v| *v > 0)
+                            .ok_or_else(|| Error::Invalid(format!("无效的页码, {}", v)))?
+                    },
+                    None => 1
+                };
+                return Ok(Some(Command::Todo {
+                    page
+                }));
+            },
+            _ => return Ok(None)
+        };
+        let flownumber = parts.next().ok_or_else(|| Error::Invalid("缺少流程号".to_owned()))?.to_owned();
+        let remark = parts.collect::<Vec<_>>().join(" ");
+        Ok(Some(match approve {
+            true => {
+                Command::Approve {
+                    flownumber,
+                    comment: remark
+                }
+            },
+            false => {
+                Command::Reject {
+                    flownumber,
+                    reason: remark
+                }
+            },
+        }))
+    }
+    /// 流程号,查询待办时为`None`
+    pub fn flownumber(&self) -> Option<&str> {
+        match self {
+            Command::Approve {
+                flownumber,
+                ..
+            } |
+            Command::Reject {
+                flownumber,
+                ..
+            } => Some(flownumber),
+            Command::Todo {
+                ..
+            } => None
+        }
+    }
+}
+
+/// 回复内容
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reply {
+    Text(String),
+    Markdown {
+        title: String,
+        text: String
+    }
+}
+
+impl Reply {
+    /// 回调响应的消息体
+    pub fn to_json(&self) -> Value {
+        match self {
+            Reply::Text(content) => json!({ "msgtype": "text", "text": { "content": content } }),
+            Reply::Markdown {
+                title,
+                text
+            } => json!({ "msgtype": "markdown", "markdown": { "title": title, "text": text } })
+        }
+    }
+}
+
+/// 待办流程
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Todo {
+    pub flownumber: String,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub created_at: Option<String>
+}
+
+/// 互动卡片回调请求体
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CardCallback {
+    pub out_track_id: String,
+    /// 点击按钮的用户userid
+    #[serde(default)]
+    pub user_id: String,
+    /// 回调数据(JSON字符串),包含`cardPrivateData.actionIds`与`cardPrivateData.params`
+    #[serde(default, alias = "content")]
+    pub value: String
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CardValue {
+    #[serde(default)]
+    card_private_data: CardPrivateData
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CardPrivateData {
+    #[serde(default)]
+    action_ids: Vec<String>,
+    #[serde(default)]
+    params: Map<String, Value>
+}
+
+impl CardCallback {
+    /// 按点击的按钮(`approve`/`reject`)与输入的意见(`remark`)生成审批指令
+    pub fn command(&self, flownumber: &str) -> Result<Command> {
+        let value: CardValue = match self.value.trim() {
+            "" => CardValue::default(),
+            v => serde_json::from_str(v)?
+        };
+        let data = value.card_private_data;
+        let remark = data.params.get("remark").and_then(Value::as_str).unwrap_or_default().trim().to_owned();
+        let flownumber = flownumber.to_owned();
+        match data.action_ids.first().map(String::as_str) {
+            Some("approve") => {
+                Ok(Command::Approve {
+                    flownumber,
+                    comment: remark
+                })
+            },
+            Some("reject") => {
+                Ok(Command::Reject {
+                    flownumber,
+                    reason: remark
+                })
+            },
+            Some(action) => Err(Error::Invalid(format!("未知的操作, {}", action))),
+            None => Err(Error::Invalid("缺少操作".to_owned()))
+        }
+    }
+}
+
+/// 审批结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decided {
+    /// 已写回流程表,审批人姓名
+    Done(String),
+    /// 流程不存在或已处理
+    NotPending,
+    /// 发送人不是当前审批人
+    NotAssignee
+}
+
+/// 审批人
+#[derive(Debug, Clone, Default)]
+pub struct Approver {
+    pub userid: String,
+    pub nick: String
+}
+
+/// 聊天审批
+pub struct Approvals {
+    outbox: Outbox,
+    delivery: Arc<ArcSwap<Delivery>>,
+    cfg: ApprovalConfig
+}
+
+impl Approvals {
+    pub fn new(outbox: Outbox, delivery: Arc<ArcSwap<Delivery>>, cfg: ApprovalConfig) -> Result<Approvals> {
+        for (name, sql) in [
+            ("assignee_sql", &cfg.assignee_sql),
+            ("approve_sql", &cfg.approve_sql),
+            ("reject_sql", &cfg.reject_sql)
+        ] {
+            if sql.trim().is_empty() {
+                return Err(Error::config(format!("启用聊天审批需配置approval.{}", name)));
+            }
+        }
+        Ok(Approvals {
+            outbox,
+            delivery,
+            cfg
+        })
+    }
+
+    /// 校验回调签名,返回机器人所属应用
+    ///
+    /// `timestamp`为毫秒时间戳,与当前时间的偏差不能超过`max_skew`
+    pub fn verify(&self, robotcode: &str, timestamp: &str, sign: &str) -> Option<Arc<DDApp>> {
+        if !self.fresh(timestamp) {
+            return None;
+        }
+        let delivery = self.delivery.load();
+        let app = delivery.apps().by_robot(robotcode)?;
+        app.verify_sign(timestamp, sign).then(|| app.clone())
+    }
+
+    /// 校验互动卡片回调签名,未配置`card_secret`时始终无效
+    pub fn verify_card(&self, timestamp: &str, sign: &str) -> bool {
+        match self.cfg.card_secret.as_deref().filter(|v| !v.is_empty()) {
+            Some(secret) => self.fresh(timestamp) && dingtalk::verify_webhook_sign(secret, timestamp, sign),
+            None => false
+        }
+    }
+
+    /// 毫秒时间戳与当前时间的偏差不超过`max_skew`
+    fn fresh(&self, timestamp: &str) -> bool {
+        let Ok(ts) = timestamp.parse::<u128>() else {
+            return false;
+        };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+        now.abs_diff(ts) <= self.cfg.max_skew().as_millis()
+    }
+
+    /// 处理机器人消息,返回回复内容
+    pub async fn handle(&self, app: &DDApp, msg: &RobotMessage) -> Reply {
+        let cmd = match Command::parse(msg.content()) {
+            Ok(Some(cmd)) => cmd,
+            Ok(None) => return Reply::Text(USAGE.to_owned()),
+            Err(e) => return Reply::Text(format!("{}\n{}", e, USAGE))
+        };
+        let approver = Approver {
+            userid: msg.sender_staff_id.clone(),
+            nick: msg.sender_nick.clone()
+        };
+        if let Command::Todo {
+            page
+        } = cmd
+        {
+            return match self.todos(app, &approver.userid).await {
+                Ok(todos) => self.todo_reply(&todos, page),
+                Err(Error::Invalid(msg)) => Reply::Text(msg),
+                Err(e) => {
+                    error!("{} todo of {} error: {}", app.name(), approver.userid, e);
+                    Reply::Text("查询失败, 请稍后重试".to_owned())
+                }
+            };
+        }
+        let flownumber = cmd.flownumber().unwrap_or_default().to_owned();
+        let reply = match self.decide(app, &approver, &cmd).await {
+            Ok(Decided::Done(..)) => {
+                match cmd {
+                    Command::Reject {
+                        reason,
+                        ..
+                    } => format!("已驳回 {}, 原因: {}", flownumber, reason),
+                    _ => format!("已同意 {}", flownumber)
+                }
+            },
+            Ok(Decided::NotPending) => format!("流程{}不存在或已处理", flownumber),
+            Ok(Decided::NotAssignee) => format!("您不是流程{}的当前审批人", flownumber),
+            Err(Error::Invalid(msg)) => msg,
+            Err(e) => {
+                error!("{} {} by {} error: {}", app.name(), flownumber, approver.userid, e);
+                "处理失败, 请稍后重试".to_owned()
+            }
+        };
+        Reply::Text(reply)
+    }
+
+    /// 按通讯录中的手机号查询待办流程
+    pub async fn todos(&self, app: &DDApp, userid: &str) -> Result<Vec<Todo>> {
+        if self.cfg.todo_sql.trim().is_empty() {
+            return Err(Error::Invalid("未启用待办查询".to_owned()));
+        }
+        if userid.is_empty() {
+            return Err(Error::Invalid("无法识别发送人".to_owned()));
+        }
+        let info = match app.user_info(userid).await {
+            Ok(info) => info,
+            Err(e) if e.is_undeliverable() => {
+                return Err(Error::Invalid("通讯录中未找到您的账号".to_owned()))
+            },
+            Err(e) => return Err(e)
+        };
+        if info.mobile.is_empty() {
+            return Err(Error::Invalid("通讯录中未找到您的手机号".to_owned()));
+        }
+        let mut query = Sql::new(self.cfg.todo_sql.clone());
+        query.bind(info.mobile);
+        query.bind(userid.to_owned());
+        let conn = self.outbox.pool().get().await?;
+        let todos = conn.query_collect(query).await?;
+        Ok(todos)
+    }
+
+    /// 待办列表的第`page`页
+    pub fn todo_reply(&self, todos: &[Todo], page: u32) -> Reply {
+        if todos.is_empty() {
+            return Reply::Text("您没有待办流程".to_owned());
+        }
+        let size = self.cfg.todo_page_size.max(1) as usize;
+        let pages = todos.len().div_ceil(size);
+        let page = (page as usize).min(pages);
+        let mut text = format!("#### 待办流程 (共{}条, 第{}/{}页)\n\n", todos.len(), page, pages);
+        for (idx, todo) in todos.iter().enumerate().skip((page - 1) * size).take(size) {
+            let vars = HashMap::from([("flownumber".to_owned(), todo.flownumber.clone())]);
+            let link = match self.cfg.todo_url.as_str() {
+                "" => None,
+                url => card::render(url, &vars, true).ok().map(|url| card::wrap_link(&url, LinkTarget::App))
+            };
+            let flownumber = match link {
+                Some(link) => format!("[{}]({})", todo.flownumber, link),
+                None => todo.flownumber.clone()
+            };
+            text.push_str(&format!("{}. {}", idx + 1, flownumber));
+            for v in [&todo.title, &todo.created_at].into_iter().flatten().filter(|v| !v.is_empty()) {
+                text.push_str(&format!(" {}", v));
+            }
+            text.push('\n');
+        }
+        if page < pages {
+            text.push_str(&format!("\n发送 \"待办 {}\" 查看下一页", page + 1));
+        }
+        Reply::Markdown {
+            title: "待办流程".to_owned(),
+            text
+        }
+    }
+
+    /// 处理互动卡片按钮回调,返回点击人看到的卡片数据
+    ///
+    /// 流程已处理时更新所有接收人的卡片;校验失败只返回给点击人
+    pub async fn handle_card(&self, cb: &CardCallback) -> DDCardData {
+        let (status, operator, message) = match self.decide_card(cb).await {
+            Ok((app, cmd, decided)) => {
+                let (status, operator, message) = match (decided, cmd) {
+                    (
+                        Decided::Done(name),
+                        Command::Reject {
+                            reason,
+                            ..
+                        }
+                    ) => ("rejected", name, reason),
+                    (
+                        Decided::Done(name),
+                        Command::Approve {
+                            comment,
+                            ..
+                        }
+                    ) => ("approved", name, comment),
+                    (Decided::Done(name), _) => ("approved", name, String::new()),
+                    (Decided::NotPending, cmd) => {
+                        let flownumber = cmd.flownumber().unwrap_or_default();
+                        ("closed", String::new(), format!("流程{}不存在或已处理", flownumber))
+                    },
+                    (Decided::NotAssignee, cmd) => {
+                        let flownumber = cmd.flownumber().unwrap_or_default();
+                        return card_data(None, "", &format!("您不是流程{}的当前审批人", flownumber));
+                    }
+                };
+                let data = card_data(Some(status), &operator, &message);
+                if let Err(e) = self.update_card(&app, &cb.out_track_id, data.clone()).await {
+                    warn!("update card {} error: {}", cb.out_track_id, e);
+                }
+                return data;
+            },
+            Err(Error::Invalid(msg)) => (None, String::new(), msg),
+            Err(e) => {
+                error!("card callback {} by {} error: {}", cb.out_track_id, cb.user_id, e);
+                (None, String::new(), "处理失败, 请稍后重试".to_owned())
+            }
+        };
+        card_data(status, &operator, &message)
+    }
+
+    /// 按卡片对应的发件箱记录审批
+    async fn decide_card(&self, cb: &CardCallback) -> Result<(Arc<DDApp>, Command, Decided)> {
+        let id = card::parse_out_track_id(&cb.out_track_id)
+            .ok_or_else(|| Error::Invalid(format!("无效的outTrackId, {}", cb.out_track_id)))?;
+        let user = self.outbox.get(id).await?.ok_or_else(|| Error::Invalid("发件箱记录不存在".to_owned()))?;
+        let cmd = cb.command(&user.flownumber)?;
+        let (app, _) = self.delivery.load().apps().route(&user)?;
+        let approver = Approver {
+            userid: cb.user_id.clone(),
+            nick: String::new()
+        };
+        let decided = self.decide(&app, &approver, &cmd).await?;
+        Ok((app, cmd, decided))
+    }
+
+    /// 更新所有接收人的卡片
+    async fn update_card(&self, app: &DDApp, out_track_id: &str, data: DDCardData) -> Result<()> {
+        app.call(ApiVersion::V1, |access_token| {
+            let req =
+                DDInteractiveCardUpdate::new(app.api(), access_token, out_track_id.to_owned(), data.clone());
+            async move { req.update().await }
+        })
+        .await
+    }
+
+    /// 校验审批人并写回流程表
+    pub async fn decide(&self, app: &DDApp, approver: &Approver, cmd: &Command) -> Result<Decided> {
+        let (sql, remark, flownumber) = match cmd {
+            Command::Approve {
+                comment,
+                flownumber
+            } => (&self.cfg.approve_sql, comment, flownumber),
+            Command::Reject {
+                reason,
+                flownumber
+            } => {
+                if self.cfg.reject_reason_required && reason.trim().is_empty() {
+                    return Err(Error::Invalid("驳回须填写原因".to_owned()));
+                }
+                (&self.cfg.reject_sql, reason, flownumber)
+            },
+            Command::Todo {
+                ..
+            } => return Err(Error::Invalid("不是审批指令".to_owned()))
+        };
+        if approver.userid.is_empty() {
+            return Ok(Decided::NotAssignee);
+        }
+        let conn = self.outbox.pool().get().await?;
+        let mut query = Sql::new(self.cfg.assignee_sql.clone());
+        query.bind(flownumber.clone());
+        //第一列为手机号,第二列(可选)为姓名
+        let assignees: Vec<(String, Option<String>)> = conn
+            .query_collect_row(query)
+            .await?
+            .iter()
+            .filter_map(|row| {
+                let mobile = row.try_get_str(0).ok().flatten()?.to_owned();
+                let name = match row.column_count() > 1 {
+                    true => row.try_get_str(1).ok().flatten().map(str::to_owned),
+                    false => None
+                };
+                Some((mobile, name))
+            })
+            .collect();
+        //解析userid需调用钉钉接口,期间不占用连接
+        drop(conn);
+        if assignees.is_empty() {
+            return Ok(Decided::NotPending);
+        }
+        let refs: Vec<&str> = assignees.iter().map(|(mobile, _)| mobile.as_str()).collect();
+        let userids = app.resolve_userids(&refs, RESOLVE_CONCURRENCY).await?;
+        let (mobile, name) =
+            match userids.iter().position(|v| v.as_ref().is_ok_and(|v| *v == approver.userid)) {
+                Some(idx) => assignees[idx].clone(),
+                None => return Ok(Decided::NotAssignee)
+            };
+        let name = name.filter(|v| !v.is_empty()).unwrap_or_else(|| mobile.clone());
+
+        let mut update = Sql::new(sql.clone());
+        update.bind(flownumber.clone());
+        update.bind(mobile);
+        update.bind(remark.clone());
+        update.bind(approver.userid.clone());
+        update.bind(approver.nick.clone());
+        let conn = self.outbox.pool().get().await?;
+        let affected = conn.scoped_trans(async { conn.exec(update).await }).await?;
+        info!("approval {:?} by {}, affected: {}", cmd, approver.userid, affected);
+        Ok(match affected {
+            0 => Decided::NotPending,
+            _ => Decided::Done(name)
+        })
+    }
+}
+
+/// 卡片参数,`status`为空时不更新状态
+fn card_data(status: Option<&str>, operator: &str, message: &str) -> DDCardData {
+    let mut params = Map::new();
+    if let Some(status) = status {
+        params.insert("status".to_owned(), json!(status));
+        params.insert("operator".to_owned(), json!(operator));
+    }
+    params.insert("message".to_owned(), json!(message));
+    DDCardData {
+        card_param_map: params
+    }
+}
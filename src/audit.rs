@@ -0,0 +1,277 @@
+//!
+//! 钉钉接口审计日志
+//!
+//! 每次钉钉接口调用(请求参数已隐藏敏感字段)通过队列异步写入审计表,
+//! 写入失败或队列已满时丢弃记录,不影响消息发送。
+//! 手机号按规范化后的格式记录,一次调用涉及多个手机号时以`,`分隔
+//!
+
+use crate::{config::AuditConfig, phone, Result};
+use mssql::{sql_bind, Pool};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::future::Future;
+use tokio::{sync::mpsc, time};
+
+/// 审计表结构
+const AUDIT_DDL: &str = "
+CREATE TABLE sendmsg_audit (
+    id BIGINT IDENTITY(1,1) NOT NULL PRIMARY KEY,
+    method VARCHAR(10) NOT NULL,
+    endpoint VARCHAR(200) NOT NULL,
+    params NVARCHAR(4000) NULL,
+    status INT NOT NULL,
+    errcode VARCHAR(100) NULL,
+    errmsg NVARCHAR(1000) NULL,
+    request_id VARCHAR(100) NULL,
+    latency_ms INT NOT NULL,
+    flownumber NVARCHAR(100) NULL,
+    userphone VARCHAR(500) NULL,
+    created_at DATETIME NOT NULL DEFAULT GETDATE()
+);
+CREATE INDEX ix_sendmsg_audit_flownumber ON sendmsg_audit(flownumber);
+CREATE INDEX ix_sendmsg_audit_userphone ON sendmsg_audit(userphone);
+CREATE INDEX ix_sendmsg_audit_created_at ON sendmsg_audit(created_at);
+";
+
+/// 每次清理删除的最大记录数
+const PRUNE_BATCH: i32 = 5000;
+
+tokio::task_local! {
+    static CONTEXT: AuditContext;
+}
+
+/// 审计上下文,关联接口调用与流程
+#[derive(Debug, Clone, Default)]
+pub struct AuditContext {
+    pub flownumber: Option<String>,
+    pub userphone: Option<String>
+}
+
+impl AuditContext {
+    /// `userphone`为发件箱记录中的手机号(可有多个)
+    pub fn new(flownumber: &str, userphone: &str) -> AuditContext {
+        AuditContext {
+            flownumber: Some(flownumber.to_owned()),
+            userphone: Some(normalize_phones(userphone))
+        }
+    }
+    /// 关联单个手机号的调用(如按手机号解析userid)
+    pub fn with_phone(&self, mobile: &str) -> AuditContext {
+        AuditContext {
+            flownumber: self.flownumber.clone(),
+            userphone: Some(normalize_phones(mobile))
+        }
+    }
+}
+
+/// 规范化手机号,多个以`,`分隔;均无效时保留原值
+fn normalize_phones(phones: &str) -> String {
+    let mut mobiles: Vec<String> = vec![];
+    for mobile in phone::split(phones).into_iter().filter_map(|v| phone::normalize(v).ok()) {
+        if !mobiles.contains(&mobile) {
+            mobiles.push(mobile);
+        }
+    }
+    match mobiles.is_empty() {
+        true => phones.trim().to_owned(),
+        false => mobiles.join(",")
+    }
+}
+
+/// 在审计上下文中执行
+pub async fn scope<F: Future>(ctx: AuditContext, f: F) -> F::Output { CONTEXT.scope(ctx, f).await }
+
+/// 当前审计上下文
+pub fn context() -> AuditContext { CONTEXT.try_with(|v| v.clone()).unwrap_or_default() }
+
+/// 审计记录
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuditEntry {
+    #[serde(default)]
+    pub id: i64,
+    pub method: String,
+    /// 接口路径
+    pub endpoint: String,
+    /// 请求参数(已隐藏敏感字段)
+    pub params: Option<String>,
+    /// HTTP状态,请求失败时为0
+    pub status: i32,
+    /// 旧版接口`errcode`或新版接口`code`
+    pub errcode: Option<String>,
+    pub errmsg: Option<String>,
+    pub request_id: Option<String>,
+    pub latency_ms: i32,
+    pub flownumber: Option<String>,
+    pub userphone: Option<String>,
+    #[serde(default)]
+    pub created_at: Option<String>
+}
+
+impl AuditEntry {
+    /// 从响应内容提取错误码、错误信息与请求ID
+    pub fn parse_response(&mut self, text: &str) {
+        let Ok(Value::Object(obj)) = serde_json::from_str::<Value>(text) else {
+            self.errmsg = Some(text.chars().take(200).collect());
+            return;
+        };
+        let field = |keys: &[&str]| {
+            keys.iter().find_map(|k| {
+                match obj.get(*k) {
+                    Some(Value::String(v)) => Some(v.clone()),
+                    Some(Value::Number(v)) => Some(v.to_string()),
+                    _ => None
+                }
+            })
+        };
+        self.errcode = field(&["errcode", "code"]);
+        self.errmsg = field(&["errmsg", "message"]);
+        self.request_id = field(&["request_id", "requestid", "requestId"]);
+    }
+}
+
+/// 审计记录发送端,队列已满时丢弃记录
+#[derive(Debug, Clone)]
+pub struct AuditSink(mpsc::Sender<AuditEntry>);
+
+impl AuditSink {
+    pub fn record(&self, entry: AuditEntry) {
+        if let Err(e) = self.0.try_send(entry) {
+            warn!("audit queue full, entry dropped: {}", e);
+        }
+    }
+}
+
+/// 创建审计队列
+pub fn channel(queue_size: usize) -> (AuditSink, mpsc::Receiver<AuditEntry>) {
+    let (tx, rx) = mpsc::channel(queue_size.max(1));
+    (AuditSink(tx), rx)
+}
+
+/// 审计日志
+#[derive(Clone)]
+pub struct Audit {
+    pool: Pool,
+    cfg: AuditConfig
+}
+
+impl Audit {
+    pub fn new(pool: Pool, cfg: AuditConfig) -> Audit {
+        Audit {
+            pool,
+            cfg
+        }
+    }
+    /// 创建审计表(不存在时)
+    pub async fn ensure_schema(&self) -> Result<()> {
+        let conn = self.pool.get().await?;
+        if !conn.object_exists("sendmsg_audit").await? {
+            conn.exec(AUDIT_DDL).await?;
+        }
+        //旧版本表结构userphone为VARCHAR(50)
+        let len = conn
+            .query_scalar_i32(
+                "SELECT CAST(max_length AS INT) FROM sys.columns
+                WHERE object_id = OBJECT_ID('sendmsg_audit') AND name = 'userphone'"
+            )
+            .await?;
+        if len.is_some_and(|v| v > 0 && v < 500) {
+            conn.exec("ALTER TABLE sendmsg_audit ALTER COLUMN userphone VARCHAR(500) NULL").await?;
+        }
+        Ok(())
+    }
+    /// 写入审计记录
+    pub async fn insert(&self, entry: &AuditEntry) -> Result<()> {
+        let conn = self.pool.get().await?;
+        conn.exec(sql_bind!(
+            "INSERT INTO sendmsg_audit
+                (method, endpoint, params, status, errcode, errmsg, request_id, latency_ms, flownumber, userphone)
+            VALUES (@P1, @P2, LEFT(@P3, 4000), @P4, @P5, LEFT(@P6, 1000), @P7, @P8, @P9, @P10)",
+            entry.method.clone(),
+            entry.endpoint.clone(),
+            entry.params.clone(),
+            entry.status,
+            entry.errcode.clone(),
+            entry.errmsg.clone(),
+            entry.request_id.clone(),
+            entry.latency_ms,
+            entry.flownumber.clone(),
+            entry.userphone.clone()
+        ))
+        .await?;
+        Ok(())
+    }
+    /// 删除超过保留天数的记录
+    ///
+    /// 返回删除的记录数
+    pub async fn prune(&self) -> Result<u64> {
+        let conn = self.pool.get().await?;
+        let mut pruned = 0;
+        loop {
+            let deleted = conn
+                .exec(sql_bind!(
+                    "DELETE TOP(@P1) FROM sendmsg_audit WHERE created_at < DATEADD(DAY, -@P2, GETDATE())",
+                    PRUNE_BATCH,
+                    self.cfg.retention_days as i32
+                ))
+                .await?;
+            pruned += deleted;
+            if deleted < PRUNE_BATCH as u64 {
+                break;
+            }
+        }
+        Ok(pruned)
+    }
+    /// 按流程号或手机号查询审计记录,按时间倒序
+    ///
+    /// 手机号规范化后匹配,包括涉及多个手机号的调用
+    pub async fn query(
+        &self,
+        flownumber: Option<&str>,
+        userphone: Option<&str>,
+        limit: u32
+    ) -> Result<Vec<AuditEntry>> {
+        let conn = self.pool.get().await?;
+        let entries = conn
+            .query_collect(sql_bind!(
+                "SELECT TOP(@P1) * FROM sendmsg_audit
+                WHERE (@P2 IS NULL OR flownumber = @P2)
+                    AND (@P3 IS NULL OR ',' + userphone + ',' LIKE '%,' + @P3 + ',%')
+                ORDER BY id DESC",
+                limit as i32,
+                flownumber.map(str::to_owned),
+                userphone.map(|v| phone::normalize(v).unwrap_or_else(|_| v.trim().to_owned()))
+            ))
+            .await?;
+        Ok(entries)
+    }
+
+    /// 写入队列中的审计记录并定期清理
+    ///
+    /// 所有发送端(`AuditSink`)释放后写入剩余记录并返回
+    pub async fn run(&self, mut rx: mpsc::Receiver<AuditEntry>) {
+        let mut ticker = time::interval(self.cfg.prune_interval());
+        ticker.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                entry = rx.recv() => match entry {
+                    Some(entry) => self.write(&entry).await,
+                    None => return
+                },
+                _ = ticker.tick() => {
+                    match self.prune().await {
+                        Ok(0) => {},
+                        Ok(pruned) => info!("pruned {} audit entries", pruned),
+                        Err(e) => warn!("prune audit error: {}", e)
+                    }
+                }
+            }
+        }
+    }
+
+    async fn write(&self, entry: &AuditEntry) {
+        if let Err(e) = self.insert(entry).await {
+            warn!("write audit entry error: {}", e);
+        }
+    }
+}
@@ -0,0 +1,234 @@
+//!
+//! 卡片消息
+//!
+//! `sampleActionCard`消息按配置的模板生成按钮,链接指向ERP审批页面;
+//! `interactiveCard`消息使用配置的互动卡片模板,同意/驳回按钮回调到聊天审批。
+//! 模板参数使用`{name}`格式,可用参数:
+//!
+//! - 发件箱字段: `id`/`exeuser`/`flownumber`/`flowmsgtype`/`userphone`/`robotcode`/`source`
+//! - 发件箱`params`字段(JSON对象)中的参数,`{key}`或`{params.key}`
+//!
+//! 字面量的花括号写作`{{`/`}}`
+//!
+
+use crate::{
+    config::{CardButtonConfig, CardConfig, InteractiveCardConfig, LinkTarget}, Error, Result, User
+};
+use serde_json::{json, Map, Value};
+use std::{
+    collections::HashMap, time::{SystemTime, UNIX_EPOCH}
+};
+
+/// 卡片消息类型
+pub const ACTION_CARD: &str = "sampleActionCard";
+/// 互动卡片消息类型
+pub const INTERACTIVE_CARD: &str = "interactiveCard";
+/// 互动卡片`outTrackId`前缀
+const OUT_TRACK_PREFIX: &str = "sendmsg_";
+/// 最大按钮数
+const MAX_BUTTONS: usize = 5;
+/// 钉钉客户端打开链接
+const DINGTALK_LINK: &str = "dingtalk://dingtalkclient/page/link";
+
+/// 卡片消息生成器
+#[derive(Debug, Clone)]
+pub struct ActionCard {
+    target: LinkTarget,
+    buttons: Vec<CardButtonConfig>,
+    interactive: Option<InteractiveCardConfig>
+}
+
+impl ActionCard {
+    pub fn new(cfg: &CardConfig) -> Result<ActionCard> {
+        if cfg.buttons.len() > MAX_BUTTONS {
+            return Err(Error::config(format!("卡片按钮最多{}个", MAX_BUTTONS)));
+        }
+        Ok(ActionCard {
+            target: cfg.target,
+            buttons: cfg.buttons.clone(),
+            interactive: cfg.interactive.clone()
+        })
+    }
+    /// 是否为卡片消息
+    pub fn is_card(user: &User) -> bool { user.flowmsgtype == ACTION_CARD }
+    /// 是否为互动卡片消息
+    pub fn is_interactive(user: &User) -> bool { user.flowmsgtype == INTERACTIVE_CARD }
+
+    /// 互动卡片模板配置与卡片参数
+    ///
+    /// 卡片参数为模板参数及`title`(流程号)、`content`(消息内容)、`status`(`pending`)、`operator`、`message`
+    pub fn interactive(&self, user: &User) -> Result<(&InteractiveCardConfig, Map<String, Value>)> {
+        let cfg = self.interactive.as_ref().ok_or_else(|| Error::config("未配置card.interactive"))?;
+        let mut params: Map<String, Value> =
+            variables(user)?.into_iter().map(|(k, v)| (k, v.into())).collect();
+        params.insert("title".to_owned(), json!(user.flownumber));
+        params.insert("content".to_owned(), json!(user.flowmsg));
+        params.insert("status".to_owned(), json!("pending"));
+        params.insert("operator".to_owned(), json!(""));
+        params.insert("message".to_owned(), json!(""));
+        Ok((cfg, params))
+    }
+
+    /// 生成消息类型与消息参数(JSON)
+    ///
+    /// 1个按钮使用`sampleActionCard`,多个按钮使用`sampleActionCard{n}`
+    pub fn render(&self, user: &User) -> Result<(String, String)> {
+        if self.buttons.is_empty() {
+            return Err(Error::config("未配置卡片按钮"));
+        }
+        let vars = variables(user)?;
+        let mut param = Map::new();
+        param.insert("title".to_owned(), json!(user.flownumber));
+        param.insert("text".to_owned(), json!(user.flowmsg));
+        let mut buttons = vec![];
+        for button in self.buttons.iter() {
+            let title = render(&button.title, &vars, false)?;
+            let url = render(&button.url, &vars, true)?;
+            buttons.push((title, wrap_link(&url, button.target.unwrap_or(self.target))));
+        }
+        let msg_key = if buttons.len() == 1 {
+            let (title, url) = buttons.remove(0);
+            param.insert("singleTitle".to_owned(), json!(title));
+            param.insert("singleURL".to_owned(), json!(url));
+            ACTION_CARD.to_owned()
+        } else {
+            let count = buttons.len();
+            for (idx, (title, url)) in buttons.into_iter().enumerate() {
+                param.insert(format!("actionTitle{}", idx + 1), json!(title));
+                param.insert(format!("actionURL{}", idx + 1), json!(url));
+            }
+            format!("{}{}", ACTION_CARD, count)
+        };
+        Ok((msg_key, Value::Object(param).to_string()))
+    }
+}
+
+/// 互动卡片的`outTrackId`,包含发件箱记录ID,每次发送不同
+pub fn out_track_id(user: &User) -> String {
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+    format!("{}{}_{}", OUT_TRACK_PREFIX, user.id, millis)
+}
+
+/// 从`outTrackId`解析发件箱记录ID
+pub fn parse_out_track_id(out_track_id: &str) -> Option<i64> {
+    out_track_id.strip_prefix(OUT_TRACK_PREFIX)?.split('_').next()?.parse().ok()
+}
+
+/// 发件箱字段参数名
+pub const FIELDS: &[&str] =
+    &["id", "exeuser", "flownumber", "flowmsgtype", "userphone", "robotcode", "source"];
+
+/// 模板参数
+pub fn variables(user: &User) -> Result<HashMap<String, String>> {
+    let mut vars = HashMap::new();
+    if let Some(params) = user.params.as_deref().filter(|v| !v.trim().is_empty()) {
+        let params: Map<String, Value> = serde_json::from_str(params)
+            .map_err(|e| Error::custom(format!("无效的消息参数, 必须为JSON对象, {}", e)))?;
+        for (k, v) in params {
+            let v = match v {
+                Value::String(v) => v,
+                Value::Null => String::new(),
+                v => v.to_string()
+            };
+            vars.insert(format!("params.{}", k), v.clone());
+            vars.insert(k, v);
+        }
+    }
+    vars.insert("id".to_owned(), user.id.to_string());
+    vars.insert("exeuser".to_owned(), user.exeuser.clone());
+    vars.insert("flownumber".to_owned(), user.flownumber.clone());
+    vars.insert("flowmsgtype".to_owned(), user.flowmsgtype.clone());
+    vars.insert("userphone".to_owned(), user.userphone.clone());
+    vars.insert("robotcode".to_owned(), user.robotcode.clone());
+    vars.insert("source".to_owned(), user.source.clone().unwrap_or_default());
+    Ok(vars)
+}
+
+/// 替换模板中的`{name}`参数,`encode`为`true`时参数值按URL编码
+pub fn render(template: &str, vars: &HashMap<String, String>, encode: bool) -> Result<String> {
+    let mut rendered = String::with_capacity(template.len());
+    for segment in parse(template)? {
+        match segment {
+            Segment::Text(text) => rendered.push_str(text),
+            Segment::Var(name) => {
+                let value =
+                    vars.get(name).ok_or_else(|| Error::config(format!("未知的模板参数: {}", name)))?;
+                if encode {
+                    rendered.push_str(&url_encode(value));
+                } else {
+                    rendered.push_str(value);
+                }
+            }
+        }
+    }
+    Ok(rendered)
+}
+
+/// 检查模板格式,返回模板中的参数名
+pub fn placeholders(template: &str) -> Result<Vec<&str>> {
+    Ok(parse(template)?
+        .into_iter()
+        .filter_map(|v| {
+            match v {
+                Segment::Var(name) => Some(name),
+                Segment::Text(_) => None
+            }
+        })
+        .collect())
+}
+
+/// 模板片段
+enum Segment<'a> {
+    Text(&'a str),
+    Var(&'a str)
+}
+
+/// 解析模板,`{{`/`}}`为字面量的`{`/`}`,参数名只能包含字母、数字、`_`和`.`
+fn parse(template: &str) -> Result<Vec<Segment<'_>>> {
+    let invalid = || Error::config(format!("模板格式错误: {}", template));
+    let mut segments = vec![];
+    let mut rest = template;
+    while let Some(start) = rest.find(['{', '}']) {
+        segments.push(Segment::Text(&rest[..start]));
+        let escaped = &rest[start..start + 1];
+        if rest[start + 1..].starts_with(escaped) {
+            segments.push(Segment::Text(escaped));
+            rest = &rest[start + 2..];
+            continue;
+        }
+        if escaped == "}" {
+            return Err(invalid());
+        }
+        let end = rest[start..].find('}').ok_or_else(invalid)?;
+        let name = &rest[start + 1..start + end];
+        if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '.') {
+            return Err(Error::config(format!("模板参数名无效: {{{}}}, 字面量的花括号使用{{{{或}}}}", name)));
+        }
+        segments.push(Segment::Var(name));
+        rest = &rest[start + end + 1..];
+    }
+    segments.push(Segment::Text(rest));
+    Ok(segments)
+}
+
+/// 按打开方式包装链接
+pub fn wrap_link(url: &str, target: LinkTarget) -> String {
+    match target {
+        LinkTarget::App => format!("{}?url={}&pc_slide=true", DINGTALK_LINK, url_encode(url)),
+        LinkTarget::Browser => format!("{}?url={}&pc_slide=false", DINGTALK_LINK, url_encode(url)),
+        LinkTarget::Direct => url.to_owned()
+    }
+}
+
+/// URL编码(保留RFC 3986非保留字符)
+fn url_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for b in value.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'~') {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+    encoded
+}
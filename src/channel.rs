@@ -0,0 +1,180 @@
+//!
+//! 消息渠道与渠道切换
+//!
+//! 按消息类型匹配渠道链(如 机器人 → 工作通知 → 邮件 → 短信),
+//! 当前渠道的错误满足切换条件时尝试下一渠道
+//!
+
+use crate::{
+    card, config::{ChainConfig, Channel, EmailConfig, FailoverConfig, FailoverOn, SmsConfig, SmtpTls}, http::HttpClient, phone, Error, Result, User
+};
+use lettre::{
+    message::header::ContentType, transport::smtp::authentication::Credentials, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor
+};
+
+/// 未配置渠道链时的默认渠道
+const DEFAULT_CHANNELS: &[Channel] = &[Channel::Robot];
+
+/// 渠道集合
+pub struct Channels {
+    chains: Vec<ChainConfig>,
+    email: Option<EmailChannel>,
+    sms: Option<SmsChannel>
+}
+
+impl Channels {
+    pub fn new(cfg: &FailoverConfig, http: HttpClient) -> Result<Channels> {
+        for chain in cfg.chains.iter() {
+            if chain.channels.is_empty() {
+                return Err(Error::config("渠道链不能为空"));
+            }
+            if chain.channels.contains(&Channel::Email) && cfg.email.is_none() {
+                return Err(Error::config("渠道链包含email, 但未配置failover.email"));
+            }
+            if chain.channels.contains(&Channel::Sms) && cfg.sms.is_none() {
+                return Err(Error::config("渠道链包含sms, 但未配置failover.sms"));
+            }
+        }
+        Ok(Channels {
+            chains: cfg.chains.clone(),
+            email: cfg.email.as_ref().map(EmailChannel::new).transpose()?,
+            sms: cfg.sms.as_ref().map(|cfg| SmsChannel::new(cfg, http))
+        })
+    }
+    /// 消息类型对应的渠道链与切换条件
+    ///
+    /// 优先匹配指定了消息类型的渠道链,其次为默认渠道链
+    pub fn chain(&self, flowmsgtype: &str) -> (&[Channel], &[FailoverOn]) {
+        let chain = self
+            .chains
+            .iter()
+            .find(|v| v.flowmsgtypes.iter().any(|t| t == flowmsgtype))
+            .or_else(|| self.chains.iter().find(|v| v.flowmsgtypes.is_empty()));
+        match chain {
+            Some(chain) => (&chain.channels, &chain.on),
+            None => (DEFAULT_CHANNELS, &[])
+        }
+    }
+    pub fn email(&self) -> Result<&EmailChannel> {
+        self.email.as_ref().ok_or_else(|| Error::config("未配置failover.email"))
+    }
+    pub fn sms(&self) -> Result<&SmsChannel> {
+        self.sms.as_ref().ok_or_else(|| Error::config("未配置failover.sms"))
+    }
+}
+
+/// 错误是否满足切换条件
+pub fn should_failover(on: &[FailoverOn], e: &Error) -> bool {
+    on.iter().any(|cond| {
+        match cond {
+            FailoverOn::Undeliverable => e.is_undeliverable(),
+            FailoverOn::Unavailable => e.is_unavailable(),
+            FailoverOn::Any => true
+        }
+    })
+}
+
+/// 邮件渠道
+pub struct EmailChannel {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: String,
+    subject: String
+}
+
+impl EmailChannel {
+    pub fn new(cfg: &EmailConfig) -> Result<EmailChannel> {
+        let builder = match cfg.tls {
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&cfg.host),
+            SmtpTls::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&cfg.host),
+            SmtpTls::None => Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&cfg.host))
+        }
+        .map_err(|e| Error::config(format!("无效的SMTP服务器, {}", e)))?;
+        let mut builder = builder.port(cfg.port);
+        if !cfg.username.is_empty() {
+            builder = builder.credentials(Credentials::new(cfg.username.clone(), cfg.password.clone()));
+        }
+        cfg.from
+            .parse::<lettre::message::Mailbox>()
+            .map_err(|e| Error::config(format!("无效的发件人, {}", e)))?;
+        Ok(EmailChannel {
+            transport: builder.build(),
+            from: cfg.from.clone(),
+            subject: cfg.subject.clone()
+        })
+    }
+
+    /// 发送邮件到记录的`email`地址,返回SMTP响应
+    pub async fn send(&self, user: &User) -> Result<String> {
+        let to: Vec<&str> = user.email.as_deref().map(split_addresses).unwrap_or_default();
+        if to.is_empty() {
+            return Err(Error::NoRecipient("邮件"));
+        }
+        let subject = card::render(&self.subject, &card::variables(user)?, false)?;
+        let mut builder = Message::builder()
+            .from(self.from.parse().map_err(|e| Error::Email(format!("{}", e)))?)
+            .subject(subject)
+            .header(ContentType::TEXT_PLAIN);
+        for addr in to {
+            builder =
+                builder.to(addr.parse().map_err(|e| Error::Email(format!("无效的邮箱 {}, {}", addr, e)))?);
+        }
+        let msg = builder.body(user.flowmsg.clone()).map_err(|e| Error::Email(e.to_string()))?;
+        let resp = self.transport.send(msg).await.map_err(|e| Error::Email(e.to_string()))?;
+        Ok(resp.message().collect::<Vec<_>>().join(" "))
+    }
+}
+
+/// 拆分多个邮箱地址
+fn split_addresses(addrs: &str) -> Vec<&str> {
+    addrs.split([',', ';', '，', '；']).map(str::trim).filter(|v| !v.is_empty()).collect()
+}
+
+/// 短信渠道(HTTP网关)
+pub struct SmsChannel {
+    http: HttpClient,
+    cfg: SmsConfig
+}
+
+impl SmsChannel {
+    pub fn new(cfg: &SmsConfig, http: HttpClient) -> SmsChannel {
+        SmsChannel {
+            http,
+            cfg: cfg.clone()
+        }
+    }
+
+    /// 发送短信到记录的手机号,返回网关响应
+    pub async fn send(&self, user: &User) -> Result<String> {
+        let mut mobiles = vec![];
+        for mobile in phone::split(&user.userphone) {
+            //短信网关只支持中国大陆手机号
+            match phone::normalize(mobile) {
+                Ok(v) if !v.starts_with('+') => mobiles.push(v),
+                Ok(_) => {},
+                Err(e) => warn!("sms skip #{} {}: {}", user.id, mobile, e)
+            }
+        }
+        if mobiles.is_empty() {
+            return Err(Error::NoRecipient("短信"));
+        }
+        let mut vars = card::variables(user)?;
+        vars.insert("mobile".to_owned(), mobiles.join(","));
+        vars.insert("flowmsg".to_owned(), user.flowmsg.clone());
+        let mut params = serde_json::Map::new();
+        for (name, template) in self.cfg.params.iter() {
+            params.insert(name.clone(), card::render(template, &vars, false)?.into());
+        }
+        let mut req = self.http.post(&self.cfg.url).json(&params);
+        for (name, value) in self.cfg.headers.iter() {
+            req = req.header(name.as_str(), value.as_str());
+        }
+        let (status, text) = self.http.execute(req).await?;
+        if !status.is_success() {
+            return Err(Error::Gateway {
+                status: status.as_u16(),
+                body: text.chars().take(500).collect()
+            });
+        }
+        Ok(text.chars().take(200).collect())
+    }
+}
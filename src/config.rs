@@ -0,0 +1,734 @@
+//!
+//! 服务配置
+//!
+
+use crate::{
+    secret::{self, SecretKey}, Error, Result
+};
+use serde::Deserialize;
+use std::{collections::HashMap, fs, path::Path, time};
+
+/// 服务配置
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// 数据库连接字符串(ADO格式)
+    pub db_conn_str: String,
+    /// 连接池
+    #[serde(default)]
+    pub pool: PoolConfig,
+    /// HTTP客户端
+    #[serde(default)]
+    pub http: HttpConfig,
+    /// 钉钉接口地址
+    #[serde(default)]
+    pub dingtalk: DingTalkConfig,
+    /// 钉钉应用
+    pub apps: Vec<AppConfig>,
+    /// 发送器
+    #[serde(default)]
+    pub sender: SenderConfig,
+    /// 审计日志
+    #[serde(default)]
+    pub audit: AuditConfig,
+    /// 卡片消息
+    #[serde(default)]
+    pub card: CardConfig,
+    /// 管理员告警
+    #[serde(default)]
+    pub alert: AlertConfig,
+    /// 渠道切换
+    #[serde(default)]
+    pub failover: FailoverConfig,
+    /// HTTP服务(健康检查)
+    #[serde(default)]
+    pub server: ServerConfig,
+    /// 消息模板
+    #[serde(default)]
+    pub templates: TemplateConfig,
+    /// 配置热加载
+    #[serde(default)]
+    pub reload: ReloadConfig,
+    /// HTTP消息写入
+    #[serde(default)]
+    pub ingest: IngestConfig,
+    /// 消息优先级
+    #[serde(default)]
+    pub priority: PriorityConfig,
+    /// 聊天审批
+    #[serde(default)]
+    pub approval: ApprovalConfig,
+    /// 钉钉待办同步
+    #[serde(default)]
+    pub todo: TodoConfig
+}
+
+impl Config {
+    /// 从TOML文件加载配置
+    pub fn load(path: impl AsRef<Path>) -> Result<Config> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|e| Error::config(format!("读取配置文件失败, path: {}, {}", path.display(), e)))?;
+        let mut cfg: Config =
+            toml::from_str(&text).map_err(|e| Error::config(format!("解析配置文件失败, {}", e)))?;
+        cfg.resolve_secrets()?;
+        cfg.validate()?;
+        Ok(cfg)
+    }
+    /// 校验配置项之间的依赖,在启动任何任务之前发现配置错误
+    pub fn validate(&self) -> Result<()> {
+        if !self.server.enabled {
+            if self.ingest.enabled {
+                return Err(Error::config("启用HTTP消息写入需要启用HTTP服务(server.enabled)"));
+            }
+            if self.approval.enabled {
+                return Err(Error::config("启用聊天审批需要启用HTTP服务(server.enabled)"));
+            }
+        }
+        Ok(())
+    }
+    /// 解析配置中的密钥引用(加密值、环境变量、文件)
+    pub fn resolve_secrets(&mut self) -> Result<()> {
+        let key = SecretKey::from_env()?;
+        let mut values = vec![("db_conn_str".to_owned(), &mut self.db_conn_str)];
+        for app in self.apps.iter_mut() {
+            values.push((format!("apps.{}.appsecret", app.name), &mut app.appsecret));
+        }
+        if let Some(proxy) = self.http.proxy.as_mut() {
+            values.push(("http.proxy".to_owned(), proxy));
+        }
+        values.push(("alert.webhook".to_owned(), &mut self.alert.webhook));
+        if let Some(email) = self.failover.email.as_mut() {
+            values.push(("failover.email.password".to_owned(), &mut email.password));
+        }
+        if let Some(sms) = self.failover.sms.as_mut() {
+            for (name, value) in sms.headers.iter_mut() {
+                values.push((format!("failover.sms.headers.{}", name), value));
+            }
+        }
+        if let Some(secret) = self.alert.secret.as_mut() {
+            values.push(("alert.secret".to_owned(), secret));
+        }
+        if let Some(token) = self.server.token.as_mut() {
+            values.push(("server.token".to_owned(), token));
+        }
+        if let Some(secret) = self.approval.card_secret.as_mut() {
+            values.push(("approval.card_secret".to_owned(), secret));
+        }
+        for (name, value) in values {
+            if secret::is_reference(value) {
+                *value = secret::resolve(value, key.as_ref()).map_err(|e| {
+                    match e {
+                        Error::Config(msg) => Error::Config(format!("{}: {}", name, msg)),
+                        e => e
+                    }
+                })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 连接池配置
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PoolConfig {
+    /// 最大连接数
+    pub max_size: u32,
+    /// 最小保持空闲连接数
+    pub min_idle: u32,
+    /// 连接超时(sec)
+    pub connect_timeout: u64,
+    /// 关闭连接池时等待连接归还的时间(sec)
+    pub close_timeout: u64
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            max_size: 10,
+            min_idle: 1,
+            connect_timeout: 10,
+            close_timeout: 5
+        }
+    }
+}
+
+/// HTTP客户端配置
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    /// 连接超时(sec)
+    pub connect_timeout: u64,
+    /// 请求超时(sec)
+    pub timeout: u64,
+    /// 空闲连接保持时间(sec)
+    pub pool_idle_timeout: u64,
+    /// TCP keep-alive间隔(sec)
+    pub tcp_keepalive: u64,
+    /// HTTP代理,如`http://proxy:8080`
+    pub proxy: Option<String>,
+    /// 额外信任的CA证书(PEM)路径
+    pub ca_cert: Option<String>,
+    pub user_agent: String,
+    /// 是否在日志中记录请求/响应内容
+    pub log_body: bool
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            connect_timeout: 5,
+            timeout: 15,
+            pool_idle_timeout: 90,
+            tcp_keepalive: 60,
+            proxy: None,
+            ca_cert: None,
+            user_agent: concat!("sendmsg/", env!("CARGO_PKG_VERSION")).to_owned(),
+            log_body: false
+        }
+    }
+}
+
+/// 钉钉接口地址配置
+///
+/// 默认为钉钉开放平台地址,测试时可指向本地模拟服务
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DingTalkConfig {
+    /// 旧版接口地址
+    pub oapi_url: String,
+    /// 新版(v1.0)接口地址
+    pub api_url: String
+}
+
+impl DingTalkConfig {
+    /// 旧版接口URL
+    pub fn oapi(&self, path: &str) -> String { format!("{}{}", self.oapi_url.trim_end_matches('/'), path) }
+    /// 新版接口URL
+    pub fn api(&self, path: &str) -> String { format!("{}{}", self.api_url.trim_end_matches('/'), path) }
+}
+
+impl Default for DingTalkConfig {
+    fn default() -> Self {
+        DingTalkConfig {
+            oapi_url: "https://oapi.dingtalk.com".to_owned(),
+            api_url: "https://api.dingtalk.com".to_owned()
+        }
+    }
+}
+
+/// 钉钉应用配置
+#[derive(Debug, Clone, Deserialize)]
+pub struct AppConfig {
+    /// 应用名称(唯一)
+    pub name: String,
+    pub appkey: String,
+    pub appsecret: String,
+    /// 机器人编码,第一个为默认机器人,为空时使用`appkey`
+    #[serde(default)]
+    pub robots: Vec<String>,
+    /// 路由到此应用的来源公司/数据库
+    #[serde(default)]
+    pub sources: Vec<String>,
+    /// 是否为默认应用(无法按机器人或来源路由时使用)
+    #[serde(default)]
+    pub default: bool,
+    /// 应用AgentId,发送工作通知时使用
+    #[serde(default)]
+    pub agent_id: Option<i64>
+}
+
+/// 发送器配置
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SenderConfig {
+    /// 轮询发件箱间隔(sec)
+    pub poll_interval: u64,
+    /// 每次领取的最大记录数
+    pub batch_size: u32,
+    /// 最大并发发送数
+    pub concurrency: usize,
+    /// 一条记录有多个手机号时,解析userid的最大并发数
+    pub resolve_concurrency: usize,
+    /// 停机时等待在途消息完成的时间(sec)
+    pub shutdown_timeout: u64,
+    /// 领取租约时长(sec),实例异常退出后其领取的记录在租约到期后由其他实例重新领取
+    pub lease_timeout: u64,
+    /// 实例标识,为空时使用`主机名:进程ID`
+    pub instance_id: Option<String>,
+    /// 最大发送次数,达到后发送失败的记录放弃发送(`dead`)
+    pub max_attempts: u32,
+    /// 查询机器人消息已读状态的间隔(sec),为0时不查询
+    pub read_interval: u64,
+    /// 只查询最近发送的消息的已读状态(sec)
+    pub read_window: u64
+}
+
+impl SenderConfig {
+    pub fn poll_interval(&self) -> time::Duration { time::Duration::from_secs(self.poll_interval.max(1)) }
+    pub fn shutdown_timeout(&self) -> time::Duration { time::Duration::from_secs(self.shutdown_timeout) }
+    pub fn lease_timeout(&self) -> time::Duration { time::Duration::from_secs(self.lease_timeout.max(30)) }
+    pub fn read_interval(&self) -> time::Duration { time::Duration::from_secs(self.read_interval.max(60)) }
+    /// 实例标识
+    pub fn instance_id(&self) -> String {
+        match self.instance_id.as_deref().filter(|v| !v.is_empty()) {
+            Some(id) => id.to_owned(),
+            None => {
+                let host = fs::read_to_string("/etc/hostname")
+                    .ok()
+                    .or_else(|| std::env::var("HOSTNAME").ok())
+                    .or_else(|| std::env::var("COMPUTERNAME").ok())
+                    .map(|v| v.trim().to_owned())
+                    .filter(|v| !v.is_empty())
+                    .unwrap_or_else(|| "sendmsg".to_owned());
+                format!("{}:{}", host, std::process::id())
+            }
+        }
+    }
+}
+
+impl Default for SenderConfig {
+    fn default() -> Self {
+        SenderConfig {
+            poll_interval: 5,
+            batch_size: 50,
+            concurrency: 8,
+            resolve_concurrency: 4,
+            shutdown_timeout: 30,
+            lease_timeout: 300,
+            instance_id: None,
+            max_attempts: 3,
+            read_interval: 600,
+            read_window: 86400
+        }
+    }
+}
+
+/// 审计日志配置
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AuditConfig {
+    /// 是否记录钉钉接口调用
+    pub enabled: bool,
+    /// 保留天数
+    pub retention_days: u32,
+    /// 清理间隔(sec)
+    pub prune_interval: u64,
+    /// 写入队列长度,队列满时丢弃记录
+    pub queue_size: usize
+}
+
+impl AuditConfig {
+    pub fn prune_interval(&self) -> time::Duration { time::Duration::from_secs(self.prune_interval.max(60)) }
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        AuditConfig {
+            enabled: true,
+            retention_days: 30,
+            prune_interval: 3600,
+            queue_size: 1000
+        }
+    }
+}
+
+/// 卡片消息链接的打开方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkTarget {
+    /// 钉钉内打开(PC端侧边栏)
+    #[default]
+    App,
+    /// 外部浏览器打开
+    Browser,
+    /// 不包装,直接使用链接
+    Direct
+}
+
+/// 卡片按钮配置
+#[derive(Debug, Clone, Deserialize)]
+pub struct CardButtonConfig {
+    /// 按钮标题模板
+    pub title: String,
+    /// 链接模板,如`https://erp.example.com/approve?flownumber={flownumber}`
+    pub url: String,
+    /// 打开方式,为空时使用卡片配置
+    #[serde(default)]
+    pub target: Option<LinkTarget>
+}
+
+/// 卡片消息(`sampleActionCard`)配置
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct CardConfig {
+    /// 默认打开方式
+    pub target: LinkTarget,
+    /// 按钮,最多5个
+    pub buttons: Vec<CardButtonConfig>,
+    /// 互动卡片(`interactiveCard`)
+    pub interactive: Option<InteractiveCardConfig>
+}
+
+/// 互动卡片配置
+#[derive(Debug, Clone, Deserialize)]
+pub struct InteractiveCardConfig {
+    /// 卡片模板ID
+    pub template_id: String,
+    /// 注册回调地址时指定的回调路由
+    pub callback_route_key: String
+}
+
+/// 管理员告警配置
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AlertConfig {
+    /// 是否定期汇总无法送达的记录并通知管理员
+    pub enabled: bool,
+    /// 汇总间隔(sec)
+    pub interval: u64,
+    /// 自定义群机器人Webhook地址
+    pub webhook: String,
+    /// 群机器人加签密钥
+    pub secret: Option<String>
+}
+
+impl AlertConfig {
+    pub fn interval(&self) -> time::Duration { time::Duration::from_secs(self.interval.max(60)) }
+}
+
+impl Default for AlertConfig {
+    fn default() -> Self {
+        AlertConfig {
+            enabled: false,
+            interval: 3600,
+            webhook: String::new(),
+            secret: None
+        }
+    }
+}
+
+/// 消息模板配置
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct TemplateConfig {
+    /// 模板目录,`<flowmsgtype>.md`/`<flowmsgtype>.txt`为对应消息类型的消息内容模板
+    pub dir: Option<String>
+}
+
+/// 配置热加载配置
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ReloadConfig {
+    /// 是否监听配置文件与模板目录的变更
+    pub enabled: bool,
+    /// 合并连续变更的等待时间(ms)
+    pub debounce: u64
+}
+
+impl ReloadConfig {
+    pub fn debounce(&self) -> time::Duration { time::Duration::from_millis(self.debounce.max(100)) }
+}
+
+impl Default for ReloadConfig {
+    fn default() -> Self {
+        ReloadConfig {
+            enabled: false,
+            debounce: 500
+        }
+    }
+}
+
+/// 聊天审批配置
+///
+/// 各SQL的参数: `@P1`流程号, `@P2`审批人手机号, `@P3`审批意见/驳回原因, `@P4`审批人userid, `@P5`审批人昵称
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ApprovalConfig {
+    /// 是否启用机器人消息接收(需启用HTTP服务)
+    pub enabled: bool,
+    /// 接收机器人消息的路径
+    pub path: String,
+    /// 查询流程当前审批人手机号(可多行),参数`@P1`流程号
+    pub assignee_sql: String,
+    /// 同意,影响行数为0时视为流程已处理
+    pub approve_sql: String,
+    /// 驳回,影响行数为0时视为流程已处理
+    pub reject_sql: String,
+    /// 驳回时必须填写原因
+    pub reject_reason_required: bool,
+    /// 回调时间戳允许的最大偏差(sec)
+    pub max_skew: u64,
+    /// 接收互动卡片回调的路径
+    pub card_path: String,
+    /// 互动卡片回调签名密钥(注册回调地址时指定的apiSecret),为空时不接收卡片回调
+    pub card_secret: Option<String>,
+    /// 查询待办流程,参数`@P1`手机号、`@P2`userid,返回`flownumber`、`title`(可选)、`created_at`(可选)列
+    pub todo_sql: String,
+    /// 待办流程链接模板,参数`{flownumber}`,为空时不生成链接
+    pub todo_url: String,
+    /// 待办列表每页条数
+    pub todo_page_size: u32
+}
+
+impl ApprovalConfig {
+    pub fn max_skew(&self) -> time::Duration { time::Duration::from_secs(self.max_skew.max(60)) }
+}
+
+impl Default for ApprovalConfig {
+    fn default() -> Self {
+        ApprovalConfig {
+            enabled: false,
+            path: "/dingtalk/robot".to_owned(),
+            assignee_sql: String::new(),
+            approve_sql: String::new(),
+            reject_sql: String::new(),
+            reject_reason_required: true,
+            max_skew: 3600,
+            card_path: "/dingtalk/card".to_owned(),
+            card_secret: None,
+            todo_sql: String::new(),
+            todo_url: String::new(),
+            todo_page_size: 10
+        }
+    }
+}
+
+/// 钉钉待办同步配置
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TodoConfig {
+    /// 是否将待办流程同步到钉钉待办
+    pub enabled: bool,
+    /// 同步间隔(sec)
+    pub interval: u64,
+    /// 查询待办流程,返回`flownumber`、`mobile`、`subject`列及可选的`description`、`due_at`、`source`列
+    pub pending_sql: String,
+    /// 待办详情链接模板,参数`{flownumber}`
+    pub detail_url: String,
+    /// 流程完成后的处理方式
+    pub on_complete: TodoCompletion,
+    /// `pending_sql`无结果时是否仍完成所有未完成的钉钉待办,默认跳过,避免查询异常返回空结果时误完成
+    pub complete_on_empty: bool
+}
+
+impl TodoConfig {
+    pub fn interval(&self) -> time::Duration { time::Duration::from_secs(self.interval.max(30)) }
+}
+
+impl Default for TodoConfig {
+    fn default() -> Self {
+        TodoConfig {
+            enabled: false,
+            interval: 300,
+            pending_sql: String::new(),
+            detail_url: String::new(),
+            on_complete: TodoCompletion::Done,
+            complete_on_empty: false
+        }
+    }
+}
+
+/// 流程完成后钉钉待办的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TodoCompletion {
+    /// 标记为已完成
+    #[default]
+    Done,
+    /// 删除
+    Delete
+}
+
+/// 消息优先级配置
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PriorityConfig {
+    /// 消息类型的默认优先级(0-9,越大越优先),记录未指定`priority`时使用
+    pub levels: HashMap<String, u8>,
+    /// 每次领取时为普通优先级(0)保留的名额比例(0-1)
+    pub reserved_share: f64,
+    /// 领取排序时记录每等待`aging`秒提升一级,避免持续有高优先级记录时较低优先级长期得不到发送,0为不提升
+    pub aging: u64
+}
+
+impl Default for PriorityConfig {
+    fn default() -> Self {
+        PriorityConfig {
+            levels: HashMap::new(),
+            reserved_share: 0.2,
+            aging: 60
+        }
+    }
+}
+
+/// HTTP消息写入配置
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct IngestConfig {
+    /// 是否启用`POST /messages`(需启用HTTP服务)
+    pub enabled: bool,
+    /// 数据库不可达时的本地缓存目录
+    pub spool_dir: String,
+    /// 回放本地缓存的间隔(sec)
+    pub replay_interval: u64
+}
+
+impl IngestConfig {
+    pub fn replay_interval(&self) -> time::Duration { time::Duration::from_secs(self.replay_interval.max(1)) }
+}
+
+impl Default for IngestConfig {
+    fn default() -> Self {
+        IngestConfig {
+            enabled: false,
+            spool_dir: "spool".to_owned(),
+            replay_interval: 5
+        }
+    }
+}
+
+/// HTTP服务配置
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// 是否启用
+    pub enabled: bool,
+    /// 监听地址
+    pub listen: String,
+    /// 就绪检查中每项检查的超时时间(sec)
+    pub check_timeout: u64,
+    /// 最早待发送记录的最大等待时间(sec),超过时未就绪
+    pub max_lag: u64,
+    /// 写入接口的访问令牌(`Authorization: Bearer <token>`),为空时不校验
+    pub token: Option<String>
+}
+
+impl ServerConfig {
+    pub fn check_timeout(&self) -> time::Duration { time::Duration::from_secs(self.check_timeout.max(1)) }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            enabled: false,
+            listen: "127.0.0.1:8080".to_owned(),
+            check_timeout: 3,
+            max_lag: 600,
+            token: None
+        }
+    }
+}
+
+/// 消息渠道
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Channel {
+    /// 钉钉机器人单聊消息
+    Robot,
+    /// 钉钉工作通知
+    WorkNotice,
+    /// 邮件
+    Email,
+    /// 短信(HTTP网关)
+    Sms
+}
+
+impl Channel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Channel::Robot => "robot",
+            Channel::WorkNotice => "work_notice",
+            Channel::Email => "email",
+            Channel::Sms => "sms"
+        }
+    }
+}
+
+/// 切换到下一渠道的条件
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailoverOn {
+    /// 接收人无法送达(手机号无效、未匹配到钉钉账号、缺少接收地址)
+    Undeliverable,
+    /// 渠道暂时不可用(网络错误、服务端错误、限流)
+    Unavailable,
+    /// 任何错误
+    Any
+}
+
+/// 渠道链配置
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChainConfig {
+    /// 适用的消息类型,为空时作为默认渠道链
+    #[serde(default)]
+    pub flowmsgtypes: Vec<String>,
+    /// 依次尝试的渠道
+    pub channels: Vec<Channel>,
+    /// 切换到下一渠道的条件
+    #[serde(default = "ChainConfig::default_on")]
+    pub on: Vec<FailoverOn>
+}
+
+impl ChainConfig {
+    fn default_on() -> Vec<FailoverOn> { vec![FailoverOn::Undeliverable, FailoverOn::Unavailable] }
+}
+
+/// SMTP加密方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// 隐式TLS(通常为465端口)
+    #[default]
+    Tls,
+    /// STARTTLS(通常为587端口)
+    Starttls,
+    /// 不加密
+    None
+}
+
+/// 邮件渠道配置
+#[derive(Debug, Clone, Deserialize)]
+pub struct EmailConfig {
+    pub host: String,
+    #[serde(default = "EmailConfig::default_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub tls: SmtpTls,
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub password: String,
+    /// 发件人,如`ERP <erp@example.com>`
+    pub from: String,
+    /// 邮件标题模板
+    #[serde(default = "EmailConfig::default_subject")]
+    pub subject: String
+}
+
+impl EmailConfig {
+    fn default_port() -> u16 { 465 }
+    fn default_subject() -> String { "待办提醒: {flownumber}".to_owned() }
+}
+
+/// 短信网关配置
+#[derive(Debug, Clone, Deserialize)]
+pub struct SmsConfig {
+    /// 网关地址,以JSON格式POST请求参数
+    pub url: String,
+    /// 请求参数模板,`{mobile}`为规范化后的手机号(多个以逗号分隔)
+    pub params: HashMap<String, String>,
+    /// 请求头
+    #[serde(default)]
+    pub headers: HashMap<String, String>
+}
+
+/// 渠道切换配置
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct FailoverConfig {
+    /// 渠道链,未匹配时只使用机器人单聊消息
+    pub chains: Vec<ChainConfig>,
+    pub email: Option<EmailConfig>,
+    pub sms: Option<SmsConfig>
+}
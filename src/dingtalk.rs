@@ -0,0 +1,892 @@
+//!
+//! 钉钉开放平台接口
+//!
+//! 旧版接口(`oapi.dingtalk.com`)使用`gettoken`获取的access_token作为查询参数,返回`errcode`/`errmsg`;
+//! 新版接口(`api.dingtalk.com/v1.0`)使用`oauth2/accessToken`获取的access_token作为
+//! `x-acs-dingtalk-access-token`请求头,失败时返回非2xx状态及`code`/`message`
+//!
+
+use crate::{config::DingTalkConfig, http::HttpClient, Error, Result};
+use httprequest::{
+    multipart::{Form, Part}, RequestBuilder, StatusCode
+};
+//系列化
+use serde::{Deserialize, Serialize};
+//hashmap
+use std::collections::HashMap;
+
+/// 接口版本
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ApiVersion {
+    /// 旧版接口,`gettoken`获取的access_token
+    Legacy,
+    /// 新版接口,`oauth2/accessToken`获取的access_token
+    V1
+}
+
+impl ApiVersion {
+    pub const ALL: [ApiVersion; 2] = [ApiVersion::Legacy, ApiVersion::V1];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiVersion::Legacy => "legacy",
+            ApiVersion::V1 => "v1.0"
+        }
+    }
+    /// 附加access_token
+    pub fn authorize(self, req: RequestBuilder, access_token: &str) -> RequestBuilder {
+        match self {
+            ApiVersion::Legacy => req.query(&[("access_token", access_token)]),
+            ApiVersion::V1 => req.header("x-acs-dingtalk-access-token", access_token)
+        }
+    }
+    /// 解析返回,非2xx状态或返回错误时按两种错误格式解析
+    pub fn parse<T: serde::de::DeserializeOwned>(self, (status, text): (StatusCode, String)) -> Result<T> {
+        if let Some(e) = envelope_error(&text) {
+            return Err(e);
+        }
+        if !status.is_success() {
+            return Err(Error::DingTalkApi {
+                code: format!("HTTP{}", status.as_u16()),
+                message: text.chars().take(200).collect()
+            });
+        }
+        Ok(serde_json::from_str(&text)?)
+    }
+}
+
+//错误返回,旧版接口`errcode`/`errmsg`,新版接口`code`/`message`
+#[derive(Debug, Deserialize)]
+struct DDErrorEnvelope {
+    #[serde(default)]
+    errcode: Option<i64>,
+    #[serde(default)]
+    errmsg: Option<String>,
+    #[serde(default)]
+    code: Option<String>,
+    #[serde(default)]
+    message: Option<String>
+}
+
+/// 从返回内容解析错误,`errcode`为0或不是错误格式时返回`None`
+pub fn envelope_error(text: &str) -> Option<Error> {
+    let envelope: DDErrorEnvelope = serde_json::from_str(text).ok()?;
+    match envelope {
+        DDErrorEnvelope {
+            errcode: Some(errcode),
+            errmsg,
+            ..
+        } if errcode != 0 => {
+            Some(Error::DingTalk {
+                errcode,
+                errmsg: errmsg.unwrap_or_default()
+            })
+        },
+        DDErrorEnvelope {
+            code: Some(code),
+            message: Some(message),
+            ..
+        } => {
+            Some(Error::DingTalkApi {
+                code,
+                message
+            })
+        },
+        _ => None
+    }
+}
+
+/// 钉钉接口地址与共享HTTP客户端
+#[derive(Debug, Clone)]
+pub struct DDApi {
+    cfg: DingTalkConfig,
+    http: HttpClient
+}
+
+impl DDApi {
+    pub fn new(cfg: &DingTalkConfig, http: HttpClient) -> DDApi {
+        DDApi {
+            cfg: cfg.clone(),
+            http
+        }
+    }
+    /// 旧版接口URL
+    pub fn oapi(&self, path: &str) -> String { self.cfg.oapi(path) }
+    /// 新版接口URL
+    pub fn api(&self, path: &str) -> String { self.cfg.api(path) }
+    /// HTTP客户端
+    pub fn http(&self) -> &HttpClient { &self.http }
+}
+
+//钉钉获取token请求主体
+#[derive(Debug, Serialize)]
+pub struct DDToken {
+    #[serde(skip)]
+    http: HttpClient,
+    url: String,
+    appkey: String,
+    appsecret: String
+}
+//DDTokenResult
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DDTokenResult {
+    #[serde(default)]
+    pub access_token: String,
+    /// 有效期(sec)
+    #[serde(default)]
+    pub expires_in: u64
+}
+
+//实现token请求主体
+impl DDToken {
+    //创建实例
+    pub fn new(api: &DDApi, appkey: String, appsecret: String) -> DDToken {
+        DDToken {
+            http: api.http().clone(),
+            //获取钉钉token的URL及参数
+            url: api.oapi("/gettoken"),
+            appkey,
+            appsecret
+        }
+    }
+
+    //获取钉钉机器人token方法
+    pub async fn get_token(&self) -> Result<DDTokenResult> {
+        //将获取token参数加入到一个hash变量
+        let mut get_token_param = HashMap::new();
+        get_token_param.insert("appkey", self.appkey.clone());
+        get_token_param.insert("appsecret", self.appsecret.clone());
+
+        //通过共享客户端访问钉钉接口获取access_token
+        let req = self.http.get(&self.url).query(&get_token_param);
+        ApiVersion::Legacy.parse(self.http.execute(req).await?)
+    }
+}
+
+//新版接口获取token请求主体
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DDApiToken {
+    #[serde(skip)]
+    http: HttpClient,
+    #[serde(skip)]
+    url: String,
+    app_key: String,
+    app_secret: String
+}
+
+//新版接口token返回类型
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DDApiTokenResult {
+    pub access_token: String,
+    /// 有效期(sec)
+    #[serde(default)]
+    pub expire_in: u64
+}
+
+impl DDApiToken {
+    pub fn new(api: &DDApi, app_key: String, app_secret: String) -> DDApiToken {
+        DDApiToken {
+            http: api.http().clone(),
+            url: api.api("/v1.0/oauth2/accessToken"),
+            app_key,
+            app_secret
+        }
+    }
+
+    pub async fn get_token(&self) -> Result<DDApiTokenResult> {
+        let req = self.http.post(&self.url).json(self);
+        ApiVersion::V1.parse(self.http.execute(req).await?)
+    }
+}
+
+//通过useriphone获取userid
+#[derive(Debug, Serialize)]
+pub struct DDUserid {
+    #[serde(skip)]
+    http: HttpClient,
+    url: String,
+    access_token: String,
+    mobile: String
+}
+
+//userid返回类型
+#[derive(Debug, Serialize, Deserialize)]
+struct DDUseridResult {
+    #[serde(default)]
+    result: Option<DDUseridValue>
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DDUseridValue {
+    userid: String
+}
+//钉钉DDUserid实现
+impl DDUserid {
+    pub fn new(api: &DDApi, access_token: String, mobile: String) -> DDUserid {
+        DDUserid {
+            http: api.http().clone(),
+            url: api.oapi("/topapi/v2/user/getbymobile"),
+            access_token,
+            mobile
+        }
+    }
+
+    pub async fn get_userid(&self) -> Result<String> {
+        //通过共享客户端访问钉钉接口获取userid
+        let req = self.http.post(&self.url).query(&[("mobile", &self.mobile)]);
+        let req = ApiVersion::Legacy.authorize(req, &self.access_token);
+        let userid: DDUseridResult = ApiVersion::Legacy.parse(self.http.execute(req).await?)?;
+        match userid.result.map(|v| v.userid).filter(|v| !v.is_empty()) {
+            Some(userid) => Ok(userid),
+            None => Err(Error::UserNotFound(self.mobile.clone()))
+        }
+    }
+}
+
+//通过userid获取用户详情
+#[derive(Debug, Serialize)]
+pub struct DDUserGet {
+    #[serde(skip)]
+    http: HttpClient,
+    #[serde(skip)]
+    url: String,
+    #[serde(skip)]
+    access_token: String,
+    userid: String
+}
+
+//用户详情返回类型
+#[derive(Debug, Serialize, Deserialize)]
+struct DDUserGetResult {
+    #[serde(default)]
+    result: Option<DDUserInfo>
+}
+
+/// 用户详情
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DDUserInfo {
+    pub userid: String,
+    #[serde(default)]
+    pub name: String,
+    /// 手机号(需通讯录手机号读取权限)
+    #[serde(default)]
+    pub mobile: String,
+    /// 新版接口使用的unionId
+    #[serde(default)]
+    pub unionid: String
+}
+
+impl DDUserGet {
+    pub fn new(api: &DDApi, access_token: String, userid: String) -> DDUserGet {
+        DDUserGet {
+            http: api.http().clone(),
+            url: api.oapi("/topapi/v2/user/get"),
+            access_token,
+            userid
+        }
+    }
+
+    pub async fn get_user(&self) -> Result<DDUserInfo> {
+        let req = ApiVersion::Legacy.authorize(self.http.post(&self.url), &self.access_token).json(self);
+        let rv: DDUserGetResult = ApiVersion::Legacy.parse(self.http.execute(req).await?)?;
+        rv.result.ok_or_else(|| Error::custom(format!("未返回用户详情, userid: {}", self.userid)))
+    }
+}
+
+//上传媒体文件
+#[derive(Debug)]
+pub struct DDMediaUpload {
+    http: HttpClient,
+    url: String,
+    access_token: String,
+    /// 媒体类型: image/voice/video/file
+    media_type: String,
+    file_name: String,
+    data: Vec<u8>
+}
+
+//上传媒体文件返回类型
+#[derive(Debug, Serialize, Deserialize)]
+struct DDMediaUploadResult {
+    #[serde(default)]
+    media_id: String
+}
+
+impl DDMediaUpload {
+    pub fn new(
+        api: &DDApi,
+        access_token: String,
+        media_type: String,
+        file_name: String,
+        data: Vec<u8>
+    ) -> DDMediaUpload {
+        DDMediaUpload {
+            http: api.http().clone(),
+            url: api.oapi("/media/upload"),
+            access_token,
+            media_type,
+            file_name,
+            data
+        }
+    }
+
+    /// 上传文件,返回media_id
+    pub async fn upload(&self) -> Result<String> {
+        let part = Part::bytes(self.data.clone()).file_name(self.file_name.clone());
+        let req = self
+            .http
+            .post(&self.url)
+            .query(&[("type", &self.media_type)])
+            .multipart(Form::new().part("media", part));
+        let req = ApiVersion::Legacy.authorize(req, &self.access_token);
+        let rv: DDMediaUploadResult = ApiVersion::Legacy.parse(self.http.execute(req).await?)?;
+        Ok(rv.media_id)
+    }
+}
+
+//机器人批量发送单聊消息
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DDRobotMsg {
+    #[serde(skip)]
+    http: HttpClient,
+    #[serde(skip)]
+    url: String,
+    #[serde(skip)]
+    access_token: String,
+    robot_code: String,
+    user_ids: Vec<String>,
+    msg_key: String,
+    msg_param: String
+}
+
+//批量发送返回类型
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DDRobotMsgResult {
+    /// 消息发送任务ID,用于查询已读状态与撤回
+    pub process_query_key: String,
+    /// 无效的userid
+    #[serde(default)]
+    pub invalid_staff_id_list: Vec<String>,
+    /// 被限流的userid
+    #[serde(default)]
+    pub flow_controlled_staff_id_list: Vec<String>
+}
+
+impl DDRobotMsg {
+    pub fn new(
+        api: &DDApi,
+        access_token: String,
+        robot_code: String,
+        user_ids: Vec<String>,
+        msg_key: String,
+        msg_param: String
+    ) -> DDRobotMsg {
+        DDRobotMsg {
+            http: api.http().clone(),
+            url: api.api("/v1.0/robot/oToMessages/batchSend"),
+            access_token,
+            robot_code,
+            user_ids,
+            msg_key,
+            msg_param
+        }
+    }
+
+    /// 使用新的access_token(如token失效重新获取后)
+    pub fn with_token(&self, access_token: String) -> DDRobotMsg {
+        DDRobotMsg {
+            access_token,
+            ..self.clone()
+        }
+    }
+    /// 接口URL
+    pub fn url(&self) -> &str { &self.url }
+
+    pub async fn send(&self) -> Result<DDRobotMsgResult> {
+        let req = ApiVersion::V1.authorize(self.http.post(&self.url), &self.access_token).json(self);
+        ApiVersion::V1.parse(self.http.execute(req).await?)
+    }
+}
+
+//机器人批量撤回单聊消息
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DDRobotRecall {
+    #[serde(skip)]
+    http: HttpClient,
+    #[serde(skip)]
+    url: String,
+    #[serde(skip)]
+    access_token: String,
+    robot_code: String,
+    process_query_keys: Vec<String>
+}
+
+//批量撤回返回类型
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DDRobotRecallResult {
+    /// 撤回成功的消息发送任务ID
+    #[serde(default)]
+    pub success_result: Vec<String>,
+    /// 撤回失败的消息发送任务ID及原因
+    #[serde(default)]
+    pub failed_result: HashMap<String, String>
+}
+
+impl DDRobotRecall {
+    pub fn new(
+        api: &DDApi,
+        access_token: String,
+        robot_code: String,
+        process_query_keys: Vec<String>
+    ) -> DDRobotRecall {
+        DDRobotRecall {
+            http: api.http().clone(),
+            url: api.api("/v1.0/robot/otoMessages/batchRecall"),
+            access_token,
+            robot_code,
+            process_query_keys
+        }
+    }
+
+    pub async fn recall(&self) -> Result<DDRobotRecallResult> {
+        let req = ApiVersion::V1.authorize(self.http.post(&self.url), &self.access_token).json(self);
+        ApiVersion::V1.parse(self.http.execute(req).await?)
+    }
+}
+
+//查询机器人单聊消息已读状态
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DDRobotReadStatus {
+    #[serde(skip)]
+    http: HttpClient,
+    #[serde(skip)]
+    url: String,
+    #[serde(skip)]
+    access_token: String,
+    robot_code: String,
+    process_query_key: String
+}
+
+//已读状态返回类型
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DDRobotReadStatusResult {
+    /// 发送状态
+    #[serde(default)]
+    pub send_status: String,
+    #[serde(default)]
+    pub message_read_info_list: Vec<DDMessageReadInfo>
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DDMessageReadInfo {
+    #[serde(default)]
+    pub name: String,
+    pub user_id: String,
+    /// 已读状态: READ/UNREAD
+    pub read_status: String,
+    /// 已读时间(毫秒时间戳)
+    #[serde(default)]
+    pub read_timestamp: i64
+}
+
+impl DDRobotReadStatus {
+    pub fn new(
+        api: &DDApi,
+        access_token: String,
+        robot_code: String,
+        process_query_key: String
+    ) -> DDRobotReadStatus {
+        DDRobotReadStatus {
+            http: api.http().clone(),
+            url: api.api("/v1.0/robot/oToMessages/readStatus"),
+            access_token,
+            robot_code,
+            process_query_key
+        }
+    }
+
+    pub async fn get_read_status(&self) -> Result<DDRobotReadStatusResult> {
+        let req = ApiVersion::V1.authorize(self.http.get(&self.url), &self.access_token).query(self);
+        ApiVersion::V1.parse(self.http.execute(req).await?)
+    }
+}
+
+//机器人发送互动卡片
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DDInteractiveCard {
+    #[serde(skip)]
+    http: HttpClient,
+    #[serde(skip)]
+    url: String,
+    #[serde(skip)]
+    access_token: String,
+    card_template_id: String,
+    /// 卡片唯一标识,用于更新卡片
+    out_track_id: String,
+    robot_code: String,
+    /// 0: 单聊, 1: 群聊
+    conversation_type: i32,
+    receiver_user_id_list: Vec<String>,
+    callback_route_key: String,
+    card_data: DDCardData,
+    /// 1: userid
+    user_id_type: i32
+}
+
+/// 卡片数据
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DDCardData {
+    #[serde(default)]
+    pub card_param_map: serde_json::Map<String, serde_json::Value>
+}
+
+//发送互动卡片返回类型
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DDInteractiveCardResult {
+    #[serde(default)]
+    pub result: DDInteractiveCardValue
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DDInteractiveCardValue {
+    /// 消息发送任务ID
+    #[serde(default)]
+    pub process_query_key: String
+}
+
+impl DDInteractiveCard {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        api: &DDApi,
+        access_token: String,
+        card_template_id: String,
+        out_track_id: String,
+        robot_code: String,
+        user_ids: Vec<String>,
+        callback_route_key: String,
+        card_data: DDCardData
+    ) -> DDInteractiveCard {
+        DDInteractiveCard {
+            http: api.http().clone(),
+            url: api.api("/v1.0/im/interactiveCards/send"),
+            access_token,
+            card_template_id,
+            out_track_id,
+            robot_code,
+            conversation_type: 0,
+            receiver_user_id_list: user_ids,
+            callback_route_key,
+            card_data,
+            user_id_type: 1
+        }
+    }
+
+    /// 使用新的access_token(如token失效重新获取后)
+    pub fn with_token(&self, access_token: String) -> DDInteractiveCard {
+        DDInteractiveCard {
+            access_token,
+            ..self.clone()
+        }
+    }
+    /// 接口URL
+    pub fn url(&self) -> &str { &self.url }
+
+    pub async fn send(&self) -> Result<DDInteractiveCardResult> {
+        let req = ApiVersion::V1.authorize(self.http.post(&self.url), &self.access_token).json(self);
+        ApiVersion::V1.parse(self.http.execute(req).await?)
+    }
+}
+
+//更新互动卡片
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DDInteractiveCardUpdate {
+    #[serde(skip)]
+    http: HttpClient,
+    #[serde(skip)]
+    url: String,
+    #[serde(skip)]
+    access_token: String,
+    out_track_id: String,
+    card_data: DDCardData,
+    user_id_type: i32,
+    card_options: serde_json::Value
+}
+
+impl DDInteractiveCardUpdate {
+    pub fn new(
+        api: &DDApi,
+        access_token: String,
+        out_track_id: String,
+        card_data: DDCardData
+    ) -> DDInteractiveCardUpdate {
+        DDInteractiveCardUpdate {
+            http: api.http().clone(),
+            url: api.api("/v1.0/im/interactiveCards"),
+            access_token,
+            out_track_id,
+            card_data,
+            user_id_type: 1,
+            //只更新传入的参数
+            card_options: serde_json::json!({ "updateCardDataByKey": true })
+        }
+    }
+
+    pub async fn update(&self) -> Result<()> {
+        let req = ApiVersion::V1.authorize(self.http.put(&self.url), &self.access_token).json(self);
+        let _: serde_json::Value = ApiVersion::V1.parse(self.http.execute(req).await?)?;
+        Ok(())
+    }
+}
+
+//创建钉钉待办
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DDTodoCreate {
+    #[serde(skip)]
+    http: HttpClient,
+    #[serde(skip)]
+    url: String,
+    #[serde(skip)]
+    access_token: String,
+    #[serde(skip)]
+    unionid: String,
+    /// 业务ID,同一业务ID重复创建时返回已创建的待办
+    source_id: String,
+    subject: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    description: String,
+    /// 截止时间(毫秒时间戳)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    due_time: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail_url: Option<DDTodoDetailUrl>,
+    executor_ids: Vec<String>
+}
+
+/// 待办详情链接
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DDTodoDetailUrl {
+    pub app_url: String,
+    pub pc_url: String
+}
+
+//创建待办返回类型
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DDTodoCreateResult {
+    /// 待办ID
+    pub id: String
+}
+
+impl DDTodoCreate {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        api: &DDApi,
+        access_token: String,
+        unionid: String,
+        source_id: String,
+        subject: String,
+        description: String,
+        due_time: Option<i64>,
+        detail_url: Option<DDTodoDetailUrl>
+    ) -> DDTodoCreate {
+        DDTodoCreate {
+            http: api.http().clone(),
+            url: api.api(&format!("/v1.0/todo/users/{}/tasks", unionid)),
+            access_token,
+            executor_ids: vec![unionid.clone()],
+            unionid,
+            source_id,
+            subject,
+            description,
+            due_time,
+            detail_url
+        }
+    }
+
+    /// 创建待办,返回待办ID
+    pub async fn create(&self) -> Result<String> {
+        let req = ApiVersion::V1
+            .authorize(self.http.post(&self.url), &self.access_token)
+            .query(&[("operatorId", &self.unionid)])
+            .json(self);
+        let rv: DDTodoCreateResult = ApiVersion::V1.parse(self.http.execute(req).await?)?;
+        Ok(rv.id)
+    }
+}
+
+//完成或删除钉钉待办
+#[derive(Debug)]
+pub struct DDTodoTask {
+    http: HttpClient,
+    url: String,
+    access_token: String,
+    unionid: String
+}
+
+impl DDTodoTask {
+    pub fn new(api: &DDApi, access_token: String, unionid: String, task_id: &str) -> DDTodoTask {
+        DDTodoTask {
+            http: api.http().clone(),
+            url: api.api(&format!("/v1.0/todo/users/{}/tasks/{}", unionid, task_id)),
+            access_token,
+            unionid
+        }
+    }
+
+    /// 标记为已完成
+    pub async fn done(&self) -> Result<()> {
+        let req = ApiVersion::V1
+            .authorize(self.http.put(&self.url), &self.access_token)
+            .query(&[("operatorId", &self.unionid)])
+            .json(&serde_json::json!({ "done": true }));
+        let _: serde_json::Value = ApiVersion::V1.parse(self.http.execute(req).await?)?;
+        Ok(())
+    }
+
+    /// 删除待办
+    pub async fn delete(&self) -> Result<()> {
+        let req = ApiVersion::V1
+            .authorize(self.http.delete(&self.url), &self.access_token)
+            .query(&[("operatorId", &self.unionid)]);
+        let _: serde_json::Value = ApiVersion::V1.parse(self.http.execute(req).await?)?;
+        Ok(())
+    }
+}
+
+//发送工作通知
+#[derive(Debug, Clone, Serialize)]
+pub struct DDWorkNotice {
+    #[serde(skip)]
+    http: HttpClient,
+    #[serde(skip)]
+    url: String,
+    #[serde(skip)]
+    access_token: String,
+    agent_id: i64,
+    /// 接收人userid,多个以逗号分隔
+    userid_list: String,
+    msg: serde_json::Value
+}
+
+//工作通知返回类型
+#[derive(Debug, Serialize, Deserialize)]
+struct DDWorkNoticeResult {
+    #[serde(default)]
+    task_id: i64
+}
+
+impl DDWorkNotice {
+    pub fn new(
+        api: &DDApi,
+        access_token: String,
+        agent_id: i64,
+        user_ids: &[String],
+        msg: serde_json::Value
+    ) -> DDWorkNotice {
+        DDWorkNotice {
+            http: api.http().clone(),
+            url: api.oapi("/topapi/message/corpconversation/asyncsend_v2"),
+            access_token,
+            agent_id,
+            userid_list: user_ids.join(","),
+            msg
+        }
+    }
+
+    /// 使用新的access_token(如token失效重新获取后)
+    pub fn with_token(&self, access_token: String) -> DDWorkNotice {
+        DDWorkNotice {
+            access_token,
+            ..self.clone()
+        }
+    }
+    /// 接口URL
+    pub fn url(&self) -> &str { &self.url }
+
+    /// 发送工作通知,返回异步发送任务ID
+    pub async fn send(&self) -> Result<i64> {
+        let req = ApiVersion::Legacy.authorize(self.http.post(&self.url), &self.access_token).json(self);
+        let rv: DDWorkNoticeResult = ApiVersion::Legacy.parse(self.http.execute(req).await?)?;
+        Ok(rv.task_id)
+    }
+}
+
+//自定义群机器人(Webhook)发送消息
+#[derive(Debug)]
+pub struct DDWebhookRobot {
+    http: HttpClient,
+    webhook: String,
+    /// 加签密钥
+    secret: Option<String>
+}
+
+impl DDWebhookRobot {
+    pub fn new(http: HttpClient, webhook: String, secret: Option<String>) -> DDWebhookRobot {
+        DDWebhookRobot {
+            http,
+            webhook,
+            secret
+        }
+    }
+
+    /// 发送markdown消息
+    pub async fn send_markdown(&self, title: &str, text: &str) -> Result<()> {
+        let body = serde_json::json!({
+            "msgtype": "markdown",
+            "markdown": { "title": title, "text": text }
+        });
+        let mut req = self.http.post(&self.webhook).json(&body);
+        if let Some(secret) = self.secret.as_deref().filter(|v| !v.is_empty()) {
+            let timestamp = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis()
+                .to_string();
+            req = req.query(&[("sign", webhook_sign(secret, &timestamp)), ("timestamp", timestamp)]);
+        }
+        let _: serde_json::Value = ApiVersion::Legacy.parse(self.http.execute(req).await?)?;
+        Ok(())
+    }
+}
+
+/// 自定义群机器人加签: base64(HmacSHA256(secret, "{timestamp}\n{secret}"))
+pub fn webhook_sign(secret: &str, timestamp: &str) -> String {
+    use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+    use hmac::Mac;
+
+    BASE64.encode(webhook_mac(secret, timestamp).finalize().into_bytes())
+}
+
+/// 校验回调签名,按常量时间比较避免时序攻击
+pub fn verify_webhook_sign(secret: &str, timestamp: &str, sign: &str) -> bool {
+    use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+    use hmac::Mac;
+
+    match BASE64.decode(sign) {
+        Ok(sign) => webhook_mac(secret, timestamp).verify_slice(&sign).is_ok(),
+        Err(_) => false
+    }
+}
+
+fn webhook_mac(secret: &str, timestamp: &str) -> hmac::Hmac<sha2::Sha256> {
+    use hmac::{Hmac, Mac};
+
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes()).expect("hmac key");
+    mac.update(format!("{}\n{}", timestamp, secret).as_bytes());
+    mac
+}
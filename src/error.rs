@@ -0,0 +1,122 @@
+/// 服务相关错误
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("数据库错误, {0}")]
+    Db(#[from] mssql::Error),
+    #[error("HTTP请求错误, {0}")]
+    Http(#[from] httprequest::Error),
+    #[error("JSON解析错误, {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("配置错误, {0}")]
+    Config(String),
+    #[error("钉钉接口错误, errcode: {errcode}, errmsg: {errmsg}")]
+    DingTalk {
+        errcode: i64,
+        errmsg: String
+    },
+    #[error("钉钉接口错误, code: {code}, message: {message}")]
+    DingTalkApi {
+        code: String,
+        message: String
+    },
+    #[error("无效的手机号, {phone}, {reason}")]
+    InvalidPhone {
+        phone: String,
+        reason: &'static str
+    },
+    #[error("未找到钉钉用户, mobile: {0}")]
+    UserNotFound(String),
+    #[error("缺少{0}接收地址")]
+    NoRecipient(&'static str),
+    #[error("邮件发送失败, {0}")]
+    Email(String),
+    #[error("短信网关错误, status: {status}, {body}")]
+    Gateway {
+        status: u16,
+        body: String
+    },
+    #[error("无效的状态变更, {from} → {to}")]
+    InvalidTransition {
+        from: String,
+        to: &'static str
+    },
+    #[error("无效的请求, {0}")]
+    Invalid(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("{0}")]
+    Custom(std::borrow::Cow<'static, str>)
+}
+
+impl Error {
+    pub fn custom(msg: impl std::fmt::Display) -> Error { Error::Custom(msg.to_string().into()) }
+    pub fn config(msg: impl std::fmt::Display) -> Error { Error::Config(msg.to_string()) }
+}
+
+impl Error {
+    /// access_token无效或过期
+    pub fn is_token_invalid(&self) -> bool {
+        match self {
+            //40014: 不合法的access_token, 42001: access_token超时
+            Error::DingTalk {
+                errcode: 40014 | 42001,
+                ..
+            } => true,
+            Error::DingTalkApi {
+                code,
+                ..
+            } => code == "InvalidAuthentication",
+            _ => false
+        }
+    }
+    /// 接收人无法送达(手机号无效或未匹配到钉钉账号),重试无意义
+    pub fn is_undeliverable(&self) -> bool {
+        match self {
+            Error::InvalidPhone {
+                ..
+            } |
+            Error::UserNotFound(..) |
+            Error::NoRecipient(..) => true,
+            //60121: 找不到该用户
+            Error::DingTalk {
+                errcode: 60121,
+                ..
+            } => true,
+            _ => false
+        }
+    }
+    /// 渠道暂时不可用(网络错误、服务端错误、限流),可切换渠道或重试
+    pub fn is_unavailable(&self) -> bool {
+        match self {
+            Error::Http(..) | Error::Json(..) | Error::Io(..) | Error::Email(..) => true,
+            //-1: 系统繁忙
+            Error::DingTalk {
+                errcode: -1,
+                ..
+            } => true,
+            Error::DingTalkApi {
+                code,
+                ..
+            } => {
+                //非JSON的非2xx返回(如网关错误)
+                code.starts_with("HTTP5") ||
+                    code == "HTTP429" ||
+                    code.starts_with("ServiceUnavailable") ||
+                    code.contains("InternalError") ||
+                    code.contains("QpsLimit")
+            },
+            Error::Gateway {
+                status,
+                ..
+            } => *status >= 500 || *status == 429,
+            _ => false
+        }
+    }
+    /// 数据库暂时不可达(连接失败、超时或正在恢复)
+    pub fn is_db_unavailable(&self) -> bool {
+        match self {
+            Error::Db(e) => e.is_request() || e.is_recovering(),
+            _ => false
+        }
+    }
+}
@@ -0,0 +1,162 @@
+//!
+//! 健康检查
+//!
+//! - 存活检查: 进程正常响应即为存活
+//! - 就绪检查: 数据库连接池能在超时时间内取得连接、所有钉钉应用持有有效的access_token、
+//!   最早待发送记录的等待时间未超过阈值
+//!
+
+use crate::{
+    app::AppRegistry, config::ServerConfig, dingtalk::ApiVersion, outbox::Outbox, sender::Delivery, Error, Result
+};
+use arc_swap::ArcSwap;
+use mssql::Pool;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::{future::Future, sync::Arc};
+use tokio::time;
+
+/// 单项检查结果
+#[derive(Debug, Serialize)]
+pub struct Check {
+    pub ok: bool,
+    pub latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// 检查详情
+    #[serde(flatten)]
+    pub detail: Map<String, Value>
+}
+
+/// 就绪检查结果
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    pub db: Check,
+    pub tokens: Check,
+    pub outbox: Check
+}
+
+/// 健康检查
+pub struct Health {
+    pool: Pool,
+    outbox: Outbox,
+    /// 当前投递配置(热加载后检查新配置中的钉钉应用)
+    delivery: Arc<ArcSwap<Delivery>>,
+    cfg: ServerConfig
+}
+
+impl Health {
+    pub fn new(pool: Pool, outbox: Outbox, delivery: Arc<ArcSwap<Delivery>>, cfg: ServerConfig) -> Health {
+        Health {
+            pool,
+            outbox,
+            delivery,
+            cfg
+        }
+    }
+
+    /// 执行所有就绪检查
+    pub async fn ready(&self) -> Readiness {
+        let (db, tokens, outbox) = tokio::join!(self.check_db(), self.check_tokens(), self.check_outbox());
+        Readiness {
+            ready: db.ok && tokens.ok && outbox.ok,
+            db,
+            tokens,
+            outbox
+        }
+    }
+
+    /// 连接池能否取得连接
+    async fn check_db(&self) -> Check {
+        check(self.cfg.check_timeout(), async {
+            self.pool.get().await?;
+            Ok(Map::new())
+        })
+        .await
+    }
+
+    /// 所有钉钉应用是否持有有效的access_token(过期时重新获取)
+    async fn check_tokens(&self) -> Check {
+        let delivery = self.delivery.load_full();
+        check_tokens(delivery.apps(), self.cfg.check_timeout()).await
+    }
+
+    /// 最早待发送记录的等待时间
+    async fn check_outbox(&self) -> Check {
+        let max_lag = self.cfg.max_lag as i64;
+        check(self.cfg.check_timeout(), async {
+            let lag = self.outbox.lag().await?;
+            let mut detail = Map::new();
+            detail.insert("lag".to_owned(), json!(lag));
+            detail.insert("max_lag".to_owned(), json!(max_lag));
+            if lag > max_lag {
+                return Err(Error::custom(format!("最早待发送记录已等待{}秒", lag)));
+            }
+            Ok(detail)
+        })
+        .await
+    }
+}
+
+/// 检查所有钉钉应用的access_token
+///
+/// 详情`apps`为各应用的检查结果
+pub async fn check_tokens(apps: &AppRegistry, timeout: time::Duration) -> Check {
+    let started = time::Instant::now();
+    let mut ok = true;
+    let mut detail = Map::new();
+    for app in apps.apps() {
+        let rv = check(timeout, async {
+            for version in ApiVersion::ALL {
+                app.token(version).await?;
+            }
+            Ok(Map::new())
+        })
+        .await;
+        ok &= rv.ok;
+        detail.insert(app.name().to_owned(), json!(rv));
+    }
+    Check {
+        ok,
+        latency_ms: started.elapsed().as_millis() as u64,
+        error: None,
+        detail: Map::from_iter([("apps".to_owned(), Value::Object(detail))])
+    }
+}
+
+/// 在超时时间内执行检查
+async fn check<F>(timeout: time::Duration, f: F) -> Check
+where
+    F: Future<Output = Result<Map<String, Value>>>
+{
+    let started = time::Instant::now();
+    let rv = time::timeout(timeout, f).await;
+    let latency_ms = started.elapsed().as_millis() as u64;
+    match rv {
+        Ok(Ok(detail)) => {
+            Check {
+                ok: true,
+                latency_ms,
+                error: None,
+                detail
+            }
+        },
+        Ok(Err(e)) => {
+            Check {
+                ok: false,
+                latency_ms,
+                error: Some(e.to_string()),
+                detail: Map::new()
+            }
+        },
+        Err(_) => {
+            Check {
+                ok: false,
+                latency_ms,
+                error: Some(format!("检查超时({}ms)", timeout.as_millis())),
+                detail: Map::new()
+            }
+        },
+    }
+}
@@ -0,0 +1,177 @@
+//!
+//! 共享HTTP客户端
+//!
+//! 所有钉钉接口调用共用一个连接池,统一超时、代理、证书与请求日志
+//!
+
+use crate::{
+    audit::{self, AuditEntry, AuditSink}, config::HttpConfig, Error, Result
+};
+use httprequest::{Certificate, Client, Method, Proxy, RequestBuilder, StatusCode, Url};
+use std::{fs, time};
+
+/// 日志中需要隐藏的参数
+const SENSITIVE_PARAMS: &[&str] = &["access_token", "appsecret", "sign", "appSecret", "accessToken"];
+
+/// 共享HTTP客户端
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    client: Client,
+    /// 是否记录请求/响应内容
+    log_body: bool,
+    /// 审计日志
+    audit: Option<AuditSink>
+}
+
+impl HttpClient {
+    pub fn new(cfg: &HttpConfig) -> Result<HttpClient> {
+        let mut builder = Client::builder()
+            .connect_timeout(time::Duration::from_secs(cfg.connect_timeout))
+            .timeout(time::Duration::from_secs(cfg.timeout))
+            .pool_idle_timeout(time::Duration::from_secs(cfg.pool_idle_timeout))
+            .tcp_keepalive(time::Duration::from_secs(cfg.tcp_keepalive))
+            .user_agent(cfg.user_agent.clone());
+        if let Some(proxy) = cfg.proxy.as_deref().filter(|v| !v.is_empty()) {
+            builder =
+                builder.proxy(Proxy::all(proxy).map_err(|e| Error::config(format!("无效的代理, {}", e)))?);
+        }
+        if let Some(path) = cfg.ca_cert.as_deref().filter(|v| !v.is_empty()) {
+            let pem = fs::read(path)
+                .map_err(|e| Error::config(format!("读取CA证书失败, path: {}, {}", path, e)))?;
+            let cert =
+                Certificate::from_pem(&pem).map_err(|e| Error::config(format!("无效的CA证书, {}", e)))?;
+            builder = builder.add_root_certificate(cert);
+        }
+        Ok(HttpClient {
+            client: builder.build()?,
+            log_body: cfg.log_body,
+            audit: None
+        })
+    }
+    /// 记录每次请求到审计日志
+    pub fn with_audit(mut self, sink: AuditSink) -> HttpClient {
+        self.audit = Some(sink);
+        self
+    }
+    pub fn get(&self, url: impl AsRef<str>) -> RequestBuilder {
+        self.client.request(Method::GET, url.as_ref())
+    }
+    pub fn post(&self, url: impl AsRef<str>) -> RequestBuilder {
+        self.client.request(Method::POST, url.as_ref())
+    }
+    pub fn put(&self, url: impl AsRef<str>) -> RequestBuilder {
+        self.client.request(Method::PUT, url.as_ref())
+    }
+    pub fn delete(&self, url: impl AsRef<str>) -> RequestBuilder {
+        self.client.request(Method::DELETE, url.as_ref())
+    }
+    /// 发送请求并读取响应内容
+    ///
+    /// # Returns
+    ///
+    /// 返回HTTP状态与响应内容,非2xx状态不视为错误
+    pub async fn execute(&self, req: RequestBuilder) -> Result<(StatusCode, String)> {
+        let req = req.build()?;
+        let method = req.method().clone();
+        let redacted = redact(req.url());
+        let url = redacted.as_str();
+        let body = req.body().and_then(|v| v.as_bytes()).map(|v| redact_body(&String::from_utf8_lossy(v)));
+        let entry = self.audit.as_ref().map(|_| {
+            let ctx = audit::context();
+            let mut params = redacted.query().unwrap_or_default().to_owned();
+            if let Some(body) = &body {
+                params = if params.is_empty() {
+                    body.clone()
+                } else {
+                    format!("{} {}", params, body)
+                };
+            }
+            AuditEntry {
+                method: method.to_string(),
+                endpoint: req.url().path().to_owned(),
+                params: Some(params).filter(|v| !v.is_empty()),
+                flownumber: ctx.flownumber,
+                userphone: ctx.userphone,
+                ..Default::default()
+            }
+        });
+        if self.log_body {
+            debug!("--> {} {} {}", method, url, body.unwrap_or_default());
+        } else {
+            debug!("--> {} {}", method, url);
+        }
+        let now = time::Instant::now();
+        let rv = async {
+            let resp = self.client.execute(req).await?;
+            let status = resp.status();
+            Ok::<_, httprequest::Error>((status, resp.text().await?))
+        }
+        .await;
+        if let (Some(sink), Some(mut entry)) = (&self.audit, entry) {
+            entry.latency_ms = now.elapsed().as_millis() as i32;
+            match &rv {
+                Ok((status, text)) => {
+                    entry.status = status.as_u16() as i32;
+                    entry.parse_response(text);
+                },
+                Err(e) => entry.errmsg = Some(e.to_string())
+            }
+            sink.record(entry);
+        }
+        match rv {
+            Ok((status, text)) => {
+                if self.log_body {
+                    debug!(
+                        "<-- {} {} {} elapsed: {}ms, {}",
+                        status,
+                        method,
+                        url,
+                        now.elapsed().as_millis(),
+                        redact_body(&text)
+                    );
+                } else {
+                    debug!("<-- {} {} {} elapsed: {}ms", status, method, url, now.elapsed().as_millis());
+                }
+                Ok((status, text))
+            },
+            Err(e) => {
+                warn!("<-- {} {} elapsed: {}ms, error: {}", method, url, now.elapsed().as_millis(), e);
+                Err(e.into())
+            }
+        }
+    }
+}
+
+/// 隐藏URL中的敏感参数
+fn redact(url: &Url) -> Url {
+    let mut url = url.clone();
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .map(|(k, v)| {
+            if SENSITIVE_PARAMS.contains(&k.as_ref()) {
+                (k.into_owned(), "***".to_owned())
+            } else {
+                (k.into_owned(), v.into_owned())
+            }
+        })
+        .collect();
+    if !pairs.is_empty() {
+        url.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+    url
+}
+
+/// 隐藏JSON内容中的敏感字段
+fn redact_body(text: &str) -> String {
+    match serde_json::from_str::<serde_json::Value>(text) {
+        Ok(serde_json::Value::Object(mut obj)) => {
+            for key in SENSITIVE_PARAMS {
+                if let Some(v) = obj.get_mut(*key) {
+                    *v = "***".into();
+                }
+            }
+            serde_json::Value::Object(obj).to_string()
+        },
+        _ => text.to_owned()
+    }
+}
@@ -0,0 +1,150 @@
+//!
+//! HTTP消息写入
+//!
+//! 消息写入发件箱;数据库不可达时写入本地缓存并定期回放。
+//! 缓存中有待回放的消息时新消息也写入缓存,保证写入顺序。
+//! 写入缓存的消息未指定幂等键时自动生成,回放中断后重新回放不会重复写入
+//!
+
+use crate::{
+    config::IngestConfig, outbox::{Enqueued, NewMessage, Outbox}, priority, shutdown::Shutdown, spool::Spool, Error, Result
+};
+use std::{
+    sync::atomic::{AtomicU64, Ordering}, time::{SystemTime, UNIX_EPOCH}
+};
+use tokio::time;
+
+/// 写入结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Submitted {
+    /// 已写入发件箱,返回记录ID
+    Queued(i64),
+    /// 幂等键重复,返回已存在的记录ID
+    Duplicate(i64),
+    /// 已写入本地缓存,返回幂等键
+    Spooled(String)
+}
+
+/// HTTP消息写入
+pub struct Ingest {
+    outbox: Outbox,
+    spool: Spool,
+    cfg: IngestConfig,
+    /// 生成幂等键的序号
+    seq: AtomicU64
+}
+
+impl Ingest {
+    pub fn new(outbox: Outbox, spool: Spool, cfg: IngestConfig) -> Ingest {
+        Ingest {
+            outbox,
+            spool,
+            cfg,
+            seq: AtomicU64::new(0)
+        }
+    }
+
+    /// 本地缓存
+    pub fn spool(&self) -> &Spool { &self.spool }
+
+    /// 写入消息
+    pub async fn submit(&self, msg: NewMessage) -> Result<Submitted> {
+        validate(&msg)?;
+        //缓存非空时写入缓存排在未回放的消息之后;持有期间回放等待,避免先于缓存中的消息写入发件箱
+        let _hold = self.spool.hold().await;
+        if !self.spool.is_empty().await {
+            return self.spool_msg(msg).await;
+        }
+        match self.outbox.enqueue(&msg).await {
+            Ok(Enqueued::Queued(id)) => Ok(Submitted::Queued(id)),
+            Ok(Enqueued::Duplicate(id)) => Ok(Submitted::Duplicate(id)),
+            Err(e) if e.is_db_unavailable() => {
+                warn!("db unavailable, spooling {}: {}", msg.flownumber, e);
+                self.spool_msg(msg).await
+            },
+            Err(e) => Err(e)
+        }
+    }
+
+    /// 回放本地缓存到发件箱,返回回放的消息数
+    pub async fn replay(&self) -> Result<usize> {
+        self.spool
+            .replay(|msg| {
+                let outbox = self.outbox.clone();
+                async move {
+                    if let Enqueued::Duplicate(id) = outbox.enqueue(&msg).await? {
+                        info!(
+                            "spooled {} already in outbox #{}",
+                            msg.idempotency_key.unwrap_or_default(),
+                            id
+                        );
+                    }
+                    Ok(())
+                }
+            })
+            .await
+    }
+
+    /// 定期回放本地缓存直到收到停机信号
+    pub async fn run(&self, mut shutdown: Shutdown) {
+        let mut ticker = time::interval(self.cfg.replay_interval());
+        ticker.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = shutdown.wait() => return,
+                _ = ticker.tick() => {}
+            }
+            if self.spool.is_empty().await {
+                continue;
+            }
+            match self.replay().await {
+                Ok(replayed) => info!("replayed {} spooled messages", replayed),
+                Err(e) if e.is_db_unavailable() => debug!("db still unavailable: {}", e),
+                Err(e) => error!("replay spool error: {}", e)
+            }
+        }
+    }
+
+    async fn spool_msg(&self, mut msg: NewMessage) -> Result<Submitted> {
+        let key = match msg.idempotency_key.as_deref().filter(|v| !v.is_empty()) {
+            Some(key) => key.to_owned(),
+            None => {
+                let key = self.next_key();
+                msg.idempotency_key = Some(key.clone());
+                key
+            }
+        };
+        self.spool.append(&msg).await?;
+        Ok(Submitted::Spooled(key))
+    }
+
+    /// 生成幂等键: `spool:<实例标识>:<毫秒时间戳>:<序号>`
+    fn next_key(&self) -> String {
+        let millis = SystemTime::now().duration_since(UNIX_EPOCH).map(|v| v.as_millis()).unwrap_or_default();
+        let seq = self.seq.fetch_add(1, Ordering::Relaxed);
+        format!("spool:{}:{}:{}", self.outbox.instance_id(), millis, seq)
+    }
+}
+
+/// 检查必填字段
+fn validate(msg: &NewMessage) -> Result<()> {
+    let required = [
+        ("exeuser", &msg.exeuser),
+        ("flownumber", &msg.flownumber),
+        ("flowmsgtype", &msg.flowmsgtype),
+        ("flowmsg", &msg.flowmsg),
+        ("userphone", &msg.userphone)
+    ];
+    for (name, value) in required {
+        if value.trim().is_empty() {
+            return Err(Error::Invalid(format!("{}不能为空", name)));
+        }
+    }
+    if msg.idempotency_key.as_ref().is_some_and(|v| v.chars().count() > 200) {
+        return Err(Error::Invalid("idempotency_key最长200个字符".to_owned()));
+    }
+    if msg.priority.is_some_and(|v| v > priority::MAX) {
+        return Err(Error::Invalid(format!("priority取值范围0-{}", priority::MAX)));
+    }
+    Ok(())
+}
@@ -0,0 +1,41 @@
+//!
+//! 钉钉消息推送服务
+//!
+
+#[macro_use]
+extern crate tracing;
+
+pub mod alert;
+pub mod app;
+pub mod approval;
+pub mod audit;
+pub mod card;
+pub mod channel;
+pub mod config;
+pub mod dingtalk;
+pub mod error;
+pub mod health;
+pub mod http;
+pub mod ingest;
+pub mod outbox;
+pub mod phone;
+pub mod priority;
+pub mod receipt;
+pub mod reload;
+pub mod resend;
+pub mod secret;
+pub mod sender;
+pub mod server;
+pub mod shutdown;
+pub mod spool;
+pub mod status;
+pub mod systemd;
+pub mod template;
+pub mod todo;
+pub mod user;
+
+pub use config::Config;
+pub use error::Error;
+pub use user::User;
+
+pub type Result<T> = ::std::result::Result<T, error::Error>;
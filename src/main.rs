@@ -1,172 +1,481 @@
-//tokio异步运行时
-use tokio::time;
-//reqwestHTTP请求
-use httprequest::Client;
-//hashmap
-use std::{collections::HashMap, result};
-//系列化
-use serde::{Serialize,Deserialize};
-
-
-
-//钉钉获取token请求主体
-#[derive(Debug,Serialize,Deserialize)]
-struct DDToken {
-    url:String,
-    appkey: String,
-    appsecret:String    
-}
-//DDTokenResult
-#[derive(Debug,Serialize,Deserialize)]
-struct DDTokenResult{
-    errcode:u32,
-    access_token:String,
-    errmsg:String,
-} 
-
-//实现token请求主体
-impl DDToken {
-    //创建实例
-    pub fn new () -> DDToken {
-        DDToken { 
-            //获取钉钉token的URL及参数
-            url:"https://oapi.dingtalk.com/gettoken".to_owned(),
-            appkey:"dingzblrl7qs6pkygqcn".to_owned(), 
-            appsecret:"26GGYRR_UD1VpHxDBYVixYvxbPGDBsY5lUB8DcRqpSgO4zZax427woZTmmODX4oU".to_owned()
-        }
-    }
+#[macro_use]
+extern crate tracing;
+
+use arc_swap::ArcSwap;
+use clap::{Args, Parser, Subcommand};
+use mssql::prelude::NaiveDateTime;
+use sendmsg::{
+    alert::Alerter, app::AppRegistry, approval::Approvals, audit::{self, Audit}, dingtalk::{ApiVersion, DDApi}, health::Health, http::HttpClient, ingest::Ingest, outbox::Outbox, priority::Priorities, receipt::ReadTracker, reload::Reloader, resend::{self, ResendFilter}, secret::{self, SecretKey, SECRET_KEY_ENV}, sender::{Delivery, Sender}, server, shutdown::{self, Shutdown}, spool::Spool, status::Status, systemd, todo::TodoSync, Config, Result
+};
+use std::{fs, io::Read, sync::Arc, time};
 
-    //获取钉钉机器人token方法
-    pub async fn get_token(&self) -> String {
-        //将获取token参数加入到一个hash变量
-        let mut get_token_param = HashMap::new();
-        get_token_param.insert("appkey", self.appkey.clone());
-        get_token_param.insert("appsecret", self.appsecret.clone());        
+/// 钉钉消息推送服务
+#[derive(Parser)]
+#[command(name = "sendmsg", version)]
+struct Cli {
+    /// 配置文件路径
+    #[arg(short, long, global = true, env = "SENDMSG_CONFIG", default_value = "sendmsg.toml")]
+    config: String,
+    #[command(subcommand)]
+    command: Option<Command>
+}
 
-        //新增一个客户端实例用来访问钉钉接口获取access_token
-        let client = Client::new();
-        let token_str = client.get(self.url.clone()).query(&get_token_param).send().await.unwrap().text().await.unwrap();
-        let access_token:DDTokenResult =  serde_json::from_str(&token_str).unwrap();
-        
-        access_token.access_token     
+#[derive(Subcommand)]
+enum Command {
+    /// 运行发送服务(默认)
+    Run,
+    /// 配置密钥管理
+    #[command(subcommand)]
+    Secret(SecretCommand),
+    /// 查询钉钉接口审计记录
+    Audit(AuditQuery),
+    /// 试运行:输出待发送记录将要发送的请求,不发送消息也不更新发件箱
+    DryRun(DryRunArgs),
+    /// 按条件批量重新发送发送失败的记录
+    Resend(ResendArgs),
+    /// 查询发件箱记录的状态变更历史
+    History {
+        /// 发件箱记录ID
+        id: i64
+    },
+    /// 撤回已发送的机器人单聊消息
+    Recall {
+        /// 发件箱记录ID
+        id: i64
+    }
+}
 
-    }    
+#[derive(Args)]
+struct DryRunArgs {
+    /// 最多预览的记录数
+    #[arg(long, default_value_t = 50)]
+    limit: u32,
+    /// 输出文件,未指定时输出到标准输出
+    #[arg(short, long)]
+    output: Option<String>
+}
 
+#[derive(Args)]
+struct ResendArgs {
+    /// 写入时间起(含),如`2024-05-25 08:00`
+    #[arg(long, value_parser = resend::parse_time)]
+    since: Option<NaiveDateTime>,
+    /// 写入时间止(不含)
+    #[arg(long, value_parser = resend::parse_time)]
+    until: Option<NaiveDateTime>,
+    /// 消息类型
+    #[arg(long)]
+    flowmsgtype: Option<String>,
+    /// 失败原因中的错误码,如`40014`、`ServiceUnavailable`
+    #[arg(long)]
+    errcode: Option<String>,
+    /// 机器人编码
+    #[arg(long)]
+    robotcode: Option<String>,
+    /// 接收人
+    #[arg(long)]
+    exeuser: Option<String>,
+    /// 记录状态(failed/undeliverable),可多次指定
+    #[arg(long = "status", default_value = "failed")]
+    statuses: Vec<Status>,
+    /// 只统计符合条件的记录数,不重新发送
+    #[arg(long)]
+    dry_run: bool,
+    /// 每秒最多重新发送的记录数
+    #[arg(long, default_value_t = 10)]
+    rate: u32,
+    /// 最多重新发送的记录数
+    #[arg(long)]
+    limit: Option<u32>,
+    /// 报告输出文件,未指定时输出到标准输出
+    #[arg(short, long)]
+    output: Option<String>
 }
 
-//通过useriphone获取userid
-#[derive(Debug,Serialize,Deserialize)]
-struct DDUserid {
-    url: String,
-    access_token: String,
-    mobile: String,
+#[derive(Args)]
+struct AuditQuery {
+    /// 流程号
+    #[arg(long)]
+    flownumber: Option<String>,
+    /// 手机号,支持`+86 138 0000 0000`等格式
+    #[arg(long)]
+    phone: Option<String>,
+    /// 最多显示的记录数
+    #[arg(long, default_value_t = 50)]
+    limit: u32
 }
 
+#[derive(Subcommand)]
+enum SecretCommand {
+    /// 生成加密密钥(base64),设置到环境变量SENDMSG_SECRET_KEY
+    Keygen,
+    /// 使用SENDMSG_SECRET_KEY加密配置值,输出`enc:`格式的值
+    Encrypt {
+        /// 明文,未指定时从标准输入读取
+        value: Option<String>
+    }
+}
 
-//userid返回类型
-#[derive(Debug,Serialize,Deserialize)]
-struct DDUseridResult {
-    errcode:u32,
-    result:DDUseridValue,    
-    errmsg:String,
-    request_id:String,
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt().with_max_level(tracing::Level::INFO).with_writer(std::io::stderr).init();
 
+    let cli = Cli::parse();
+    let rv = match cli.command.unwrap_or(Command::Run) {
+        Command::Run => {
+            match Config::load(&cli.config) {
+                Ok(cfg) => run(cfg, &cli.config).await,
+                Err(e) => Err(e)
+            }
+        },
+        Command::Secret(cmd) => run_secret(cmd),
+        Command::Audit(query) => {
+            match Config::load(&cli.config) {
+                Ok(cfg) => run_audit(cfg, query).await,
+                Err(e) => Err(e)
+            }
+        },
+        Command::DryRun(args) => {
+            match Config::load(&cli.config) {
+                Ok(cfg) => run_dry_run(cfg, args).await,
+                Err(e) => Err(e)
+            }
+        },
+        Command::Resend(args) => {
+            match Config::load(&cli.config) {
+                Ok(cfg) => run_resend(cfg, args).await,
+                Err(e) => Err(e)
+            }
+        },
+        Command::History {
+            id
+        } => {
+            match Config::load(&cli.config) {
+                Ok(cfg) => run_history(cfg, id).await,
+                Err(e) => Err(e)
+            }
+        },
+        Command::Recall {
+            id
+        } => {
+            match Config::load(&cli.config) {
+                Ok(cfg) => run_recall(cfg, id).await,
+                Err(e) => Err(e)
+            }
+        },
+    };
+    if let Err(e) = rv {
+        error!("{}", e);
+        std::process::exit(1);
+    }
 }
 
-impl DDUseridResult {
-    pub fn new() -> DDUseridResult {
-        DDUseridResult{errcode:0,result:DDUseridValue{userid:"".to_owned()},errmsg:"".to_owned(),request_id:"".to_owned()}
+fn run_secret(cmd: SecretCommand) -> Result<()> {
+    match cmd {
+        SecretCommand::Keygen => {
+            println!("{}", SecretKey::generate().to_base64());
+        },
+        SecretCommand::Encrypt {
+            value
+        } => {
+            let key = SecretKey::from_env()?
+                .ok_or_else(|| sendmsg::Error::config(format!("未设置环境变量{}", SECRET_KEY_ENV)))?;
+            let value = match value {
+                Some(v) => v,
+                None => {
+                    let mut v = String::new();
+                    std::io::stdin().read_to_string(&mut v)?;
+                    v.trim_end_matches(['\r', '\n']).to_owned()
+                }
+            };
+            println!("{}", secret::encrypt(&key, &value)?);
+        }
     }
-    
+    Ok(())
 }
 
-#[derive(Debug,Serialize,Deserialize)]
-struct DDUseridValue {
-    userid:String,
+async fn connect_pool(cfg: &Config) -> Result<mssql::Pool> {
+    let pool = mssql::Pool::builder()
+        .max_size(cfg.pool.max_size)
+        .min_idle(cfg.pool.min_idle)
+        .connect_timeout(cfg.pool.connect_timeout)
+        .connect(&cfg.db_conn_str)
+        .await?;
+    Ok(pool)
 }
-//钉钉DDUserid实现
-impl DDUserid {
-    pub fn new(access_token: String, mobile:String) -> DDUserid {
-        DDUserid{
-            url:"https://oapi.dingtalk.com/topapi/v2/user/getbymobile".to_owned(),
-            access_token,
-            mobile
-        }
+
+async fn run(cfg: Config, path: &str) -> Result<()> {
+    let pool = connect_pool(&cfg).await?;
+
+    //监听停机信号
+    let (trigger, shutdown) = shutdown::channel();
+    let signal = shutdown::wait_signal()?;
+    tokio::spawn(async move {
+        signal.await;
+        systemd::stopping();
+        trigger.trigger();
+    });
+
+    let mut http = HttpClient::new(&cfg.http)?;
+    let mut audit_task = None;
+    if cfg.audit.enabled {
+        let audit = Audit::new(pool.clone(), cfg.audit.clone());
+        audit.ensure_schema().await?;
+        let (sink, rx) = audit::channel(cfg.audit.queue_size);
+        http = http.with_audit(sink);
+        audit_task = Some(tokio::spawn(async move { audit.run(rx).await }));
     }
+    let api = DDApi::new(&cfg.dingtalk, http.clone());
+    let delivery = Delivery::from_config(&cfg, &api, http.clone())?;
+    let delivery = Arc::new(ArcSwap::from_pointee(delivery));
 
-    pub async fn get_userid(&self) -> String { 
-        let mut access_token = HashMap::new();
-        access_token.insert("access_token", self.access_token.clone());
-        //let mut mobile = HashMap::new();
-        access_token.insert("mobile", self.mobile.clone());
-         
+    let outbox = Outbox::new(pool.clone(), cfg.sender.instance_id(), cfg.sender.lease_timeout())
+        .with_priorities(Priorities::new(&cfg.priority)?);
+    outbox.ensure_schema().await?;
+    info!("instance: {}", outbox.instance_id());
 
-        //新增一个客户端实例用来访问钉钉接口获取userid
-        let client = Client::new(); 
-        let useridresult =  
-        client.post("https://oapi.dingtalk.com/topapi/v2/user/getbymobile").query(&access_token).send().await.unwrap().text().await; 
-        let mut userid = DDUseridResult::new();
-        match useridresult {
-            Ok(v) =>{
-                userid  = serde_json::from_str(&v).unwrap();
-            },
-            Err(e) => println!("ErrMSG:{:#?}",e),            
-        }        
-        userid.result.userid 
+    let (mut server_task, mut ingest_task) = (None, None);
+    if cfg.server.enabled {
+        let health = Health::new(pool.clone(), outbox.clone(), delivery.clone(), cfg.server.clone());
+        let listener = server::bind(&cfg.server.listen).await?;
+        let mut router = server::router(Arc::new(health));
+        if cfg.ingest.enabled {
+            let spool = Spool::open(&cfg.ingest.spool_dir)?;
+            let ingest = Arc::new(Ingest::new(outbox.clone(), spool, cfg.ingest.clone()));
+            router = router.merge(server::ingest_router(ingest.clone(), cfg.server.token.clone()));
+            let shutdown = shutdown.clone();
+            ingest_task = Some(tokio::spawn(async move { ingest.run(shutdown).await }));
+        }
+        if cfg.approval.enabled {
+            let approvals = Arc::new(Approvals::new(outbox.clone(), delivery.clone(), cfg.approval.clone())?);
+            router = router.merge(server::robot_router(approvals.clone(), &cfg.approval.path));
+            if cfg.approval.card_secret.as_deref().is_some_and(|v| !v.is_empty()) {
+                router = router.merge(server::card_router(approvals, &cfg.approval.card_path));
+            }
+        }
+        server_task = Some(tokio::spawn(server::serve(listener, router, shutdown.clone())));
+    }
 
+    let mut reload_task = None;
+    if cfg.reload.enabled {
+        let reloader = Reloader::new(path, api.clone(), http.clone(), delivery.clone(), cfg.reload.clone());
+        let (template_dir, shutdown) = (cfg.templates.dir.clone(), shutdown.clone());
+        reload_task =
+            Some(tokio::spawn(async move { reloader.run(template_dir.as_deref(), shutdown).await }));
     }
-}
 
-struct User {
-    exeuser:String,
-    flownumber:String,
-    flowmsgtype:String,
-    flowmsg:String,
-    userphone:String,
-    robotcode:String,
-}
+    let mut alert_task = None;
+    if cfg.alert.enabled {
+        let alerter = Alerter::new(outbox.clone(), http.clone(), cfg.alert.clone())?;
+        let shutdown = shutdown.clone();
+        alert_task = Some(tokio::spawn(async move { alerter.run(shutdown).await }));
+    }
 
-impl User {
-    //初始化用户实例
-    pub fn new(exeuser:String, flownumber:String,flowmsgtype:String,flowmsg:String,userphone:String,robotcode:String) -> User {
-        User {exeuser,flownumber,flowmsgtype,flowmsg, userphone,robotcode}
+    let mut todo_task = None;
+    if cfg.todo.enabled {
+        let sync = TodoSync::new(pool.clone(), delivery.clone(), cfg.todo.clone())?;
+        sync.ensure_schema().await?;
+        let shutdown = shutdown.clone();
+        todo_task = Some(tokio::spawn(async move { sync.run(shutdown).await }));
     }
 
-    //获取用于访问钉钉机器人的token
-    pub async fn get_token(&self) -> String {
-        let dd_get_token = DDToken::new();
-        let dd_access_token = dd_get_token.get_token().await;
-        dd_access_token
+    let mut read_task = None;
+    if cfg.sender.read_interval > 0 {
+        let tracker = ReadTracker::new(outbox.clone(), delivery.clone(), cfg.sender.clone());
+        let shutdown = shutdown.clone();
+        read_task = Some(tokio::spawn(async move { tracker.run(shutdown).await }));
     }
 
-    //通过用户手机获取userid
-    pub async fn get_userid(&self,dd_access_token:String,mobile:String) -> String {
-        //通过手机获取userid
-        let dd_get_userid = DDUserid::new(dd_access_token, mobile);
-        let dd_userid = dd_get_userid.get_userid().await; 
-        dd_userid 
+    init_tokens(delivery.load_full().apps(), shutdown.clone()).await;
+    if !shutdown.is_shutdown() {
+        systemd::ready();
     }
 
-    //发送消息到当前用户钉钉账号
-    pub async fn send_msg(&self,access_token:String,robotcode:String,userid:String,msgkey:String,msgparams:String) {
-        
+    let sender = Sender::new(outbox, delivery, cfg.sender);
+    let rv = sender.run(shutdown).await;
+    if let Some(task) = alert_task {
+        let _ = task.await;
+    }
+    if let Some(task) = todo_task {
+        let _ = task.await;
+    }
+    if let Some(task) = read_task {
+        let _ = task.await;
+    }
+    if let Some(task) = reload_task {
+        if let Ok(Err(e)) = task.await {
+            error!("config watcher error: {}", e);
+        }
+    }
+    if let Some(task) = ingest_task {
+        let _ = task.await;
+    }
+    if let Some(task) = server_task {
+        if let Ok(Err(e)) = task.await {
+            error!("http server error: {}", e);
+        }
+    }
+    //释放所有HTTP客户端后审计队列关闭,等待剩余记录写入
+    drop(sender);
+    drop(api);
+    drop(http);
+    if let Some(task) = audit_task {
+        if tokio::time::timeout(time::Duration::from_secs(cfg.pool.close_timeout), task).await.is_err() {
+            warn!("audit writer timeout, remaining entries dropped");
+        }
+    }
 
+    if !pool.close(time::Duration::from_secs(cfg.pool.close_timeout)).await {
+        warn!("db pool closed with connections still in use");
     }
+    info!("stopped");
+    rv
 }
- 
 
+/// 获取所有钉钉应用的旧版与新版接口access_token,失败时重试直到成功或停机
+async fn init_tokens(apps: &AppRegistry, mut shutdown: Shutdown) {
+    let mut retry = time::Duration::from_secs(1);
+    for (app, version) in apps.apps().iter().flat_map(|app| ApiVersion::ALL.map(|v| (app, v))) {
+        while let Err(e) = app.token(version).await {
+            warn!(
+                "init {} access_token of {} error: {}, retry in {}s",
+                version.as_str(),
+                app.name(),
+                e,
+                retry.as_secs()
+            );
+            systemd::status(&format!(
+                "waiting for {} access_token of {}: {}",
+                version.as_str(),
+                app.name(),
+                e
+            ));
+            tokio::select! {
+                _ = shutdown.wait() => return,
+                _ = tokio::time::sleep(retry) => {}
+            }
+            retry = (retry * 2).min(time::Duration::from_secs(60));
+        }
+    }
+}
 
+/// 试运行
+///
+/// 不创建/修改表结构,不记录审计日志
+async fn run_dry_run(cfg: Config, args: DryRunArgs) -> Result<()> {
+    let pool = connect_pool(&cfg).await?;
+    let http = HttpClient::new(&cfg.http)?;
+    let api = DDApi::new(&cfg.dingtalk, http.clone());
+    let delivery = Delivery::from_config(&cfg, &api, http)?;
+    let outbox = Outbox::new(pool, cfg.sender.instance_id(), cfg.sender.lease_timeout())
+        .with_priorities(Priorities::new(&cfg.priority)?);
+    let sender = Sender::new(outbox, Arc::new(ArcSwap::from_pointee(delivery)), cfg.sender);
+    let count = match &args.output {
+        Some(path) => {
+            let mut file = std::io::BufWriter::new(fs::File::create(path)?);
+            sender.dry_run(args.limit, &mut file).await?
+        },
+        None => sender.dry_run(args.limit, &mut std::io::stdout().lock()).await?
+    };
+    info!("dry run finished, {} records previewed", count);
+    Ok(())
+}
 
+/// 批量重新发送
+async fn run_resend(cfg: Config, args: ResendArgs) -> Result<()> {
+    let filter = ResendFilter {
+        since: args.since,
+        until: args.until,
+        flowmsgtype: args.flowmsgtype,
+        errcode: args.errcode,
+        robotcode: args.robotcode,
+        exeuser: args.exeuser,
+        statuses: args.statuses
+    };
+    filter.validate()?;
+    let pool = connect_pool(&cfg).await?;
+    let outbox = Outbox::new(pool, cfg.sender.instance_id(), cfg.sender.lease_timeout());
+    outbox.ensure_schema().await?;
+    let count = outbox.count_resend(&filter).await?;
+    info!("{} records matched", count);
+    if args.dry_run || count == 0 {
+        return Ok(());
+    }
+    let report = match &args.output {
+        Some(path) => {
+            let mut file = std::io::BufWriter::new(fs::File::create(path)?);
+            resend::resend(&outbox, &filter, args.rate, args.limit, &mut file).await?
+        },
+        None => resend::resend(&outbox, &filter, args.rate, args.limit, &mut std::io::stdout().lock()).await?
+    };
+    for (flowmsgtype, count) in report.flowmsgtypes.iter() {
+        info!("  {}: {}", flowmsgtype, count);
+    }
+    info!("resend finished, {} records re-queued", report.total);
+    Ok(())
+}
 
-#[tokio::main]
-async fn main() {
-    let user = User::new("苏宁绿".to_owned(),"EBS20240525000001_20240525135052".to_owned(),"sampleMarkdown".to_owned(),"您有待办任务需要处理".to_owned(),"15345923407".to_owned(),"dingzblrl7qs6pkygqcn".to_owned());
-    let access_token = user.get_token().await;
-    println!("{}",access_token);
-    let userid=user.get_userid(access_token, user.userphone.clone()).await;
-    println!("{}",userid);    
+/// 查询状态变更历史
+async fn run_history(cfg: Config, id: i64) -> Result<()> {
+    let pool = connect_pool(&cfg).await?;
+    let outbox = Outbox::new(pool, cfg.sender.instance_id(), cfg.sender.lease_timeout());
+    let history = outbox.history(id).await?;
+    for entry in history.iter() {
+        println!(
+            "{} {} → {} instance: {} cause: {}",
+            entry.created_at.as_deref().unwrap_or_default(),
+            entry.from_status.as_deref().unwrap_or("-"),
+            entry.to_status,
+            entry.instance.as_deref().unwrap_or("-"),
+            entry.cause.as_deref().unwrap_or("-")
+        );
+    }
+    println!("{} transitions", history.len());
+    Ok(())
+}
 
+/// 撤回消息
+async fn run_recall(cfg: Config, id: i64) -> Result<()> {
+    let pool = connect_pool(&cfg).await?;
+    let outbox = Outbox::new(pool, cfg.sender.instance_id(), cfg.sender.lease_timeout());
+    outbox.ensure_schema().await?;
+    let user =
+        outbox.get(id).await?.ok_or_else(|| sendmsg::Error::custom(format!("发件箱记录不存在, #{}", id)))?;
+    let status: Status = user.status.as_deref().unwrap_or_default().parse()?;
+    status.transition(Status::Recalled)?;
+    let http = HttpClient::new(&cfg.http)?;
+    let api = DDApi::new(&cfg.dingtalk, http.clone());
+    let delivery = Delivery::from_config(&cfg, &api, http)?;
+    delivery.recall(&user).await?;
+    outbox.transition(id, Status::Recalled, "recall").await?;
+    info!("#{} recalled", id);
+    Ok(())
+}
 
-   
-    
+/// 查询审计记录
+async fn run_audit(cfg: Config, query: AuditQuery) -> Result<()> {
+    if query.flownumber.is_none() && query.phone.is_none() {
+        return Err(sendmsg::Error::custom("请指定--flownumber或--phone"));
+    }
+    let pool = connect_pool(&cfg).await?;
+    let audit = Audit::new(pool, cfg.audit);
+    let entries = audit.query(query.flownumber.as_deref(), query.phone.as_deref(), query.limit).await?;
+    for entry in entries.iter().rev() {
+        println!(
+            "{} {} {} {} {}ms errcode: {} errmsg: {} request_id: {} flownumber: {} phone: {}\n    {}",
+            entry.created_at.as_deref().unwrap_or_default(),
+            entry.method,
+            entry.endpoint,
+            entry.status,
+            entry.latency_ms,
+            entry.errcode.as_deref().unwrap_or("-"),
+            entry.errmsg.as_deref().unwrap_or("-"),
+            entry.request_id.as_deref().unwrap_or("-"),
+            entry.flownumber.as_deref().unwrap_or("-"),
+            entry.userphone.as_deref().unwrap_or("-"),
+            entry.params.as_deref().unwrap_or_default()
+        );
+    }
+    println!("{} entries", entries.len());
+    Ok(())
 }
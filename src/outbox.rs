@@ -0,0 +1,700 @@
+//!
+//! 发件箱
+//!
+//! 记录状态及允许的状态变更见[`crate::status`],无法送达的记录定期汇总通知管理员。
+//! 状态变更只更新当前状态允许变更的记录,每次变更(含时间、原因与实例)写入`sendmsg_outbox_history`
+//!
+//! 多个实例可同时运行,领取时使用`READPAST, UPDLOCK, ROWLOCK`跳过其他实例已锁定的记录,
+//! 实例异常退出后其领取的记录在租约到期后自动重新领取
+//!
+//! `sampleFile`/`sampleImageMsg`消息的附件取自`attachment`(varbinary)或`attachment_path`,
+//! `sampleActionCard`消息的按钮链接由配置的模板与`params`(JSON对象)生成
+//!
+//! 通过HTTP写入的记录可指定`idempotency_key`,相同键的记录只写入一次
+//!
+//! 领取时按优先级(`priority`,为空时取消息类型的默认优先级)从高到低、同优先级按写入顺序领取,
+//! 并为普通优先级保留部分名额,见[`crate::priority`]
+//!
+//! 发送成功或最终失败时`channel`记录最后尝试的渠道,切换过渠道时`errmsg`记录各渠道的失败原因
+//!
+//! `attempts`记录调用发送接口的次数,达到最大发送次数后发送失败的记录放弃发送(`dead`);
+//! 机器人单聊消息定期查询已读状态,`read_checked_at`记录最后查询时间
+//!
+
+use crate::{
+    config::Channel, priority::Priorities, resend::{ResendFilter, Resent}, status::Status, Error, Result, User
+};
+use mssql::{sql_bind, sql_format, Pool, Sql, ToSqlString};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time;
+
+/// 发件箱表结构
+const OUTBOX_DDL: &str = "
+CREATE TABLE sendmsg_outbox (
+    id BIGINT IDENTITY(1,1) NOT NULL PRIMARY KEY,
+    exeuser NVARCHAR(50) NOT NULL,
+    flownumber NVARCHAR(100) NOT NULL,
+    flowmsgtype VARCHAR(50) NOT NULL,
+    flowmsg NVARCHAR(MAX) NOT NULL,
+    userphone VARCHAR(50) NOT NULL,
+    robotcode VARCHAR(100) NOT NULL,
+    source NVARCHAR(100) NULL,
+    params NVARCHAR(MAX) NULL,
+    attachment_path NVARCHAR(500) NULL,
+    attachment_name NVARCHAR(200) NULL,
+    attachment VARBINARY(MAX) NULL,
+    email NVARCHAR(200) NULL,
+    status VARCHAR(16) NOT NULL DEFAULT 'queued',
+    claimed_at DATETIME NULL,
+    claimed_by VARCHAR(100) NULL,
+    lease_expires_at DATETIME NULL,
+    sent_at DATETIME NULL,
+    process_query_key VARCHAR(200) NULL,
+    channel VARCHAR(20) NULL,
+    idempotency_key NVARCHAR(200) NULL,
+    priority TINYINT NULL,
+    errmsg NVARCHAR(1000) NULL,
+    attempts INT NOT NULL DEFAULT 0,
+    read_checked_at DATETIME NULL,
+    alerted_at DATETIME NULL,
+    created_at DATETIME NOT NULL DEFAULT GETDATE()
+);
+CREATE INDEX ix_sendmsg_outbox_status ON sendmsg_outbox(status, id);
+";
+
+/// 状态变更历史表结构
+const HISTORY_DDL: &str = "
+CREATE TABLE sendmsg_outbox_history (
+    id BIGINT IDENTITY(1,1) NOT NULL PRIMARY KEY,
+    outbox_id BIGINT NOT NULL,
+    from_status VARCHAR(16) NULL,
+    to_status VARCHAR(16) NOT NULL,
+    cause NVARCHAR(1000) NULL,
+    instance VARCHAR(100) NULL,
+    created_at DATETIME NOT NULL DEFAULT GETDATE()
+);
+CREATE INDEX ix_sendmsg_outbox_history_outbox_id ON sendmsg_outbox_history(outbox_id, id);
+";
+
+/// 旧版本表结构缺少的字段
+const MIGRATIONS: &[(&str, &str)] = &[
+    ("source", "ALTER TABLE sendmsg_outbox ADD source NVARCHAR(100) NULL"),
+    ("claimed_by", "ALTER TABLE sendmsg_outbox ADD claimed_by VARCHAR(100) NULL"),
+    ("lease_expires_at", "ALTER TABLE sendmsg_outbox ADD lease_expires_at DATETIME NULL"),
+    ("attachment_path", "ALTER TABLE sendmsg_outbox ADD attachment_path NVARCHAR(500) NULL"),
+    ("attachment_name", "ALTER TABLE sendmsg_outbox ADD attachment_name NVARCHAR(200) NULL"),
+    ("attachment", "ALTER TABLE sendmsg_outbox ADD attachment VARBINARY(MAX) NULL"),
+    ("params", "ALTER TABLE sendmsg_outbox ADD params NVARCHAR(MAX) NULL"),
+    ("alerted_at", "ALTER TABLE sendmsg_outbox ADD alerted_at DATETIME NULL"),
+    ("email", "ALTER TABLE sendmsg_outbox ADD email NVARCHAR(200) NULL"),
+    ("channel", "ALTER TABLE sendmsg_outbox ADD channel VARCHAR(20) NULL"),
+    ("idempotency_key", "ALTER TABLE sendmsg_outbox ADD idempotency_key NVARCHAR(200) NULL"),
+    ("priority", "ALTER TABLE sendmsg_outbox ADD priority TINYINT NULL"),
+    ("attempts", "ALTER TABLE sendmsg_outbox ADD attempts INT NOT NULL DEFAULT 0"),
+    ("read_checked_at", "ALTER TABLE sendmsg_outbox ADD read_checked_at DATETIME NULL")
+];
+
+/// 索引(不存在时创建)
+const INDEXES: &[(&str, &str)] = &[(
+    "ux_sendmsg_outbox_idempotency_key",
+    "CREATE UNIQUE INDEX ux_sendmsg_outbox_idempotency_key ON sendmsg_outbox(idempotency_key)
+    WHERE idempotency_key IS NOT NULL"
+)];
+
+/// 新消息(HTTP写入)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewMessage {
+    pub exeuser: String,
+    pub flownumber: String,
+    pub flowmsgtype: String,
+    pub flowmsg: String,
+    pub userphone: String,
+    #[serde(default)]
+    pub robotcode: String,
+    #[serde(default)]
+    pub source: Option<String>,
+    /// 消息模板参数,JSON对象或JSON字符串
+    #[serde(default)]
+    pub params: Option<Value>,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub attachment_path: Option<String>,
+    #[serde(default)]
+    pub attachment_name: Option<String>,
+    /// 幂等键,相同键的消息只写入一次
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
+    /// 优先级(0-9,越大越优先),为空时取消息类型的默认优先级
+    #[serde(default)]
+    pub priority: Option<u8>
+}
+
+/// 写入结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Enqueued {
+    /// 已写入,返回记录ID
+    Queued(i64),
+    /// 幂等键重复,返回已存在的记录ID
+    Duplicate(i64)
+}
+
+/// 状态变更记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Transition {
+    pub id: i64,
+    pub outbox_id: i64,
+    /// 变更前状态,写入时为空
+    pub from_status: Option<String>,
+    pub to_status: String,
+    /// 变更原因
+    pub cause: Option<String>,
+    /// 执行变更的实例
+    pub instance: Option<String>,
+    pub created_at: Option<String>
+}
+
+/// 无法送达的记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Undeliverable {
+    pub id: i64,
+    pub exeuser: String,
+    pub userphone: String,
+    pub flownumber: String,
+    #[serde(default)]
+    pub errmsg: Option<String>
+}
+
+/// 发件箱
+#[derive(Clone)]
+pub struct Outbox {
+    pool: Pool,
+    /// 实例标识,记录在`claimed_by`
+    instance_id: String,
+    /// 领取租约时长
+    lease: time::Duration,
+    /// 消息类型的默认优先级
+    priorities: Priorities,
+    /// 记录实际优先级的SQL表达式
+    priority_expr: String,
+    /// 领取排序的SQL表达式(含等待时间提升)
+    order_expr: String
+}
+
+impl Outbox {
+    pub fn new(pool: Pool, instance_id: String, lease: time::Duration) -> Outbox {
+        let priorities = Priorities::default();
+        Outbox {
+            pool,
+            instance_id,
+            lease,
+            priority_expr: priorities.sql_expr(),
+            order_expr: priorities.order_expr(),
+            priorities
+        }
+    }
+    /// 设置消息类型的默认优先级
+    pub fn with_priorities(mut self, priorities: Priorities) -> Outbox {
+        self.priority_expr = priorities.sql_expr();
+        self.order_expr = priorities.order_expr();
+        self.priorities = priorities;
+        self
+    }
+    /// 连接池
+    pub fn pool(&self) -> &Pool { &self.pool }
+    /// 实例标识
+    pub fn instance_id(&self) -> &str { &self.instance_id }
+    /// 领取租约时长
+    pub fn lease(&self) -> time::Duration { self.lease }
+    /// 消息类型的默认优先级
+    pub fn priorities(&self) -> &Priorities { &self.priorities }
+    /// 创建发件箱表(不存在时)
+    pub async fn ensure_schema(&self) -> Result<()> {
+        let conn = self.pool.get().await?;
+        if !conn.object_exists("sendmsg_outbox").await? {
+            conn.exec(OUTBOX_DDL).await?;
+        }
+        if !conn.object_exists("sendmsg_outbox_history").await? {
+            conn.exec(HISTORY_DDL).await?;
+        }
+        //兼容旧版本表结构
+        for (column, ddl) in MIGRATIONS {
+            if !conn.column_exists("sendmsg_outbox", column).await? {
+                conn.exec(*ddl).await?;
+            }
+        }
+        for (name, ddl) in INDEXES {
+            let exists = conn
+                .query_scalar_i32(sql_bind!(
+                    "SELECT 1 FROM sys.indexes WHERE object_id = OBJECT_ID('sendmsg_outbox') AND name = @P1",
+                    *name
+                ))
+                .await?;
+            if exists.is_none() {
+                conn.exec(*ddl).await?;
+            }
+        }
+        Ok(())
+    }
+    /// 写入待发送记录
+    ///
+    /// 幂等键已存在时不重复写入
+    pub async fn enqueue(&self, msg: &NewMessage) -> Result<Enqueued> {
+        let conn = self.pool.get().await?;
+        let params = match &msg.params {
+            Some(Value::String(v)) => Some(v.clone()),
+            Some(Value::Null) | None => None,
+            Some(v) => Some(v.to_string())
+        };
+        let rv = conn
+            .query_scalar_i64(sql_bind!(
+                "INSERT INTO sendmsg_outbox
+                    (exeuser, flownumber, flowmsgtype, flowmsg, userphone, robotcode, source, params, email,
+                    attachment_path, attachment_name, idempotency_key, priority)
+                OUTPUT inserted.id, NULL, inserted.status, N'enqueued', @P14
+                    INTO sendmsg_outbox_history(outbox_id, from_status, to_status, cause, instance)
+                OUTPUT inserted.id
+                VALUES (@P1, @P2, @P3, @P4, @P5, @P6, @P7, @P8, @P9, @P10, @P11, @P12, @P13)",
+                msg.exeuser.clone(),
+                msg.flownumber.clone(),
+                msg.flowmsgtype.clone(),
+                msg.flowmsg.clone(),
+                msg.userphone.clone(),
+                msg.robotcode.clone(),
+                msg.source.clone(),
+                params,
+                msg.email.clone(),
+                msg.attachment_path.clone(),
+                msg.attachment_name.clone(),
+                msg.idempotency_key.clone(),
+                msg.priority,
+                self.instance_id.clone()
+            ))
+            .await;
+        match rv {
+            Ok(id) => Ok(Enqueued::Queued(id.unwrap_or_default())),
+            Err(e) if e.is_unique_violation() => {
+                let id = conn
+                    .query_scalar_i64(sql_bind!(
+                        "SELECT id FROM sendmsg_outbox WHERE idempotency_key = @P1",
+                        msg.idempotency_key.clone()
+                    ))
+                    .await?;
+                Ok(Enqueued::Duplicate(id.unwrap_or_default()))
+            },
+            Err(e) => Err(e.into())
+        }
+    }
+    /// 领取待发送记录
+    ///
+    /// 最多领取`batch_size`条待发送或租约已到期的记录,其中最多`reserved`条为普通优先级保留,
+    /// 其余按优先级(随等待时间提升)从高到低领取。领取后状态为`claimed`,其他实例正在领取(已加锁)的记录直接跳过
+    pub async fn claim(&self, batch_size: u32, reserved: u32) -> Result<Vec<User>> {
+        let mut normal = vec![];
+        if reserved > 0 {
+            normal = self.claim_lane(reserved.min(batch_size), true).await?;
+        }
+        let rest = batch_size.saturating_sub(normal.len() as u32);
+        let mut users = match rest {
+            0 => vec![],
+            _ => self.claim_lane(rest, false).await?
+        };
+        users.append(&mut normal);
+        Ok(users)
+    }
+    /// 按优先级领取记录,`normal_only`时只领取普通优先级的记录
+    async fn claim_lane(&self, limit: u32, normal_only: bool) -> Result<Vec<User>> {
+        let lane = match normal_only {
+            true => format!("AND {} = {}", self.priority_expr, crate::priority::NORMAL),
+            false => String::new()
+        };
+        let mut sql = Sql::new(format!(
+            "WITH lane AS (
+                SELECT TOP(@P1) * FROM sendmsg_outbox WITH (READPAST, UPDLOCK, ROWLOCK)
+                WHERE status IN {sources}
+                    AND (status = 'queued' OR lease_expires_at IS NULL OR lease_expires_at < GETDATE()) {lane}
+                ORDER BY {order} DESC, id
+            )
+            UPDATE lane
+            SET status = 'claimed', claimed_at = GETDATE(), claimed_by = @P2,
+                lease_expires_at = DATEADD(SECOND, @P3, GETDATE())
+            {history}
+            OUTPUT inserted.*",
+            sources = sources(Status::Claimed),
+            lane = lane,
+            order = self.order_expr,
+            history = self
+                .history_output("CASE deleted.status WHEN 'queued' THEN N'claim' ELSE N'lease expired' END")
+        ));
+        sql.bind(limit as i32);
+        sql.bind(self.instance_id.clone());
+        sql.bind(self.lease.as_secs() as i32);
+        let conn = self.pool.get().await?;
+        let mut users: Vec<User> = conn.query_collect(sql).await?;
+        //OUTPUT不保证顺序
+        users.sort_by_key(|user| (std::cmp::Reverse(self.priority(user)), user.id));
+        Ok(users)
+    }
+    /// 记录的实际优先级
+    pub fn priority(&self, user: &User) -> u8 {
+        user.priority.unwrap_or_else(|| self.priorities.level(&user.flowmsgtype))
+    }
+    /// 读取待发送记录(不领取),用于试运行
+    pub async fn peek(&self, limit: u32) -> Result<Vec<User>> {
+        let mut sql = Sql::new(format!(
+            "SELECT TOP(@P1) * FROM sendmsg_outbox WHERE status = 'queued' ORDER BY {} DESC, id",
+            self.order_expr
+        ));
+        sql.bind(limit as i32);
+        let conn = self.pool.get().await?;
+        let users = conn.query_collect(sql).await?;
+        Ok(users)
+    }
+    /// 读取单条记录
+    pub async fn get(&self, id: i64) -> Result<Option<User>> {
+        let conn = self.pool.get().await?;
+        let user = conn.query_first(sql_bind!("SELECT * FROM sendmsg_outbox WHERE id = @P1", id)).await?;
+        Ok(user)
+    }
+    /// 待发送记录数
+    pub async fn depth(&self) -> Result<i64> {
+        let conn = self.pool.get().await?;
+        let depth =
+            conn.query_scalar_i32("SELECT COUNT(*) FROM sendmsg_outbox WHERE status = 'queued'").await?;
+        Ok(depth.unwrap_or_default() as i64)
+    }
+    /// 最早待发送记录的等待时间(sec),没有待发送记录时返回0
+    pub async fn lag(&self) -> Result<i64> {
+        let conn = self.pool.get().await?;
+        let lag = conn
+            .query_scalar_i32(
+                "SELECT DATEDIFF(SECOND, MIN(created_at), GETDATE()) FROM sendmsg_outbox WHERE status = 'queued'"
+            )
+            .await?;
+        Ok(lag.unwrap_or_default() as i64)
+    }
+    /// 续期本实例领取的记录
+    ///
+    /// 返回续期的记录数
+    pub async fn renew(&self, ids: &[i64]) -> Result<u64> {
+        if ids.is_empty() {
+            return Ok(0);
+        }
+        let conn = self.pool.get().await?;
+        let renewed = conn
+            .exec(sql_format!(
+                "UPDATE sendmsg_outbox SET lease_expires_at = DATEADD(SECOND, {}, GETDATE())
+                WHERE status IN ('claimed', 'sending') AND claimed_by = {} AND id IN {}",
+                self.lease.as_secs() as i64,
+                self.instance_id.as_str(),
+                ids
+            ))
+            .await?;
+        Ok(renewed)
+    }
+    /// 标记为正在发送并累加发送次数
+    ///
+    /// 记录已被其他实例重新领取时返回`InvalidTransition`,不应继续发送
+    pub async fn mark_sending(&self, id: i64) -> Result<()> {
+        let sql = self.transition_sql(id, Status::Sending, "sending", ", attempts = attempts + 1", true);
+        self.apply(id, Status::Sending, sql).await
+    }
+    /// 标记为已发送
+    ///
+    /// `errmsg`为切换渠道前各渠道的失败原因
+    pub async fn mark_sent(
+        &self,
+        id: i64,
+        channel: Channel,
+        process_query_key: &str,
+        errmsg: Option<&str>
+    ) -> Result<()> {
+        let cause = format!("{}: {}", channel.as_str(), process_query_key);
+        let mut sql = self.transition_sql(
+            id,
+            Status::Sent,
+            &cause,
+            ", sent_at = GETDATE(), process_query_key = LEFT(@P3, 200), channel = @P4, errmsg = LEFT(@P5, 1000),
+                lease_expires_at = NULL",
+            true
+        );
+        sql.bind(process_query_key.to_owned());
+        sql.bind(channel.as_str().to_owned());
+        sql.bind(errmsg.map(str::to_owned));
+        self.apply(id, Status::Sent, sql).await
+    }
+    /// 标记为发送失败
+    pub async fn mark_failed(&self, id: i64, channel: Channel, errmsg: &str) -> Result<()> {
+        let mut sql = self.transition_sql(
+            id,
+            Status::Failed,
+            errmsg,
+            ", channel = @P3, errmsg = LEFT(@P2, 1000), lease_expires_at = NULL",
+            true
+        );
+        sql.bind(channel.as_str().to_owned());
+        self.apply(id, Status::Failed, sql).await
+    }
+    /// 标记为无法送达
+    pub async fn mark_undeliverable(&self, id: i64, channel: Channel, reason: &str) -> Result<()> {
+        let mut sql = self.transition_sql(
+            id,
+            Status::Undeliverable,
+            reason,
+            ", channel = @P3, errmsg = LEFT(@P2, 1000), lease_expires_at = NULL",
+            true
+        );
+        sql.bind(channel.as_str().to_owned());
+        self.apply(id, Status::Undeliverable, sql).await
+    }
+    /// 标记为放弃发送(达到最大发送次数)
+    pub async fn mark_dead(&self, id: i64, channel: Channel, errmsg: &str) -> Result<()> {
+        let mut sql = self.transition_sql(
+            id,
+            Status::Dead,
+            errmsg,
+            ", channel = @P3, errmsg = LEFT(@P2, 1000), lease_expires_at = NULL",
+            true
+        );
+        sql.bind(channel.as_str().to_owned());
+        self.apply(id, Status::Dead, sql).await
+    }
+    /// 领取最近`window`内发送、`interval`内未查询过已读状态的机器人单聊消息,领取后记录查询时间
+    ///
+    /// 多个实例同时运行时每条记录每个周期只被领取一次
+    pub async fn take_unread(
+        &self,
+        limit: u32,
+        interval: time::Duration,
+        window: time::Duration
+    ) -> Result<Vec<User>> {
+        let conn = self.pool.get().await?;
+        let users = conn
+            .query_collect(sql_bind!(
+                "UPDATE TOP(@P1) sendmsg_outbox WITH (READPAST, UPDLOCK, ROWLOCK)
+                SET read_checked_at = GETDATE()
+                OUTPUT inserted.*
+                WHERE status = 'sent' AND channel = 'robot' AND flowmsgtype <> @P4 AND process_query_key IS NOT NULL
+                    AND sent_at > DATEADD(SECOND, -@P3, GETDATE())
+                    AND (read_checked_at IS NULL OR read_checked_at < DATEADD(SECOND, -@P2, GETDATE()))",
+                limit as i32,
+                interval.as_secs() as i32,
+                window.as_secs() as i32,
+                crate::card::INTERACTIVE_CARD
+            ))
+            .await?;
+        Ok(users)
+    }
+    /// 变更记录状态(已读、撤回、放弃发送、重新发送)
+    ///
+    /// 当前状态不允许变更到`to`时返回`InvalidTransition`
+    pub async fn transition(&self, id: i64, to: Status, cause: &str) -> Result<()> {
+        let set = match to {
+            Status::Claimed | Status::Sending | Status::Sent => {
+                return Err(Error::custom(format!("{}状态只能由发送器变更", to)));
+            },
+            Status::Queued => {
+                ", claimed_at = NULL, claimed_by = NULL, lease_expires_at = NULL, alerted_at = NULL"
+            },
+            _ => ", lease_expires_at = NULL"
+        };
+        let sql = self.transition_sql(id, to, cause, set, false);
+        self.apply(id, to, sql).await
+    }
+    /// 符合重新发送条件的记录数
+    pub async fn count_resend(&self, filter: &ResendFilter) -> Result<i64> {
+        filter.validate()?;
+        let mut sql = Sql::new(format!("SELECT COUNT(*) FROM sendmsg_outbox WHERE {}", resend_where(filter)));
+        bind_resend(&mut sql, filter, 0);
+        let conn = self.pool.get().await?;
+        let count = conn.query_scalar_i32(sql).await?;
+        Ok(count.unwrap_or_default() as i64)
+    }
+    /// 按ID顺序将ID大于`after`且符合条件的最多`limit`条记录重新置为待发送
+    pub async fn requeue(&self, filter: &ResendFilter, after: i64, limit: u32) -> Result<Vec<Resent>> {
+        filter.validate()?;
+        let mut sql = Sql::new(format!(
+            "WITH resend AS (
+                SELECT TOP(@P8) * FROM sendmsg_outbox WITH (READPAST, UPDLOCK, ROWLOCK)
+                WHERE {} ORDER BY id
+            )
+            UPDATE resend
+            SET status = 'queued', claimed_at = NULL, claimed_by = NULL, lease_expires_at = NULL,
+                alerted_at = NULL, errmsg = NULL
+            {}
+            OUTPUT inserted.id, inserted.flownumber, inserted.flowmsgtype, inserted.exeuser,
+                deleted.status AS from_status, deleted.errmsg",
+            resend_where(filter),
+            self.history_output("N'resend'")
+        ));
+        bind_resend(&mut sql, filter, after);
+        sql.bind(limit as i32);
+        let conn = self.pool.get().await?;
+        let mut rows: Vec<Resent> = conn.query_collect(sql).await?;
+        rows.sort_by_key(|row| row.id);
+        Ok(rows)
+    }
+    /// 记录的状态变更历史,按时间顺序
+    pub async fn history(&self, id: i64) -> Result<Vec<Transition>> {
+        let conn = self.pool.get().await?;
+        let rows = conn
+            .query_collect(sql_bind!(
+                "SELECT id, outbox_id, from_status, to_status, cause, instance,
+                    CONVERT(VARCHAR(19), created_at, 120) AS created_at
+                FROM sendmsg_outbox_history WHERE outbox_id = @P1 ORDER BY id",
+                id
+            ))
+            .await?;
+        Ok(rows)
+    }
+    /// 单条记录的状态变更语句
+    ///
+    /// `@P1`为记录ID,`@P2`为变更原因,`set`中的其他参数从`@P3`开始;
+    /// `own`时只变更本实例领取的记录
+    fn transition_sql(&self, id: i64, to: Status, cause: &str, set: &str, own: bool) -> Sql<'static> {
+        let owner = match own {
+            true => format!(" AND claimed_by = {}", self.instance_id.to_sql_string()),
+            false => String::new()
+        };
+        let mut sql = Sql::new(format!(
+            "UPDATE sendmsg_outbox SET status = '{to}'{set}
+            {history}
+            WHERE id = @P1 AND status IN {sources}{owner}",
+            to = to,
+            set = set,
+            history = self.history_output("LEFT(@P2, 1000)"),
+            sources = sources(to),
+            owner = owner
+        ));
+        sql.bind(id);
+        sql.bind(cause.to_owned());
+        sql
+    }
+    /// 执行单条记录的状态变更,未变更时返回错误
+    async fn apply(&self, id: i64, to: Status, sql: Sql<'static>) -> Result<()> {
+        let conn = self.pool.get().await?;
+        if conn.exec(sql).await? > 0 {
+            return Ok(());
+        }
+        let current = conn
+            .query_scalar_string(sql_bind!("SELECT status FROM sendmsg_outbox WHERE id = @P1", id))
+            .await?;
+        match current {
+            Some(from) => {
+                Err(Error::InvalidTransition {
+                    from,
+                    to: to.as_str()
+                })
+            },
+            None => Err(Error::custom(format!("发件箱记录不存在, #{}", id)))
+        }
+    }
+    /// 将变更写入历史表的`OUTPUT`子句,`cause`为变更原因的SQL表达式
+    fn history_output(&self, cause: &str) -> String {
+        format!(
+            "OUTPUT inserted.id, deleted.status, inserted.status, {}, {}
+                INTO sendmsg_outbox_history(outbox_id, from_status, to_status, cause, instance)",
+            cause,
+            self.instance_id.to_sql_string()
+        )
+    }
+    /// 领取未通知管理员的无法送达记录,领取后标记为已通知
+    ///
+    /// 多个实例同时运行时每条记录只被领取一次
+    pub async fn take_undeliverable(&self, limit: u32) -> Result<Vec<Undeliverable>> {
+        let conn = self.pool.get().await?;
+        let rows = conn
+            .query_collect(sql_bind!(
+                "UPDATE TOP(@P1) sendmsg_outbox WITH (READPAST, UPDLOCK, ROWLOCK)
+                SET alerted_at = GETDATE()
+                OUTPUT inserted.id, inserted.exeuser, inserted.userphone, inserted.flownumber, inserted.errmsg
+                WHERE status = 'undeliverable' AND alerted_at IS NULL",
+                limit as i32
+            ))
+            .await?;
+        Ok(rows)
+    }
+    /// 取消已通知标记(通知发送失败时)
+    pub async fn reset_alerted(&self, ids: &[i64]) -> Result<u64> {
+        if ids.is_empty() {
+            return Ok(0);
+        }
+        let conn = self.pool.get().await?;
+        let reset =
+            conn.exec(sql_format!("UPDATE sendmsg_outbox SET alerted_at = NULL WHERE id IN {}", ids)).await?;
+        Ok(reset)
+    }
+    /// 将本实例已领取但未完成的记录归还为待发送
+    ///
+    /// 返回归还的记录数
+    pub async fn release(&self, ids: &[i64]) -> Result<u64> {
+        if ids.is_empty() {
+            return Ok(0);
+        }
+        let conn = self.pool.get().await?;
+        let released = conn
+            .exec(Sql::new(format!(
+                "UPDATE sendmsg_outbox SET status = 'queued', claimed_at = NULL, claimed_by = NULL,
+                    lease_expires_at = NULL
+                {}
+                WHERE status = 'claimed' AND claimed_by = {} AND id IN {}",
+                self.history_output("N'release'"),
+                self.instance_id.to_sql_string(),
+                ids.to_sql_string()
+            )))
+            .await?;
+        Ok(released)
+    }
+    /// 将本实例发送中被中止的记录标记为失败
+    ///
+    /// 中止时消息可能已送达,标记为失败后不再因租约到期被自动重发,需人工确认后重新发送
+    ///
+    /// 返回标记的记录数
+    pub async fn abort(&self, ids: &[i64], errmsg: &str) -> Result<u64> {
+        if ids.is_empty() {
+            return Ok(0);
+        }
+        let mut sql = Sql::new(format!(
+            "UPDATE sendmsg_outbox SET status = 'failed', errmsg = LEFT(@P1, 1000), lease_expires_at = NULL
+            {}
+            WHERE status = 'sending' AND claimed_by = {} AND id IN {}",
+            self.history_output("LEFT(@P1, 1000)"),
+            self.instance_id.to_sql_string(),
+            ids.to_sql_string()
+        ));
+        sql.bind(errmsg.to_owned());
+        let conn = self.pool.get().await?;
+        Ok(conn.exec(sql).await?)
+    }
+}
+
+/// 允许变更到`to`的状态(SQL列表)
+fn sources(to: Status) -> String {
+    Status::sources(to).iter().map(Status::as_str).collect::<Vec<_>>().to_sql_string()
+}
+
+/// 重新发送条件,参数为`@P1`-`@P7`
+fn resend_where(filter: &ResendFilter) -> String {
+    //只允许可重新发送的状态
+    let statuses: Vec<&str> =
+        filter.statuses.iter().filter(|v| v.can_transition(Status::Queued)).map(Status::as_str).collect();
+    format!(
+        "status IN {} AND id > @P1
+        AND (@P2 IS NULL OR created_at >= @P2) AND (@P3 IS NULL OR created_at < @P3)
+        AND (@P4 IS NULL OR flowmsgtype = @P4) AND (@P5 IS NULL OR errmsg LIKE @P5)
+        AND (@P6 IS NULL OR robotcode = @P6) AND (@P7 IS NULL OR exeuser = @P7)",
+        statuses.to_sql_string()
+    )
+}
+
+/// 绑定重新发送条件的参数
+fn bind_resend(sql: &mut Sql<'static>, filter: &ResendFilter, after: i64) {
+    sql.bind(after);
+    sql.bind(filter.since);
+    sql.bind(filter.until);
+    sql.bind(filter.flowmsgtype.clone());
+    sql.bind(filter.errcode_pattern());
+    sql.bind(filter.robotcode.clone());
+    sql.bind(filter.exeuser.clone());
+}
@@ -0,0 +1,85 @@
+//!
+//! 手机号规范化
+//!
+//! 支持以下格式,规范化后中国大陆手机号为11位数字,港澳台手机号为`+区号-号码`:
+//!
+//! - `13800000000`/`138 0000 0000`/`138-0000-0000`
+//! - `+8613800000000`/`008613800000000`/`8613800000000`/`+86 138 0000 0000`
+//! - `+85261234567`/`+852-6123-4567`
+//!
+//! 固定电话与其他格式在调用钉钉接口前即被拒绝
+//!
+
+use crate::{Error, Result};
+
+/// 支持的港澳台区号
+const REGION_CODES: &[&str] = &["852", "853", "886"];
+
+/// 一条记录中多个手机号的分隔符
+const SEPARATORS: &[char] = &[',', ';', '，', '；', '|', '/'];
+
+/// 规范化手机号
+pub fn normalize(phone: &str) -> Result<String> {
+    let invalid = |reason| {
+        Err(Error::InvalidPhone {
+            phone: phone.to_owned(),
+            reason
+        })
+    };
+    let trimmed = phone.trim();
+    let international = trimmed.starts_with('+') || trimmed.starts_with("00");
+    let mut digits = String::with_capacity(trimmed.len());
+    for (idx, c) in trimmed.chars().enumerate() {
+        match c {
+            '0'..='9' => digits.push(c),
+            '+' if idx == 0 => {},
+            ' ' | '-' | '(' | ')' | '.' | '\u{3000}' => {},
+            _ => return invalid("包含非法字符")
+        }
+    }
+    if digits.is_empty() {
+        return invalid("号码为空");
+    }
+    let national = if trimmed.starts_with("00") {
+        &digits[2..]
+    } else {
+        digits.as_str()
+    };
+    if international || (national.len() == 13 && national.starts_with("861")) {
+        if let Some(number) = national.strip_prefix("86") {
+            return mainland(number).map_or_else(|| invalid("不是有效的中国大陆手机号"), Ok);
+        }
+        return match REGION_CODES.iter().find(|code| national.starts_with(*code)) {
+            Some(code) => {
+                let number = &national[code.len()..];
+                //港澳8位,台湾9位(09开头时去掉0)
+                let number = number.strip_prefix('0').filter(|_| *code == "886").unwrap_or(number);
+                if (8..=9).contains(&number.len()) {
+                    Ok(format!("+{}-{}", code, number))
+                } else {
+                    invalid("号码长度不正确")
+                }
+            },
+            None => invalid("不支持的国家或地区区号")
+        };
+    }
+    if national.starts_with('0') {
+        return invalid("不支持固定电话");
+    }
+    mainland(national).map_or_else(|| invalid("不是有效的中国大陆手机号"), Ok)
+}
+
+/// 中国大陆手机号: 11位, 以13~19开头
+fn mainland(number: &str) -> Option<String> {
+    let bytes = number.as_bytes();
+    if bytes.len() == 11 && bytes[0] == b'1' && (b'3'..=b'9').contains(&bytes[1]) {
+        Some(number.to_owned())
+    } else {
+        None
+    }
+}
+
+/// 拆分一条记录中的多个手机号
+pub fn split(phones: &str) -> Vec<&str> {
+    phones.split(SEPARATORS).map(str::trim).filter(|v| !v.is_empty()).collect()
+}
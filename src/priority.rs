@@ -0,0 +1,104 @@
+//!
+//! 消息优先级
+//!
+//! 记录的优先级取`priority`字段(调用方指定),为空时取消息类型配置的默认优先级,均未指定时为普通优先级(0)。
+//! 领取时先领取高优先级的记录,并按比例为普通优先级保留名额,避免高优先级记录积压时普通消息长期得不到发送;
+//! 排序时记录的优先级随等待时间提升,避免持续有高优先级记录时中间优先级长期得不到发送
+//!
+
+use crate::{config::PriorityConfig, Error, Result};
+use mssql::ToSqlString;
+use std::collections::HashMap;
+
+/// 普通优先级
+pub const NORMAL: u8 = 0;
+/// 最高优先级
+pub const MAX: u8 = 9;
+
+/// 消息类型的默认优先级
+#[derive(Debug, Clone, Default)]
+pub struct Priorities {
+    levels: HashMap<String, u8>,
+    reserved_share: f64,
+    aging: u64
+}
+
+impl Priorities {
+    pub fn new(cfg: &PriorityConfig) -> Result<Priorities> {
+        if let Some((flowmsgtype, level)) = cfg.levels.iter().find(|(_, level)| **level > MAX) {
+            return Err(Error::config(format!(
+                "无效的优先级, {}: {}, 取值范围0-{}",
+                flowmsgtype, level, MAX
+            )));
+        }
+        if !(0.0..1.0).contains(&cfg.reserved_share) {
+            return Err(Error::config(format!("无效的保留比例, {}, 取值范围[0, 1)", cfg.reserved_share)));
+        }
+        Ok(Priorities {
+            levels: cfg.levels.clone(),
+            reserved_share: cfg.reserved_share,
+            aging: cfg.aging
+        })
+    }
+    /// 消息类型的默认优先级
+    pub fn level(&self, flowmsgtype: &str) -> u8 { self.levels.get(flowmsgtype).copied().unwrap_or(NORMAL) }
+    /// 为普通优先级保留的名额比例
+    pub fn reserved_share(&self) -> f64 { self.reserved_share }
+    /// 等待`waited`秒的记录领取排序时的优先级
+    pub fn aged(&self, level: u8, waited: u64) -> u64 {
+        match self.aging {
+            0 => level as u64,
+            aging => level as u64 + waited / aging
+        }
+    }
+    /// 领取排序的SQL表达式,与[`aged`](Self::aged)一致
+    pub fn order_expr(&self) -> String {
+        match self.aging {
+            0 => self.sql_expr(),
+            aging => format!("({} + DATEDIFF(SECOND, created_at, GETDATE()) / {})", self.sql_expr(), aging)
+        }
+    }
+    /// 记录实际优先级的SQL表达式
+    pub fn sql_expr(&self) -> String {
+        if self.levels.is_empty() {
+            return format!("COALESCE(priority, {})", NORMAL);
+        }
+        //按消息类型排序,保证生成的SQL稳定
+        let mut levels: Vec<_> = self.levels.iter().collect();
+        levels.sort();
+        let cases: Vec<String> = levels
+            .into_iter()
+            .map(|(flowmsgtype, level)| format!("WHEN {} THEN {}", flowmsgtype.to_sql_string(), level))
+            .collect();
+        format!("COALESCE(priority, CASE flowmsgtype {} ELSE {} END)", cases.join(" "), NORMAL)
+    }
+}
+
+/// 领取名额分配
+///
+/// 每次领取按比例累计普通优先级的保留名额,不足一条的部分累计到后续领取,
+/// 保证每次只能领取少量记录时普通优先级仍能按比例得到名额
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    share: f64,
+    credit: f64
+}
+
+impl Scheduler {
+    pub fn new(share: f64) -> Scheduler {
+        Scheduler {
+            share: share.clamp(0.0, 1.0),
+            credit: 0.0
+        }
+    }
+    /// 领取`n`条记录时为普通优先级保留的名额
+    ///
+    /// 普通优先级没有待发送记录时,保留的名额由高优先级记录使用
+    pub fn reserve(&mut self, n: u32) -> u32 {
+        self.credit += n as f64 * self.share;
+        //累计误差
+        let reserved = ((self.credit + 1e-9).floor() as u32).min(n);
+        self.credit -= reserved as f64;
+        reserved
+    }
+}
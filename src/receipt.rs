@@ -0,0 +1,78 @@
+//!
+//! 已读回执
+//!
+//! 定期查询最近发送的机器人单聊消息的已读状态,所有接收人均已读时标记为已读(`sent` → `read`)。
+//! 每条记录每个周期只查询一次,多个实例同时运行时由领取到的实例查询
+//!
+
+use crate::{
+    config::SenderConfig, outbox::Outbox, sender::Delivery, shutdown::Shutdown, status::Status, Result
+};
+use arc_swap::ArcSwap;
+use std::sync::Arc;
+use tokio::time;
+
+/// 每个周期最多查询的记录数
+const READ_BATCH: u32 = 200;
+
+/// 已读回执
+pub struct ReadTracker {
+    outbox: Outbox,
+    delivery: Arc<ArcSwap<Delivery>>,
+    cfg: SenderConfig
+}
+
+impl ReadTracker {
+    pub fn new(outbox: Outbox, delivery: Arc<ArcSwap<Delivery>>, cfg: SenderConfig) -> ReadTracker {
+        ReadTracker {
+            outbox,
+            delivery,
+            cfg
+        }
+    }
+
+    /// 定期查询直到收到停机信号
+    pub async fn run(&self, mut shutdown: Shutdown) {
+        let mut ticker = time::interval(self.cfg.read_interval());
+        ticker.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = shutdown.wait() => break,
+                _ = ticker.tick() => {}
+            }
+            match self.poll().await {
+                Ok(0) => {},
+                Ok(count) => info!("{} messages read", count),
+                Err(e) => warn!("poll read status error: {}", e)
+            }
+        }
+    }
+
+    /// 查询一次,返回标记为已读的记录数
+    pub async fn poll(&self) -> Result<usize> {
+        let users = self
+            .outbox
+            .take_unread(
+                READ_BATCH,
+                self.cfg.read_interval(),
+                time::Duration::from_secs(self.cfg.read_window)
+            )
+            .await?;
+        let delivery = self.delivery.load_full();
+        let mut count = 0;
+        for user in users.iter() {
+            match delivery.read_status(user).await {
+                Ok((read, total)) if total > 0 && read == total => {
+                    let cause = format!("read by {}", total);
+                    match self.outbox.transition(user.id, Status::Read, &cause).await {
+                        Ok(()) => count += 1,
+                        Err(e) => warn!("mark #{} read error: {}", user.id, e)
+                    }
+                },
+                Ok(_) => {},
+                Err(e) => warn!("get read status of #{} error: {}", user.id, e)
+            }
+        }
+        Ok(count)
+    }
+}
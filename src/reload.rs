@@ -0,0 +1,130 @@
+//!
+//! 配置热加载
+//!
+//! 监听配置文件与模板目录,变更后重新加载并检查配置,检查通过后整体替换发送器使用的
+//! 钉钉应用、卡片、消息模板与渠道配置,在途消息继续使用替换前的配置;
+//! 加载失败时保留当前配置并记录原因。
+//!
+//! 数据库、HTTP客户端、发送器、审计、告警与HTTP服务配置修改后需重启生效
+//!
+
+use crate::{
+    config::ReloadConfig, dingtalk::DDApi, http::HttpClient, sender::Delivery, shutdown::Shutdown, Config, Error, Result
+};
+use arc_swap::ArcSwap;
+use notify::{Event, RecursiveMode, Watcher};
+use std::{
+    fs, path::{Path, PathBuf}, sync::Arc
+};
+use tokio::{sync::mpsc, time};
+
+/// 配置热加载
+pub struct Reloader {
+    /// 配置文件
+    path: PathBuf,
+    api: DDApi,
+    http: HttpClient,
+    delivery: Arc<ArcSwap<Delivery>>,
+    cfg: ReloadConfig
+}
+
+impl Reloader {
+    pub fn new(
+        path: impl Into<PathBuf>,
+        api: DDApi,
+        http: HttpClient,
+        delivery: Arc<ArcSwap<Delivery>>,
+        cfg: ReloadConfig
+    ) -> Reloader {
+        Reloader {
+            path: path.into(),
+            api,
+            http,
+            delivery,
+            cfg
+        }
+    }
+
+    /// 重新加载配置,检查通过后替换当前配置
+    ///
+    /// 失败时保留当前配置
+    pub fn reload(&self) -> Result<Config> {
+        let cfg = Config::load(&self.path)?;
+        let delivery = Delivery::from_config(&cfg, &self.api, self.http.clone())?;
+        self.delivery.store(Arc::new(delivery));
+        Ok(cfg)
+    }
+
+    /// 监听配置文件与模板目录直到收到停机信号
+    ///
+    /// `template_dir`为当前配置的模板目录
+    pub async fn run(&self, template_dir: Option<&str>, mut shutdown: Shutdown) -> Result<()> {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |ev: notify::Result<Event>| {
+            let _ = tx.send(ev);
+        })
+        .map_err(|e| Error::custom(format!("监听配置文件失败, {}", e)))?;
+        //NOTE 编辑器通常以替换文件的方式保存,监听所在目录
+        let config_file = fs::canonicalize(&self.path)?;
+        let config_dir = config_file.parent().map(Path::to_path_buf).unwrap_or_default();
+        watcher
+            .watch(&config_dir, RecursiveMode::NonRecursive)
+            .map_err(|e| Error::custom(format!("监听配置文件失败, {}", e)))?;
+        let mut template_dir = template_dir.and_then(|dir| watch_dir(&mut watcher, dir));
+        info!("watching {} for changes", config_file.display());
+
+        loop {
+            let ev = tokio::select! {
+                _ = shutdown.wait() => return Ok(()),
+                ev = rx.recv() => ev
+            };
+            let ev = match ev {
+                Some(Ok(ev)) => ev,
+                Some(Err(e)) => {
+                    warn!("watch config error: {}", e);
+                    continue;
+                },
+                None => return Ok(())
+            };
+            let changed = !ev.kind.is_access() &&
+                ev.paths.iter().any(|path| {
+                    path.file_name() == config_file.file_name() ||
+                        template_dir.as_ref().is_some_and(|dir| path.starts_with(dir))
+                });
+            if !changed {
+                continue;
+            }
+            //合并连续变更
+            while let Ok(Some(_)) = time::timeout(self.cfg.debounce(), rx.recv()).await {}
+
+            match self.reload() {
+                Ok(cfg) => {
+                    info!("config reloaded");
+                    //模板目录变更时重新监听
+                    let dir = cfg.templates.dir.filter(|v| !v.is_empty());
+                    if dir.as_ref().and_then(|v| fs::canonicalize(v).ok()) != template_dir {
+                        if let Some(current) = template_dir.take() {
+                            let _ = watcher.unwatch(&current);
+                        }
+                        template_dir = dir.and_then(|dir| watch_dir(&mut watcher, &dir));
+                    }
+                },
+                Err(e) => error!("reload config failed, keeping current config: {}", e)
+            }
+        }
+    }
+}
+
+/// 监听模板目录,失败时记录原因
+fn watch_dir(watcher: &mut impl Watcher, dir: &str) -> Option<PathBuf> {
+    let rv = fs::canonicalize(dir).map_err(|e| e.to_string()).and_then(|dir| {
+        watcher.watch(&dir, RecursiveMode::NonRecursive).map(|_| dir).map_err(|e| e.to_string())
+    });
+    match rv {
+        Ok(dir) => Some(dir),
+        Err(e) => {
+            warn!("watch template dir {} error: {}", dir, e);
+            None
+        }
+    }
+}
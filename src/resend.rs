@@ -0,0 +1,150 @@
+//!
+//! 批量重新发送
+//!
+//! 按条件将发送失败的记录重新置为待发送(`failed`/`undeliverable` → `queued`),
+//! 按记录ID顺序分批执行并限制每秒的记录数,每条重新发送的记录输出一行JSON报告。
+//! 每次执行中每条记录最多重新发送一次
+//!
+
+use crate::{outbox::Outbox, status::Status, Error, Result};
+use mssql::prelude::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, io::Write};
+use tokio::time;
+
+/// 重新发送条件
+#[derive(Debug, Clone)]
+pub struct ResendFilter {
+    /// 写入时间起(含)
+    pub since: Option<NaiveDateTime>,
+    /// 写入时间止(不含)
+    pub until: Option<NaiveDateTime>,
+    pub flowmsgtype: Option<String>,
+    /// 失败原因中的错误码(旧版接口`errcode`或新版接口`code`)
+    pub errcode: Option<String>,
+    pub robotcode: Option<String>,
+    pub exeuser: Option<String>,
+    /// 记录状态
+    pub statuses: Vec<Status>
+}
+
+impl Default for ResendFilter {
+    fn default() -> Self {
+        ResendFilter {
+            since: None,
+            until: None,
+            flowmsgtype: None,
+            errcode: None,
+            robotcode: None,
+            exeuser: None,
+            statuses: vec![Status::Failed]
+        }
+    }
+}
+
+impl ResendFilter {
+    /// 校验条件
+    pub fn validate(&self) -> Result<()> {
+        if self.statuses.is_empty() {
+            return Err(Error::Invalid("未指定记录状态".to_owned()));
+        }
+        if let Some(status) =
+            self.statuses.iter().find(|v| !matches!(v, Status::Failed | Status::Undeliverable))
+        {
+            return Err(Error::Invalid(format!("只能重新发送failed/undeliverable状态的记录, {}", status)));
+        }
+        if let (Some(since), Some(until)) = (self.since, self.until) {
+            if since >= until {
+                return Err(Error::Invalid("开始时间须早于结束时间".to_owned()));
+            }
+        }
+        Ok(())
+    }
+    /// 错误码匹配`errmsg`的`LIKE`模式
+    pub fn errcode_pattern(&self) -> Option<String> {
+        self.errcode.as_deref().map(|code| {
+            let escaped: String = code
+                .chars()
+                .map(|c| {
+                    match c {
+                        '%' | '_' | '[' => format!("[{}]", c),
+                        _ => c.to_string()
+                    }
+                })
+                .collect();
+            format!("%{}%", escaped)
+        })
+    }
+}
+
+/// 重新发送的记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Resent {
+    pub id: i64,
+    pub flownumber: String,
+    pub flowmsgtype: String,
+    pub exeuser: String,
+    /// 重新发送前的状态
+    pub from_status: String,
+    /// 重新发送前的失败原因
+    pub errmsg: Option<String>
+}
+
+/// 重新发送报告
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ResendReport {
+    pub total: usize,
+    /// 按消息类型统计
+    pub flowmsgtypes: BTreeMap<String, usize>
+}
+
+/// 按条件重新发送,每秒最多`rate`条,最多`limit`条
+///
+/// 每条记录输出一行JSON
+pub async fn resend(
+    outbox: &Outbox,
+    filter: &ResendFilter,
+    rate: u32,
+    limit: Option<u32>,
+    out: &mut impl Write
+) -> Result<ResendReport> {
+    filter.validate()?;
+    let mut report = ResendReport::default();
+    let mut after = 0;
+    loop {
+        let started = time::Instant::now();
+        let batch = match limit {
+            Some(limit) => rate.max(1).min(limit.saturating_sub(report.total as u32)),
+            None => rate.max(1)
+        };
+        if batch == 0 {
+            break;
+        }
+        let rows = outbox.requeue(filter, after, batch).await?;
+        for row in rows.iter() {
+            writeln!(out, "{}", serde_json::to_string(row)?)?;
+            *report.flowmsgtypes.entry(row.flowmsgtype.clone()).or_default() += 1;
+            after = after.max(row.id);
+        }
+        out.flush()?;
+        report.total += rows.len();
+        if rows.len() < batch as usize {
+            break;
+        }
+        time::sleep_until(started + time::Duration::from_secs(1)).await;
+    }
+    Ok(report)
+}
+
+/// 解析命令行中的时间,支持`2024-05-25 08:00:00`、`2024-05-25 08:00`、`2024-05-25`
+pub fn parse_time(s: &str) -> std::result::Result<NaiveDateTime, String> {
+    let s = s.trim();
+    for fmt in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M:%S"] {
+        if let Ok(v) = NaiveDateTime::parse_from_str(s, fmt) {
+            return Ok(v);
+        }
+    }
+    mssql::prelude::NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map(|v| v.and_time(Default::default()))
+        .map_err(|_| format!("无效的时间, {}", s))
+}
@@ -0,0 +1,99 @@
+//!
+//! 配置中的密钥值
+//!
+//! 支持以下格式:
+//!
+//! - `enc:<base64>` AES-256-GCM加密的值,使用环境变量`SENDMSG_SECRET_KEY`中的密钥解密
+//! - `env:<NAME>` 读取环境变量
+//! - `secret://file/<path>` 读取文件内容(绝对路径),如`secret://file/run/secrets/appsecret`
+//! - 其他值按明文处理
+//!
+
+use crate::{Error, Result};
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng}, Aes256Gcm, Key, Nonce
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use std::{env, fs};
+
+/// 密钥环境变量
+pub const SECRET_KEY_ENV: &str = "SENDMSG_SECRET_KEY";
+
+const ENC_PREFIX: &str = "enc:";
+const ENV_PREFIX: &str = "env:";
+const FILE_PREFIX: &str = "secret://file";
+/// AES-GCM nonce长度
+const NONCE_LEN: usize = 12;
+
+/// 加密密钥
+pub struct SecretKey(Key<Aes256Gcm>);
+
+impl SecretKey {
+    /// 生成随机密钥
+    pub fn generate() -> SecretKey { SecretKey(Aes256Gcm::generate_key(OsRng)) }
+    /// 从base64解析密钥
+    pub fn from_base64(text: &str) -> Result<SecretKey> {
+        let bytes = BASE64.decode(text.trim()).map_err(|e| Error::config(format!("无效的密钥, {}", e)))?;
+        if bytes.len() != 32 {
+            return Err(Error::config("无效的密钥, 长度必须为32字节"));
+        }
+        Ok(SecretKey(*Key::<Aes256Gcm>::from_slice(&bytes)))
+    }
+    /// 从环境变量读取密钥,未设置时返回`None`
+    pub fn from_env() -> Result<Option<SecretKey>> {
+        match env::var(SECRET_KEY_ENV) {
+            Ok(v) if !v.trim().is_empty() => SecretKey::from_base64(&v).map(Some),
+            _ => Ok(None)
+        }
+    }
+    pub fn to_base64(&self) -> String { BASE64.encode(self.0) }
+}
+
+/// 加密明文,返回`enc:`格式的值
+pub fn encrypt(key: &SecretKey, plain: &str) -> Result<String> {
+    let cipher = Aes256Gcm::new(&key.0);
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let encrypted =
+        cipher.encrypt(&nonce, plain.as_bytes()).map_err(|e| Error::custom(format!("加密失败, {}", e)))?;
+    let mut blob = nonce.to_vec();
+    blob.extend_from_slice(&encrypted);
+    Ok(format!("{}{}", ENC_PREFIX, BASE64.encode(blob)))
+}
+
+/// 解密`enc:`格式的值
+pub fn decrypt(key: &SecretKey, value: &str) -> Result<String> {
+    let blob = value.strip_prefix(ENC_PREFIX).unwrap_or(value);
+    let blob = BASE64.decode(blob.trim()).map_err(|e| Error::config(format!("无效的加密值, {}", e)))?;
+    if blob.len() <= NONCE_LEN {
+        return Err(Error::config("无效的加密值"));
+    }
+    let (nonce, encrypted) = blob.split_at(NONCE_LEN);
+    let plain = Aes256Gcm::new(&key.0)
+        .decrypt(Nonce::from_slice(nonce), encrypted)
+        .map_err(|_| Error::config("解密失败, 密钥不匹配或加密值已损坏"))?;
+    String::from_utf8(plain).map_err(|_| Error::config("解密失败, 明文不是有效的UTF-8"))
+}
+
+/// 是否为需要解析的密钥引用
+pub fn is_reference(value: &str) -> bool {
+    value.starts_with(ENC_PREFIX) || value.starts_with(ENV_PREFIX) || value.starts_with(FILE_PREFIX)
+}
+
+/// 解析配置值
+///
+/// `key`仅在解析`enc:`格式的值时需要
+pub fn resolve(value: &str, key: Option<&SecretKey>) -> Result<String> {
+    if value.starts_with(ENC_PREFIX) {
+        let key = key
+            .ok_or_else(|| Error::config(format!("存在加密的配置值, 但未设置环境变量{}", SECRET_KEY_ENV)))?;
+        decrypt(key, value)
+    } else if let Some(name) = value.strip_prefix(ENV_PREFIX) {
+        env::var(name).map_err(|_| Error::config(format!("环境变量{}未设置", name)))
+    } else if let Some(path) = value.strip_prefix(FILE_PREFIX) {
+        fs::read_to_string(path)
+            .map(|v| v.trim_end_matches(['\r', '\n']).to_owned())
+            .map_err(|e| Error::config(format!("读取密钥文件失败, path: {}, {}", path, e)))
+    } else {
+        Ok(value.to_owned())
+    }
+}
@@ -0,0 +1,615 @@
+//!
+//! 消息发送器
+//!
+//! 定时从发件箱领取记录并发送,并为在途记录续期租约,
+//! 停机时停止领取,等待在途消息完成并归还未完成的记录。
+//! 每次领取优先领取高优先级的记录,并按比例为普通优先级保留名额。
+//! 每条记录按消息类型对应的渠道链发送,当前渠道失败且满足切换条件时切换到下一渠道。
+//! 试运行时只读取待发送记录并输出将要发送的请求
+//!
+
+use crate::{
+    app::{AppRegistry, DDApp}, audit::{self, AuditContext}, card::{self, ActionCard}, channel::{self, Channels}, config::{Channel, SenderConfig}, dingtalk::{
+        ApiVersion, DDApi, DDCardData, DDInteractiveCard, DDRobotMsg, DDRobotReadStatus, DDRobotRecall, DDWorkNotice
+    }, http::HttpClient, outbox::Outbox, phone, priority::Scheduler, shutdown::Shutdown, systemd, template::Templates, Config, Error, Result, User
+};
+use arc_swap::ArcSwap;
+use serde_json::{json, Value};
+use std::{
+    borrow::Cow, collections::HashSet, io::Write, sync::{Arc, Mutex}
+};
+use tokio::{task::JoinSet, time};
+
+/// 批量发送单聊消息的最大接收人数
+const MAX_RECIPIENTS: usize = 20;
+
+/// 在途记录ID
+#[derive(Clone, Default)]
+struct InFlight(Arc<Mutex<HashSet<i64>>>);
+
+impl InFlight {
+    fn insert(&self, id: i64) { self.0.lock().unwrap().insert(id); }
+    fn remove(&self, id: i64) { self.0.lock().unwrap().remove(&id); }
+    fn ids(&self) -> Vec<i64> { self.0.lock().unwrap().iter().copied().collect() }
+    fn take(&self) -> Vec<i64> { self.0.lock().unwrap().drain().collect() }
+}
+
+/// 消息发送器
+pub struct Sender {
+    outbox: Outbox,
+    /// 当前投递配置,热加载时整体替换,在途消息继续使用替换前的配置
+    delivery: Arc<ArcSwap<Delivery>>,
+    cfg: SenderConfig
+}
+
+impl Sender {
+    pub fn new(outbox: Outbox, delivery: Arc<ArcSwap<Delivery>>, cfg: SenderConfig) -> Sender {
+        Sender {
+            outbox,
+            delivery,
+            cfg
+        }
+    }
+
+    /// 运行发送循环直到收到停机信号
+    pub async fn run(&self, mut shutdown: Shutdown) -> Result<()> {
+        let inflight = InFlight::default();
+        let mut tasks = JoinSet::new();
+        let mut scheduler = Scheduler::new(self.outbox.priorities().reserved_share());
+        let mut ticker = time::interval(self.cfg.poll_interval());
+        ticker.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+        //独立于领取喂狗,数据库较慢时不会因领取耗时超过watchdog超时被systemd终止
+        let mut watchdog =
+            time::interval(systemd::watchdog_timeout().map(|v| v / 2).unwrap_or(self.cfg.poll_interval()));
+        watchdog.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.wait() => break,
+                _ = watchdog.tick() => {
+                    systemd::watchdog();
+                    continue;
+                },
+                _ = ticker.tick() => {}
+            }
+            let poll = self.poll(&mut tasks, &inflight, &mut scheduler);
+            tokio::pin!(poll);
+            loop {
+                tokio::select! {
+                    _ = &mut poll => break,
+                    _ = watchdog.tick() => systemd::watchdog()
+                }
+            }
+        }
+
+        self.drain(tasks, inflight).await
+    }
+
+    /// 回收已结束的任务,续期在途记录并领取新记录
+    async fn poll(&self, tasks: &mut JoinSet<()>, inflight: &InFlight, scheduler: &mut Scheduler) {
+        while let Some(rv) = tasks.try_join_next() {
+            if let Err(e) = rv {
+                error!("send task panicked: {}", e);
+            }
+        }
+        if systemd::enabled() {
+            match self.outbox.depth().await {
+                Ok(depth) => systemd::status(&format!("queued: {}, in-flight: {}", depth, tasks.len())),
+                Err(e) => systemd::status(&format!("outbox unavailable: {}", e))
+            }
+        }
+        //在途记录续期,避免发送较慢时被其他实例重新领取
+        if let Err(e) = self.outbox.renew(&inflight.ids()).await {
+            warn!("renew outbox lease error: {}", e);
+        }
+        let free = self.cfg.concurrency.saturating_sub(tasks.len());
+        if free == 0 {
+            return;
+        }
+        let limit = self.cfg.batch_size.min(free as u32);
+        let users = match self.outbox.claim(limit, scheduler.reserve(limit)).await {
+            Ok(users) => users,
+            Err(e) => {
+                warn!("claim outbox error: {}", e);
+                return;
+            }
+        };
+        let delivery = self.delivery.load_full();
+        for user in users {
+            inflight.insert(user.id);
+            tasks.spawn(deliver(
+                self.outbox.clone(),
+                delivery.clone(),
+                user,
+                inflight.clone(),
+                self.cfg.max_attempts
+            ));
+        }
+    }
+
+    /// 试运行:预览待发送记录,不调用发送接口,也不更新发件箱状态
+    ///
+    /// 每条记录输出一行JSON,返回预览的记录数
+    pub async fn dry_run(&self, limit: u32, out: &mut impl Write) -> Result<usize> {
+        let users = self.outbox.peek(limit).await?;
+        let delivery = self.delivery.load();
+        for user in users.iter() {
+            let line = match delivery.preview(user).await {
+                Ok(v) => v,
+                Err(e) => {
+                    json!({
+                        "id": user.id,
+                        "flownumber": user.flownumber,
+                        "exeuser": user.exeuser,
+                        "error": e.to_string()
+                    })
+                }
+            };
+            writeln!(out, "{}", line)?;
+        }
+        out.flush()?;
+        Ok(users.len())
+    }
+
+    /// 等待在途消息完成,超时后中止并归还未完成的记录
+    async fn drain(&self, mut tasks: JoinSet<()>, inflight: InFlight) -> Result<()> {
+        info!("shutting down, in-flight: {}", tasks.len());
+        let drained =
+            time::timeout(self.cfg.shutdown_timeout(), async { while tasks.join_next().await.is_some() {} })
+                .await;
+        if drained.is_err() {
+            warn!("shutdown timeout, aborting {} in-flight sends", tasks.len());
+            tasks.abort_all();
+            while tasks.join_next().await.is_some() {}
+        }
+        let ids = inflight.take();
+        if !ids.is_empty() {
+            let released = self.outbox.release(&ids).await?;
+            info!("released {} claimed records", released);
+            let aborted = self.outbox.abort(&ids, "停机时中止,发送结果未知").await?;
+            if aborted > 0 {
+                warn!("marked {} aborted sends as failed, delivery unknown", aborted);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 发送单条记录并更新发件箱状态
+///
+/// 发送失败且发送次数达到`max_attempts`时放弃发送
+async fn deliver(outbox: Outbox, delivery: Arc<Delivery>, user: User, inflight: InFlight, max_attempts: u32) {
+    //租约到期后已被其他实例重新领取的记录不再发送
+    if let Err(e) = outbox.mark_sending(user.id).await {
+        warn!("skip #{} to {}: {}", user.id, user.exeuser, e);
+        inflight.remove(user.id);
+        return;
+    }
+    let outcome = renewing(
+        &outbox,
+        user.id,
+        audit::scope(AuditContext::new(&user.flownumber, &user.userphone), delivery.send(&user))
+    )
+    .await;
+    let channel = outcome.channel;
+    let recorded = match &outcome.result {
+        Ok(message_id) => {
+            let trail = Some(outcome.trail.join("; ")).filter(|v| !v.is_empty());
+            outbox.mark_sent(user.id, channel, message_id, trail.as_deref()).await
+        },
+        Err(e) if e.is_undeliverable() => {
+            warn!("#{} to {} undeliverable via {}: {}", user.id, user.exeuser, channel.as_str(), e);
+            outbox.mark_undeliverable(user.id, channel, &outcome.errmsg()).await
+        },
+        Err(e) if user.attempts + 1 >= max_attempts as i32 => {
+            error!(
+                "send #{} to {} via {} failed after {} attempts, giving up: {}",
+                user.id,
+                user.exeuser,
+                channel.as_str(),
+                user.attempts + 1,
+                e
+            );
+            outbox.mark_dead(user.id, channel, &outcome.errmsg()).await
+        },
+        Err(e) => {
+            warn!("send #{} to {} via {} failed: {}", user.id, user.exeuser, channel.as_str(), e);
+            outbox.mark_failed(user.id, channel, &outcome.errmsg()).await
+        }
+    };
+    match recorded {
+        Ok(()) => {},
+        //租约已过期并被其他实例重新领取,本次发送结果未能记录
+        Err(
+            e @ Error::InvalidTransition {
+                ..
+            }
+        ) => {
+            error!(
+                "#{} to {} was reclaimed while sending via {}, possible duplicate delivery: {}",
+                user.id,
+                user.exeuser,
+                channel.as_str(),
+                e
+            )
+        },
+        Err(e) => error!("update outbox #{} error: {}", user.id, e)
+    }
+    //NOTE 已调用发送接口的记录不再归还,避免重复发送
+    inflight.remove(user.id);
+}
+
+/// 发送期间定期续期租约,避免发送较慢时被其他实例重新领取
+async fn renewing<T>(outbox: &Outbox, id: i64, send: impl std::future::Future<Output = T>) -> T {
+    let period = (outbox.lease() / 3).max(time::Duration::from_secs(1));
+    let mut ticker = time::interval_at(time::Instant::now() + period, period);
+    tokio::pin!(send);
+    loop {
+        tokio::select! {
+            rv = &mut send => return rv,
+            _ = ticker.tick() => {
+                match outbox.renew(&[id]).await {
+                    Ok(0) => warn!("lease of #{} lost while sending", id),
+                    Ok(_) => {},
+                    Err(e) => warn!("renew outbox lease #{} error: {}", id, e)
+                }
+            }
+        }
+    }
+}
+
+/// 发送结果
+#[derive(Debug)]
+pub struct Outcome {
+    /// 最后尝试的渠道
+    pub channel: Channel,
+    /// 消息ID(机器人消息的`processQueryKey`、工作通知的任务ID等)
+    pub result: Result<String>,
+    /// 切换渠道前各渠道的失败原因
+    pub trail: Vec<String>
+}
+
+impl Outcome {
+    /// 各渠道的失败原因
+    pub fn errmsg(&self) -> String {
+        let mut trail = self.trail.clone();
+        if let Err(e) = &self.result {
+            trail.push(format!("{}: {}", self.channel.as_str(), e));
+        }
+        trail.join("; ")
+    }
+}
+
+/// 按渠道链投递消息
+pub struct Delivery {
+    apps: AppRegistry,
+    card: ActionCard,
+    templates: Templates,
+    channels: Channels,
+    /// 解析userid的并发数
+    parallelism: usize
+}
+
+impl Delivery {
+    pub fn new(
+        apps: AppRegistry,
+        card: ActionCard,
+        templates: Templates,
+        channels: Channels,
+        parallelism: usize
+    ) -> Delivery {
+        Delivery {
+            apps,
+            card,
+            templates,
+            channels,
+            parallelism
+        }
+    }
+
+    /// 按配置构建
+    pub fn from_config(cfg: &Config, api: &DDApi, http: HttpClient) -> Result<Delivery> {
+        let templates = match cfg.templates.dir.as_deref().filter(|v| !v.is_empty()) {
+            Some(dir) => Templates::load(dir)?,
+            None => Templates::default()
+        };
+        Ok(Delivery::new(
+            AppRegistry::new(api, &cfg.apps)?,
+            ActionCard::new(&cfg.card)?,
+            templates,
+            Channels::new(&cfg.failover, http)?,
+            cfg.sender.resolve_concurrency
+        ))
+    }
+
+    /// 钉钉应用
+    pub fn apps(&self) -> &AppRegistry { &self.apps }
+
+    /// 依次尝试渠道链中的渠道,错误满足切换条件时切换到下一渠道
+    pub async fn send(&self, user: &User) -> Outcome {
+        let (channels, on) = self.channels.chain(&user.flowmsgtype);
+        let user = match self.apply_template(user) {
+            Ok(user) => user,
+            Err(e) => {
+                return Outcome {
+                    channel: channels[0],
+                    result: Err(e),
+                    trail: vec![]
+                }
+            },
+        };
+        let user = user.as_ref();
+        let mut trail = vec![];
+        let mut idx = 0;
+        loop {
+            let channel = channels[idx];
+            match self.send_via(channel, user).await {
+                Err(e) if idx + 1 < channels.len() && channel::should_failover(on, &e) => {
+                    warn!(
+                        "#{} via {} failed, failover to {}: {}",
+                        user.id,
+                        channel.as_str(),
+                        channels[idx + 1].as_str(),
+                        e
+                    );
+                    trail.push(format!("{}: {}", channel.as_str(), e));
+                    idx += 1;
+                },
+                result => {
+                    return Outcome {
+                        channel,
+                        result,
+                        trail
+                    }
+                },
+            }
+        }
+    }
+
+    /// 预览消息:解析接收人并生成渠道链中第一个渠道的发送请求,但不调用发送接口
+    ///
+    /// 附件不上传,`mediaId`以`@dry-run`占位
+    pub async fn preview(&self, user: &User) -> Result<Value> {
+        let (channels, _) = self.channels.chain(&user.flowmsgtype);
+        let user = self.apply_template(user)?;
+        let user = user.as_ref();
+        let mut rv = json!({
+            "id": user.id,
+            "flownumber": user.flownumber,
+            "exeuser": user.exeuser,
+            "channels": channels.iter().map(Channel::as_str).collect::<Vec<_>>()
+        });
+        let preview = match channels[0] {
+            Channel::Robot if ActionCard::is_interactive(user) => {
+                let (app, robotcode) = self.apps.route(user)?;
+                let card = self.interactive_card(&app, robotcode, user).await?;
+                json!({ "app": app.name(), "url": card.url(), "payload": card })
+            },
+            Channel::Robot => {
+                let (app, robotcode) = self.apps.route(user)?;
+                let msg = self.robot_msg(&app, robotcode, user, true).await?;
+                json!({ "app": app.name(), "url": msg.url(), "payload": msg })
+            },
+            Channel::WorkNotice => {
+                let (app, _) = self.apps.route(user)?;
+                let notice = self.work_notice(&app, user).await?;
+                json!({ "app": app.name(), "url": notice.url(), "payload": notice })
+            },
+            Channel::Email => json!({ "to": user.email }),
+            Channel::Sms => json!({ "to": user.userphone })
+        };
+        if let (Value::Object(rv), Value::Object(preview)) = (&mut rv, preview) {
+            rv.extend(preview);
+        }
+        Ok(rv)
+    }
+
+    /// 查询机器人单聊消息的已读状态,返回(已读人数, 接收人数)
+    pub async fn read_status(&self, user: &User) -> Result<(usize, usize)> {
+        let (app, robotcode, process_query_key) = self.robot_message(user)?;
+        let rv = app
+            .call(ApiVersion::V1, |access_token| {
+                let status = DDRobotReadStatus::new(
+                    app.api(),
+                    access_token,
+                    robotcode.clone(),
+                    process_query_key.clone()
+                );
+                async move { status.get_read_status().await }
+            })
+            .await?;
+        let read = rv.message_read_info_list.iter().filter(|v| v.read_status == "READ").count();
+        Ok((read, rv.message_read_info_list.len()))
+    }
+
+    /// 撤回机器人单聊消息
+    pub async fn recall(&self, user: &User) -> Result<()> {
+        let (app, robotcode, process_query_key) = self.robot_message(user)?;
+        let mut rv = app
+            .call(ApiVersion::V1, |access_token| {
+                let recall = DDRobotRecall::new(app.api(), access_token, robotcode.clone(), vec![
+                    process_query_key.clone(),
+                ]);
+                async move { recall.recall().await }
+            })
+            .await?;
+        if rv.success_result.contains(&process_query_key) {
+            return Ok(());
+        }
+        let reason = rv.failed_result.remove(&process_query_key).unwrap_or_default();
+        Err(Error::custom(format!("撤回消息失败, #{} {}", user.id, reason)))
+    }
+
+    /// 已发送的机器人单聊消息的应用、机器人编码与`processQueryKey`
+    fn robot_message(&self, user: &User) -> Result<(Arc<DDApp>, String, String)> {
+        let process_query_key = match (user.channel.as_deref(), user.process_query_key.as_deref()) {
+            (Some("robot"), Some(key)) if !key.is_empty() && !ActionCard::is_interactive(user) => {
+                key.to_owned()
+            },
+            _ => return Err(Error::custom(format!("#{} 不是已发送的机器人单聊消息", user.id)))
+        };
+        let (app, robotcode) = self.apps.route(user)?;
+        Ok((app, robotcode, process_query_key))
+    }
+
+    /// 以消息内容模板生成消息内容
+    fn apply_template<'a>(&self, user: &'a User) -> Result<Cow<'a, User>> {
+        match self.templates.render(user)? {
+            Some(flowmsg) => {
+                let mut user = user.clone();
+                user.flowmsg = flowmsg;
+                Ok(Cow::Owned(user))
+            },
+            None => Ok(Cow::Borrowed(user))
+        }
+    }
+
+    /// 通过指定渠道发送,返回消息ID
+    async fn send_via(&self, channel: Channel, user: &User) -> Result<String> {
+        match channel {
+            Channel::Robot if ActionCard::is_interactive(user) => {
+                let (app, robotcode) = self.apps.route(user)?;
+                let card = self.interactive_card(&app, robotcode, user).await?;
+                let rv = app
+                    .call(ApiVersion::V1, |access_token| {
+                        let card = card.with_token(access_token);
+                        async move { card.send().await }
+                    })
+                    .await?;
+                Ok(rv.result.process_query_key)
+            },
+            Channel::Robot => {
+                let (app, robotcode) = self.apps.route(user)?;
+                let msg = self.robot_msg(&app, robotcode, user, false).await?;
+                let rv = app
+                    .call(ApiVersion::V1, |access_token| {
+                        let msg = msg.with_token(access_token);
+                        async move { msg.send().await }
+                    })
+                    .await?;
+                Ok(rv.process_query_key)
+            },
+            Channel::WorkNotice => {
+                let (app, _) = self.apps.route(user)?;
+                let notice = self.work_notice(&app, user).await?;
+                let task_id = app
+                    .call(ApiVersion::Legacy, |access_token| {
+                        let notice = notice.with_token(access_token);
+                        async move { notice.send().await }
+                    })
+                    .await?;
+                Ok(task_id.to_string())
+            },
+            Channel::Email => self.channels.email()?.send(user).await,
+            Channel::Sms => self.channels.sms()?.send(user).await
+        }
+    }
+
+    /// 解析接收人、生成消息参数并构建机器人单聊消息请求
+    async fn robot_msg(
+        &self,
+        app: &DDApp,
+        robotcode: String,
+        user: &User,
+        dry_run: bool
+    ) -> Result<DDRobotMsg> {
+        let userids = resolve_userids(app, user, self.parallelism).await?;
+        let access_token = app.token(ApiVersion::V1).await?;
+        let (msg_key, msg_param) = match user.media_type() {
+            Some(media_type) => {
+                let (file_name, data) = user.load_attachment().await?;
+                let media_id = if dry_run {
+                    "@dry-run".to_owned()
+                } else {
+                    app.upload_media(media_type, &file_name, data).await?
+                };
+                (user.flowmsgtype.clone(), user.media_msg_param(&media_id, &file_name))
+            },
+            None if ActionCard::is_card(user) => self.card.render(user)?,
+            None => (user.flowmsgtype.clone(), user.msg_param())
+        };
+        Ok(DDRobotMsg::new(app.api(), access_token, robotcode, userids, msg_key, msg_param))
+    }
+
+    /// 解析接收人并构建互动卡片请求
+    async fn interactive_card(
+        &self,
+        app: &DDApp,
+        robotcode: String,
+        user: &User
+    ) -> Result<DDInteractiveCard> {
+        let (cfg, params) = self.card.interactive(user)?;
+        let userids = resolve_userids(app, user, self.parallelism).await?;
+        let access_token = app.token(ApiVersion::V1).await?;
+        Ok(DDInteractiveCard::new(
+            app.api(),
+            access_token,
+            cfg.template_id.clone(),
+            card::out_track_id(user),
+            robotcode,
+            userids,
+            cfg.callback_route_key.clone(),
+            DDCardData {
+                card_param_map: params
+            }
+        ))
+    }
+
+    /// 解析接收人并构建工作通知请求
+    ///
+    /// 工作通知只发送文本内容,卡片与附件消息按markdown发送
+    async fn work_notice(&self, app: &DDApp, user: &User) -> Result<DDWorkNotice> {
+        let agent_id =
+            app.agent_id().ok_or_else(|| Error::config(format!("钉钉应用{}未配置agent_id", app.name())))?;
+        let userids = resolve_userids(app, user, self.parallelism).await?;
+        let access_token = app.token(ApiVersion::Legacy).await?;
+        let msg = match user.flowmsgtype.as_str() {
+            "sampleText" => json!({ "msgtype": "text", "text": { "content": user.flowmsg } }),
+            _ => {
+                json!({
+                    "msgtype": "markdown",
+                    "markdown": { "title": user.flownumber, "text": user.flowmsg }
+                })
+            }
+        };
+        Ok(DDWorkNotice::new(app.api(), access_token, agent_id, &userids, msg))
+    }
+}
+
+/// 解析记录中所有手机号的userid
+///
+/// 部分手机号解析失败时只发送给已解析的用户;全部失败时返回错误,
+/// 存在非无法送达的错误(如网络错误)时优先返回该错误
+async fn resolve_userids(app: &DDApp, user: &User, parallelism: usize) -> Result<Vec<String>> {
+    let mobiles = phone::split(&user.userphone);
+    if mobiles.is_empty() {
+        return Err(Error::InvalidPhone {
+            phone: user.userphone.clone(),
+            reason: "号码为空"
+        });
+    }
+    if mobiles.len() > MAX_RECIPIENTS {
+        return Err(Error::custom(format!("单条记录最多{}个接收人", MAX_RECIPIENTS)));
+    }
+    let mut userids: Vec<String> = vec![];
+    let mut error: Option<Error> = None;
+    for (mobile, rv) in mobiles.iter().zip(app.resolve_userids(&mobiles, parallelism).await?) {
+        match rv {
+            Ok(userid) => {
+                if !userids.contains(&userid) {
+                    userids.push(userid);
+                }
+            },
+            Err(e) => {
+                warn!("resolve #{} {} failed: {}", user.id, mobile, e);
+                if error.as_ref().is_none_or(|e| e.is_undeliverable()) {
+                    error = Some(e);
+                }
+            }
+        }
+    }
+    match error {
+        Some(e) if userids.is_empty() => Err(e),
+        _ => Ok(userids)
+    }
+}
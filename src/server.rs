@@ -0,0 +1,143 @@
+//!
+//! HTTP服务
+//!
+//! - `GET /healthz` 存活检查,进程正常响应即返回200
+//! - `GET /readyz` 就绪检查,所有检查通过返回200,否则返回503,响应内容为各项检查详情
+//! - `POST /messages` 写入消息(JSON),写入发件箱返回201,幂等键重复返回200,
+//!   数据库不可达写入本地缓存返回202
+//! - `POST <approval.path>` 机器人消息回调(聊天审批),签名无效返回401,回复内容为文本或markdown消息
+//! - `POST <approval.card_path>` 互动卡片按钮回调,签名无效返回401,响应内容为更新后的卡片数据
+//!
+
+use crate::{
+    approval::{Approvals, CardCallback, RobotMessage}, health::Health, ingest::{Ingest, Submitted}, outbox::NewMessage, shutdown::Shutdown, Error, Result
+};
+use axum::{
+    extract::State, http::{header::AUTHORIZATION, HeaderMap, StatusCode}, response::{IntoResponse, Response}, routing::{get, post}, Json, Router
+};
+use serde_json::json;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+/// 构建路由
+pub fn router(health: Arc<Health>) -> Router {
+    Router::new().route("/healthz", get(healthz)).route("/readyz", get(readyz)).with_state(health)
+}
+
+/// 构建消息写入路由
+///
+/// `token`不为空时校验`Authorization: Bearer <token>`
+pub fn ingest_router(ingest: Arc<Ingest>, token: Option<String>) -> Router {
+    Router::new().route("/messages", post(submit)).with_state(IngestState {
+        ingest,
+        token: token.filter(|v| !v.is_empty())
+    })
+}
+
+/// 构建机器人消息回调路由
+pub fn robot_router(approvals: Arc<Approvals>, path: &str) -> Router {
+    Router::new().route(path, post(robot)).with_state(approvals)
+}
+
+/// 构建互动卡片回调路由
+pub fn card_router(approvals: Arc<Approvals>, path: &str) -> Router {
+    Router::new().route(path, post(card)).with_state(approvals)
+}
+
+/// 监听地址
+pub async fn bind(listen: &str) -> Result<TcpListener> {
+    let listener =
+        TcpListener::bind(listen).await.map_err(|e| Error::config(format!("监听{}失败, {}", listen, e)))?;
+    info!("http server listening on {}", listener.local_addr()?);
+    Ok(listener)
+}
+
+/// 运行HTTP服务直到收到停机信号
+pub async fn serve(listener: TcpListener, router: Router, mut shutdown: Shutdown) -> Result<()> {
+    axum::serve(listener, router).with_graceful_shutdown(async move { shutdown.wait().await }).await?;
+    Ok(())
+}
+
+async fn healthz() -> impl IntoResponse { Json(json!({ "status": "ok" })) }
+
+async fn readyz(State(health): State<Arc<Health>>) -> impl IntoResponse {
+    let rv = health.ready().await;
+    if !rv.ready {
+        warn!("readiness check failed: {}", json!(rv));
+    }
+    let status = if rv.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(rv))
+}
+
+#[derive(Clone)]
+struct IngestState {
+    ingest: Arc<Ingest>,
+    token: Option<String>
+}
+
+async fn submit(
+    State(state): State<IngestState>,
+    headers: HeaderMap,
+    Json(msg): Json<NewMessage>
+) -> Response {
+    if let Some(token) = &state.token {
+        let authorized = headers
+            .get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .is_some_and(|v| v == token);
+        if !authorized {
+            return (StatusCode::UNAUTHORIZED, Json(json!({ "error": "未授权" }))).into_response();
+        }
+    }
+    match state.ingest.submit(msg).await {
+        Ok(Submitted::Queued(id)) => {
+            (StatusCode::CREATED, Json(json!({ "status": "queued", "id": id }))).into_response()
+        },
+        Ok(Submitted::Duplicate(id)) => Json(json!({ "status": "duplicate", "id": id })).into_response(),
+        Ok(Submitted::Spooled(key)) => {
+            (StatusCode::ACCEPTED, Json(json!({ "status": "spooled", "idempotency_key": key })))
+                .into_response()
+        },
+        Err(Error::Invalid(msg)) => (StatusCode::BAD_REQUEST, Json(json!({ "error": msg }))).into_response(),
+        Err(e) => {
+            error!("submit message error: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "error": e.to_string() }))).into_response()
+        }
+    }
+}
+
+async fn robot(
+    State(approvals): State<Arc<Approvals>>,
+    headers: HeaderMap,
+    Json(msg): Json<RobotMessage>
+) -> Response {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).unwrap_or_default();
+    let app = match approvals.verify(&msg.robot_code, header("timestamp"), header("sign")) {
+        Some(app) => app,
+        None => {
+            warn!("robot callback rejected, robotCode: {}", msg.robot_code);
+            return (StatusCode::UNAUTHORIZED, Json(json!({ "error": "签名无效" }))).into_response();
+        }
+    };
+    let reply = approvals.handle(&app, &msg).await;
+    Json(reply.to_json()).into_response()
+}
+
+async fn card(
+    State(approvals): State<Arc<Approvals>>,
+    headers: HeaderMap,
+    Json(cb): Json<CardCallback>
+) -> Response {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).unwrap_or_default();
+    if !approvals.verify_card(header("timestamp"), header("sign")) {
+        warn!("card callback rejected, outTrackId: {}", cb.out_track_id);
+        return (StatusCode::UNAUTHORIZED, Json(json!({ "error": "签名无效" }))).into_response();
+    }
+    let data = approvals.handle_card(&cb).await;
+    Json(json!({ "cardData": data })).into_response()
+}
@@ -0,0 +1,64 @@
+//!
+//! 停机信号
+//!
+
+use crate::{Error, Result};
+use std::future::Future;
+use tokio::sync::watch;
+
+/// 创建停机信号
+pub fn channel() -> (ShutdownTrigger, Shutdown) {
+    let (tx, rx) = watch::channel(false);
+    (ShutdownTrigger(tx), Shutdown(rx))
+}
+
+/// 停机触发器
+pub struct ShutdownTrigger(watch::Sender<bool>);
+
+impl ShutdownTrigger {
+    /// 通知所有监听者停机
+    pub fn trigger(&self) { self.0.send_replace(true); }
+    /// 创建新的监听者
+    pub fn subscribe(&self) -> Shutdown { Shutdown(self.0.subscribe()) }
+}
+
+/// 停机监听者
+#[derive(Clone)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+impl Shutdown {
+    /// 是否已触发停机
+    pub fn is_shutdown(&self) -> bool { *self.0.borrow() }
+    /// 等待停机
+    pub async fn wait(&mut self) {
+        //NOTE 触发器被释放也视为停机
+        let _ = self.0.wait_for(|v| *v).await;
+    }
+}
+
+/// 注册停机信号,返回等待`SIGTERM`/`SIGINT`的任务
+pub fn wait_signal() -> Result<impl Future<Output = ()>> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut term =
+            signal(SignalKind::terminate()).map_err(|e| Error::custom(format!("注册SIGTERM失败, {}", e)))?;
+        let mut int =
+            signal(SignalKind::interrupt()).map_err(|e| Error::custom(format!("注册SIGINT失败, {}", e)))?;
+        Ok(async move {
+            tokio::select! {
+                _ = term.recv() => info!("received SIGTERM"),
+                _ = int.recv() => info!("received SIGINT")
+            }
+        })
+    }
+    #[cfg(not(unix))]
+    {
+        let mut ctrl_c =
+            tokio::signal::windows::ctrl_c().map_err(|e| Error::custom(format!("注册Ctrl-C失败, {}", e)))?;
+        Ok(async move {
+            ctrl_c.recv().await;
+            info!("received Ctrl-C");
+        })
+    }
+}
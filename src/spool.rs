@@ -0,0 +1,134 @@
+//!
+//! 本地发件箱缓存
+//!
+//! 数据库不可达时HTTP写入的消息按顺序追加到本地文件(JSON Lines,每条写入后落盘),
+//! 数据库恢复后按写入顺序回放到发件箱,回放成功的消息从文件中移除。
+//! 数据库仍不可达时停止回放;其他原因写入失败的消息(如约束冲突、类型转换错误)及
+//! 进程异常退出时留下的不完整行移到`outbox.jsonl.rejected`并记录日志,继续回放后续消息
+//!
+
+use crate::{outbox::NewMessage, Result};
+use std::{
+    future::Future, io::ErrorKind, path::{Path, PathBuf}
+};
+use tokio::{
+    fs, io::AsyncWriteExt, sync::{Mutex, RwLock, RwLockReadGuard}
+};
+
+/// 缓存文件名
+const SPOOL_FILE: &str = "outbox.jsonl";
+
+/// 本地发件箱缓存
+pub struct Spool {
+    path: PathBuf,
+    /// 串行化写入与回放
+    lock: Mutex<()>,
+    /// 回放时独占,写入方持有期间回放等待,保证回放期间新消息不会先于缓存中的消息写入发件箱
+    gate: RwLock<()>
+}
+
+impl Spool {
+    /// 打开缓存目录(不存在时创建)
+    pub fn open(dir: impl AsRef<Path>) -> Result<Spool> {
+        std::fs::create_dir_all(dir.as_ref())?;
+        Ok(Spool {
+            path: dir.as_ref().join(SPOOL_FILE),
+            lock: Mutex::new(()),
+            gate: RwLock::new(())
+        })
+    }
+    /// 缓存文件路径
+    pub fn path(&self) -> &Path { &self.path }
+    /// 无法回放的消息文件路径
+    pub fn rejected_path(&self) -> PathBuf { self.path.with_extension("jsonl.rejected") }
+    /// 阻止回放直到释放,持有期间判断是否需写入缓存并写入发件箱或缓存
+    pub async fn hold(&self) -> RwLockReadGuard<'_, ()> { self.gate.read().await }
+    /// 是否没有待回放的消息
+    pub async fn is_empty(&self) -> bool {
+        fs::metadata(&self.path).await.map(|v| v.len() == 0).unwrap_or(true)
+    }
+    /// 待回放的消息数
+    pub async fn len(&self) -> Result<usize> {
+        let _guard = self.lock.lock().await;
+        Ok(self.read_lines().await?.len())
+    }
+    /// 追加消息并落盘
+    pub async fn append(&self, msg: &NewMessage) -> Result<()> {
+        let line = serde_json::to_string(msg)?;
+        let _guard = self.lock.lock().await;
+        append_line(&self.path, &line).await
+    }
+    /// 按写入顺序回放消息
+    ///
+    /// `f`返回数据库不可达错误时停止回放,未回放的消息保留在缓存中;
+    /// 返回其他错误的消息移到`rejected_path`。返回回放的消息数
+    pub async fn replay<F, Fut>(&self, mut f: F) -> Result<usize>
+    where
+        F: FnMut(NewMessage) -> Fut,
+        Fut: Future<Output = Result<()>>
+    {
+        let _gate = self.gate.write().await;
+        let _guard = self.lock.lock().await;
+        let lines = self.read_lines().await?;
+        let mut replayed = 0;
+        for (idx, line) in lines.iter().enumerate() {
+            let msg = match serde_json::from_str(line) {
+                Ok(msg) => msg,
+                Err(e) => {
+                    error!("reject corrupted spool entry: {}, {}", e, line);
+                    append_line(&self.rejected_path(), line).await?;
+                    continue;
+                }
+            };
+            match f(msg).await {
+                Ok(()) => replayed += 1,
+                Err(e) if e.is_db_unavailable() => {
+                    self.rewrite(&lines[idx..]).await?;
+                    return Err(e);
+                },
+                Err(e) => {
+                    error!("reject spool entry: {}, {}", e, line);
+                    append_line(&self.rejected_path(), line).await?;
+                }
+            }
+        }
+        if let Err(e) = fs::remove_file(&self.path).await {
+            if e.kind() != ErrorKind::NotFound {
+                return Err(e.into());
+            }
+        }
+        Ok(replayed)
+    }
+
+    async fn read_lines(&self) -> Result<Vec<String>> {
+        match fs::read_to_string(&self.path).await {
+            Ok(text) => Ok(text.lines().filter(|v| !v.trim().is_empty()).map(str::to_owned).collect()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(vec![]),
+            Err(e) => Err(e.into())
+        }
+    }
+
+    /// 以剩余消息替换缓存文件
+    async fn rewrite(&self, lines: &[String]) -> Result<()> {
+        let tmp = self.path.with_extension("jsonl.tmp");
+        let mut file = fs::File::create(&tmp).await?;
+        for line in lines {
+            file.write_all(line.as_bytes()).await?;
+            file.write_all(b"\n").await?;
+        }
+        file.sync_data().await?;
+        fs::rename(&tmp, &self.path).await?;
+        Ok(())
+    }
+}
+
+/// 追加一行并落盘
+async fn append_line(path: &Path, line: &str) -> Result<()> {
+    let mut buf = Vec::with_capacity(line.len() + 1);
+    buf.extend_from_slice(line.as_bytes());
+    buf.push(b'\n');
+    let mut file = fs::OpenOptions::new().create(true).append(true).open(path).await?;
+    file.write_all(&buf).await?;
+    file.sync_data().await?;
+    Ok(())
+}
@@ -0,0 +1,121 @@
+//!
+//! 发件箱记录状态
+//!
+//! ```text
+//! queued → claimed → sending → sent → read → recalled
+//!   ↑         │         │        └──────────→ recalled
+//!   └─────────┘         ├→ failed ──→ dead
+//!                       ├→ undeliverable ──→ dead
+//!                       └→ dead
+//! ```
+//!
+//! - 领取的记录租约到期后可被重新领取(`claimed`/`sending` → `claimed`)
+//! - 停机时归还未开始发送的记录(`claimed` → `queued`)
+//! - 发送失败或无法送达的记录可重新发送(`failed`/`undeliverable` → `queued`)
+//! - 发送次数达到`sender.max_attempts`后发送失败的记录放弃发送(`sending` → `dead`)
+//! - 机器人单聊消息所有接收人已读时标记为已读(`sent` → `read`),见[`crate::receipt`]
+//! - `sendmsg recall`撤回已发送的机器人单聊消息(`sent`/`read` → `recalled`)
+//! - `dead`、`recalled`为终态
+//!
+
+use crate::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// 记录状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    /// 待发送
+    Queued,
+    /// 已领取,等待发送
+    Claimed,
+    /// 正在调用发送接口
+    Sending,
+    /// 已发送
+    Sent,
+    /// 已读
+    Read,
+    /// 已撤回
+    Recalled,
+    /// 发送失败,可重新发送
+    Failed,
+    /// 无法送达(手机号无效或未匹配到钉钉账号)
+    Undeliverable,
+    /// 放弃发送
+    Dead
+}
+
+impl Status {
+    pub const ALL: [Status; 9] = [
+        Status::Queued,
+        Status::Claimed,
+        Status::Sending,
+        Status::Sent,
+        Status::Read,
+        Status::Recalled,
+        Status::Failed,
+        Status::Undeliverable,
+        Status::Dead
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Queued => "queued",
+            Status::Claimed => "claimed",
+            Status::Sending => "sending",
+            Status::Sent => "sent",
+            Status::Read => "read",
+            Status::Recalled => "recalled",
+            Status::Failed => "failed",
+            Status::Undeliverable => "undeliverable",
+            Status::Dead => "dead"
+        }
+    }
+    /// 是否允许变更到`to`
+    pub fn can_transition(self, to: Status) -> bool {
+        use Status::*;
+        matches!(
+            (self, to),
+            (Queued, Claimed) |
+                (Claimed, Claimed | Sending | Queued) |
+                (Sending, Claimed | Sent | Failed | Undeliverable | Dead) |
+                (Sent, Read | Recalled) |
+                (Read, Recalled) |
+                (Failed | Undeliverable, Queued | Dead)
+        )
+    }
+    /// 校验状态变更
+    pub fn transition(self, to: Status) -> Result<Status> {
+        match self.can_transition(to) {
+            true => Ok(to),
+            false => {
+                Err(Error::InvalidTransition {
+                    from: self.as_str().to_owned(),
+                    to: to.as_str()
+                })
+            },
+        }
+    }
+    /// 允许变更到`to`的状态
+    pub fn sources(to: Status) -> Vec<Status> {
+        Status::ALL.into_iter().filter(|from| from.can_transition(to)).collect()
+    }
+    /// 是否为终态
+    pub fn is_final(self) -> bool { Status::ALL.into_iter().all(|to| !self.can_transition(to)) }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { f.write_str(self.as_str()) }
+}
+
+impl FromStr for Status {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Status> {
+        Status::ALL
+            .into_iter()
+            .find(|v| v.as_str() == s)
+            .ok_or_else(|| Error::custom(format!("无效的记录状态, {}", s)))
+    }
+}
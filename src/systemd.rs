@@ -0,0 +1,61 @@
+//!
+//! systemd通知(`Type=notify`)
+//!
+//! - `READY=1` 连接池与access_token初始化完成
+//! - `WATCHDOG=1` 发送循环每次轮询时发送,循环卡住时由systemd重启(`WatchdogSec=`)
+//! - `STATUS=` 待发送记录数与在途记录数
+//! - `STOPPING=1` 开始停机
+//!
+//! 未设置`NOTIFY_SOCKET`(非systemd启动)或非Unix平台时所有通知均忽略
+//!
+
+#[cfg(unix)]
+use sd_notify::NotifyState;
+use std::{env, time};
+
+/// 是否由systemd启动并接收通知
+pub fn enabled() -> bool { env::var_os("NOTIFY_SOCKET").is_some() }
+
+/// 通知已就绪
+pub fn ready() {
+    #[cfg(unix)]
+    notify(&[NotifyState::Ready]);
+}
+
+/// 通知开始停机
+pub fn stopping() {
+    #[cfg(unix)]
+    notify(&[NotifyState::Stopping]);
+}
+
+/// 更新状态描述(`systemctl status`中显示)
+pub fn status(text: &str) {
+    #[cfg(unix)]
+    notify(&[NotifyState::Status(text)]);
+    #[cfg(not(unix))]
+    let _ = text;
+}
+
+/// 喂狗
+pub fn watchdog() {
+    #[cfg(unix)]
+    notify(&[NotifyState::Watchdog]);
+}
+
+/// watchdog超时时间,未启用时返回`None`
+pub fn watchdog_timeout() -> Option<time::Duration> {
+    #[cfg(unix)]
+    {
+        let mut usec = 0;
+        sd_notify::watchdog_enabled(false, &mut usec).then(|| time::Duration::from_micros(usec))
+    }
+    #[cfg(not(unix))]
+    None
+}
+
+#[cfg(unix)]
+fn notify(state: &[NotifyState]) {
+    if let Err(e) = sd_notify::notify(false, state) {
+        warn!("sd_notify error: {}", e);
+    }
+}
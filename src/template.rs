@@ -0,0 +1,70 @@
+//!
+//! 消息内容模板
+//!
+//! 模板目录中`<flowmsgtype>.md`/`<flowmsgtype>.txt`文件为对应消息类型的消息内容模板,
+//! 发送前以模板生成消息内容(替换`flowmsg`),所有渠道使用相同内容。
+//! 模板参数同卡片消息,另有`{flowmsg}`为发件箱中的原始消息内容;`params`中的参数须写作`{params.key}`,
+//! 其他参数名在加载时即视为错误,热加载时保留原模板
+//!
+
+use crate::{card, Error, Result, User};
+use std::{collections::HashMap, fs, path::Path};
+
+/// 模板文件扩展名
+const EXTENSIONS: &[&str] = &["md", "txt"];
+
+/// 消息内容模板
+#[derive(Debug, Clone, Default)]
+pub struct Templates(HashMap<String, String>);
+
+impl Templates {
+    /// 加载模板目录,检查模板格式
+    pub fn load(dir: impl AsRef<Path>) -> Result<Templates> {
+        let dir = dir.as_ref();
+        let entries = fs::read_dir(dir)
+            .map_err(|e| Error::config(format!("读取模板目录失败, path: {}, {}", dir.display(), e)))?;
+        let mut templates = HashMap::new();
+        for entry in entries {
+            let path = entry?.path();
+            let ext = path.extension().map(|v| v.to_string_lossy().to_lowercase()).unwrap_or_default();
+            if !path.is_file() || !EXTENSIONS.contains(&ext.as_str()) {
+                continue;
+            }
+            let Some(name) = path.file_stem().map(|v| v.to_string_lossy().into_owned()) else {
+                continue;
+            };
+            let text = fs::read_to_string(&path)
+                .map_err(|e| Error::config(format!("读取模板失败, path: {}, {}", path.display(), e)))?;
+            validate(&text).map_err(|e| Error::config(format!("{}, path: {}", e, path.display())))?;
+            if templates.insert(name.clone(), text).is_some() {
+                return Err(Error::config(format!("消息类型{}存在多个模板", name)));
+            }
+        }
+        Ok(Templates(templates))
+    }
+
+    /// 模板数
+    pub fn len(&self) -> usize { self.0.len() }
+
+    pub fn is_empty(&self) -> bool { self.0.is_empty() }
+
+    /// 以模板生成消息内容,没有对应模板时返回`None`,`params`中缺少模板引用的参数时返回错误
+    pub fn render(&self, user: &User) -> Result<Option<String>> {
+        let Some(template) = self.0.get(&user.flowmsgtype) else {
+            return Ok(None);
+        };
+        let mut vars = card::variables(user)?;
+        vars.insert("flowmsg".to_owned(), user.flowmsg.clone());
+        card::render(template, &vars, false).map(Some)
+    }
+}
+
+/// 检查模板格式及参数名
+fn validate(template: &str) -> Result<()> {
+    for name in card::placeholders(template)? {
+        if !(name == "flowmsg" || card::FIELDS.contains(&name) || name.starts_with("params.")) {
+            return Err(Error::config(format!("未知的模板参数: {}", name)));
+        }
+    }
+    Ok(())
+}
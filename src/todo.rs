@@ -0,0 +1,321 @@
+//!
+//! 钉钉待办同步
+//!
+//! 定期按`pending_sql`查询待办流程,为每个审批人(按手机号解析userid与unionId)创建钉钉待办,
+//! 待办ID与流程号记录在`sendmsg_todo`表中;流程不再待办时将对应的钉钉待办标记为已完成或删除。
+//! 多实例部署时只应在一个实例启用
+//!
+
+use crate::{
+    app::{AppRegistry, DDApp}, card, config::{TodoCompletion, TodoConfig}, dingtalk::{ApiVersion, DDTodoCreate, DDTodoDetailUrl, DDTodoTask}, phone, sender::Delivery, shutdown::Shutdown, Error, Result
+};
+use arc_swap::ArcSwap;
+use mssql::{
+    prelude::{Local, NaiveDateTime, TimeZone}, sql_bind, Pool, Sql
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet}, sync::Arc
+};
+use tokio::time;
+
+/// 待办跟踪表结构
+const TODO_DDL: &str = "
+CREATE TABLE sendmsg_todo (
+    id BIGINT IDENTITY(1,1) NOT NULL PRIMARY KEY,
+    flownumber NVARCHAR(100) NOT NULL,
+    mobile VARCHAR(50) NOT NULL,
+    app NVARCHAR(100) NOT NULL,
+    unionid VARCHAR(100) NOT NULL,
+    task_id VARCHAR(100) NOT NULL,
+    status VARCHAR(20) NOT NULL DEFAULT 'open',
+    created_at DATETIME NOT NULL DEFAULT GETDATE(),
+    completed_at DATETIME NULL
+);
+CREATE INDEX ix_sendmsg_todo_flownumber ON sendmsg_todo(flownumber);
+CREATE INDEX ix_sendmsg_todo_status ON sendmsg_todo(status);
+";
+
+/// 待办流程(`pending_sql`返回的一行)
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PendingFlow {
+    pub flownumber: String,
+    /// 审批人手机号
+    pub mobile: String,
+    /// 待办标题
+    pub subject: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// 截止时间
+    #[serde(default)]
+    pub due_at: Option<NaiveDateTime>,
+    /// 来源公司/数据库,用于路由钉钉应用
+    #[serde(default)]
+    pub source: Option<String>
+}
+
+/// 已创建的钉钉待办
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TrackedTodo {
+    pub id: i64,
+    pub flownumber: String,
+    /// 规范化后的手机号
+    pub mobile: String,
+    /// 钉钉应用名称
+    pub app: String,
+    pub unionid: String,
+    /// 钉钉待办ID
+    pub task_id: String,
+    /// open/done/deleted/orphaned(钉钉应用已从配置中移除)
+    pub status: String
+}
+
+/// 同步计划
+#[derive(Debug, Default)]
+pub struct Plan<'a> {
+    /// 需创建钉钉待办的流程
+    pub create: Vec<&'a PendingFlow>,
+    /// 流程已完成,需完成或删除的钉钉待办
+    pub complete: Vec<&'a TrackedTodo>
+}
+
+/// 对比待办流程与未完成的钉钉待办,按流程号与手机号匹配
+///
+/// 没有待办流程时只有`complete_on_empty`才完成已有的钉钉待办
+pub fn plan<'a>(pending: &'a [PendingFlow], open: &'a [TrackedTodo], complete_on_empty: bool) -> Plan<'a> {
+    let tracked: HashSet<(&str, &str)> =
+        open.iter().map(|todo| (todo.flownumber.as_str(), todo.mobile.as_str())).collect();
+    let mut keys = HashSet::new();
+    let mut plan = Plan::default();
+    for flow in pending.iter() {
+        let mobile = normalize(&flow.mobile);
+        if keys.insert((flow.flownumber.as_str(), mobile.clone())) &&
+            !tracked.contains(&(flow.flownumber.as_str(), mobile.as_str()))
+        {
+            plan.create.push(flow);
+        }
+    }
+    if pending.is_empty() && !complete_on_empty {
+        if !open.is_empty() {
+            warn!("pending_sql returned no rows, skip completing {} open todos", open.len());
+        }
+        return plan;
+    }
+    plan.complete =
+        open.iter().filter(|todo| !keys.contains(&(todo.flownumber.as_str(), todo.mobile.clone()))).collect();
+    plan
+}
+
+/// 规范化手机号,无效时保留原值
+fn normalize(mobile: &str) -> String { phone::normalize(mobile).unwrap_or_else(|_| mobile.trim().to_owned()) }
+
+/// 同步结果
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SyncReport {
+    pub created: usize,
+    pub completed: usize,
+    /// 钉钉应用已移除,不再处理的待办
+    pub orphaned: usize,
+    pub failed: usize
+}
+
+/// 钉钉待办同步
+pub struct TodoSync {
+    pool: Pool,
+    delivery: Arc<ArcSwap<Delivery>>,
+    cfg: TodoConfig
+}
+
+impl TodoSync {
+    pub fn new(pool: Pool, delivery: Arc<ArcSwap<Delivery>>, cfg: TodoConfig) -> Result<TodoSync> {
+        if cfg.pending_sql.trim().is_empty() {
+            return Err(Error::config("启用钉钉待办同步需配置todo.pending_sql"));
+        }
+        card::placeholders(&cfg.detail_url)?;
+        Ok(TodoSync {
+            pool,
+            delivery,
+            cfg
+        })
+    }
+
+    /// 创建待办跟踪表(不存在时)
+    pub async fn ensure_schema(&self) -> Result<()> {
+        let conn = self.pool.get().await?;
+        if !conn.object_exists("sendmsg_todo").await? {
+            conn.exec(TODO_DDL).await?;
+        }
+        Ok(())
+    }
+
+    /// 定期同步直到收到停机信号
+    pub async fn run(&self, mut shutdown: Shutdown) {
+        let mut ticker = time::interval(self.cfg.interval());
+        ticker.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = shutdown.wait() => break,
+                _ = ticker.tick() => {}
+            }
+            match self.sync().await {
+                Ok(report) if report == SyncReport::default() => {},
+                Ok(report) => {
+                    info!(
+                        "todo synced, created: {}, completed: {}, orphaned: {}, failed: {}",
+                        report.created, report.completed, report.orphaned, report.failed
+                    )
+                },
+                Err(e) => warn!("sync todo error: {}", e)
+            }
+        }
+    }
+
+    /// 同步一次
+    pub async fn sync(&self) -> Result<SyncReport> {
+        let conn = self.pool.get().await?;
+        let pending: Vec<PendingFlow> = conn.query_collect(Sql::new(self.cfg.pending_sql.clone())).await?;
+        let open: Vec<TrackedTodo> = conn
+            .query_collect(
+                "SELECT id, flownumber, mobile, app, unionid, task_id, status FROM sendmsg_todo WHERE status = 'open'"
+            )
+            .await?;
+        drop(conn);
+        let plan = plan(&pending, &open, self.cfg.complete_on_empty);
+        let apps = self.delivery.load().apps().clone();
+        let mut report = SyncReport::default();
+        for flow in plan.create {
+            match self.create(&apps, flow).await {
+                Ok(task_id) => {
+                    debug!("todo {} created for {} {}", task_id, flow.flownumber, flow.mobile);
+                    report.created += 1;
+                },
+                Err(e) => {
+                    warn!("create todo for {} {} error: {}", flow.flownumber, flow.mobile, e);
+                    report.failed += 1;
+                }
+            }
+        }
+        for todo in plan.complete {
+            //钉钉应用已从配置中移除,无法再完成,不再重试
+            let Some(app) = apps.get(&todo.app) else {
+                match self.orphan(todo).await {
+                    Ok(()) => {
+                        warn!(
+                            "todo {} of {} orphaned, app {} not configured",
+                            todo.task_id, todo.flownumber, todo.app
+                        );
+                        report.orphaned += 1;
+                    },
+                    Err(e) => {
+                        warn!("orphan todo {} of {} error: {}", todo.task_id, todo.flownumber, e);
+                        report.failed += 1;
+                    }
+                }
+                continue;
+            };
+            match self.complete(app, todo).await {
+                Ok(()) => report.completed += 1,
+                Err(e) => {
+                    warn!("complete todo {} of {} error: {}", todo.task_id, todo.flownumber, e);
+                    report.failed += 1;
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    /// 为审批人创建钉钉待办并记录待办ID,返回待办ID
+    ///
+    /// 以流程号与审批人手机号作为业务ID,记录待办ID失败后重试时不会重复创建
+    async fn create(&self, apps: &AppRegistry, flow: &PendingFlow) -> Result<String> {
+        let app = apps.by_source(flow.source.as_deref()).ok_or_else(|| {
+            Error::custom(format!(
+                "无法路由到钉钉应用, source: {}",
+                flow.source.as_deref().unwrap_or_default()
+            ))
+        })?;
+        let mobile = phone::normalize(&flow.mobile)?;
+        let userid = app.resolve_userids(&[mobile.as_str()], 1).await?.remove(0)?;
+        let info = app.user_info(&userid).await?;
+        if info.unionid.is_empty() {
+            return Err(Error::custom(format!("未获取到unionId, userid: {}", userid)));
+        }
+        let detail_url = match self.cfg.detail_url.as_str() {
+            "" => None,
+            url => {
+                let vars = HashMap::from([("flownumber".to_owned(), flow.flownumber.clone())]);
+                let url = card::render(url, &vars, true)?;
+                Some(DDTodoDetailUrl {
+                    app_url: url.clone(),
+                    pc_url: url
+                })
+            }
+        };
+        let due_time =
+            flow.due_at.and_then(|v| Local.from_local_datetime(&v).single()).map(|v| v.timestamp_millis());
+        let task_id = app
+            .call(ApiVersion::V1, |access_token| {
+                let req = DDTodoCreate::new(
+                    app.api(),
+                    access_token,
+                    info.unionid.clone(),
+                    format!("{}_{}", flow.flownumber, mobile),
+                    flow.subject.clone(),
+                    flow.description.clone().unwrap_or_default(),
+                    due_time,
+                    detail_url.clone()
+                );
+                async move { req.create().await }
+            })
+            .await?;
+        let conn = self.pool.get().await?;
+        conn.exec(sql_bind!(
+            "INSERT INTO sendmsg_todo (flownumber, mobile, app, unionid, task_id) VALUES (@P1, @P2, @P3, @P4, @P5)",
+            flow.flownumber.clone(),
+            mobile,
+            app.name().to_owned(),
+            info.unionid,
+            task_id.clone()
+        ))
+        .await?;
+        Ok(task_id)
+    }
+
+    /// 完成或删除钉钉待办
+    async fn complete(&self, app: &DDApp, todo: &TrackedTodo) -> Result<()> {
+        let on_complete = self.cfg.on_complete;
+        app.call(ApiVersion::V1, |access_token| {
+            let task = DDTodoTask::new(app.api(), access_token, todo.unionid.clone(), &todo.task_id);
+            async move {
+                match on_complete {
+                    TodoCompletion::Done => task.done().await,
+                    TodoCompletion::Delete => task.delete().await
+                }
+            }
+        })
+        .await?;
+        let status = match on_complete {
+            TodoCompletion::Done => "done",
+            TodoCompletion::Delete => "deleted"
+        };
+        let conn = self.pool.get().await?;
+        conn.exec(sql_bind!(
+            "UPDATE sendmsg_todo SET status = @P2, completed_at = GETDATE() WHERE id = @P1 AND status = 'open'",
+            todo.id,
+            status
+        ))
+        .await?;
+        Ok(())
+    }
+
+    /// 标记钉钉应用已移除的待办
+    async fn orphan(&self, todo: &TrackedTodo) -> Result<()> {
+        let conn = self.pool.get().await?;
+        conn.exec(sql_bind!(
+            "UPDATE sendmsg_todo SET status = 'orphaned', completed_at = GETDATE() WHERE id = @P1 AND status = 'open'",
+            todo.id
+        ))
+        .await?;
+        Ok(())
+    }
+}
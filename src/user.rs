@@ -0,0 +1,186 @@
+//!
+//! 待通知用户
+//!
+
+use crate::{Error, Result};
+use serde::{
+    de::{SeqAccess, Visitor}, Deserialize, Deserializer, Serialize
+};
+use std::{fmt, path::Path};
+
+/// 待通知用户(对应发件箱中的一条记录)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct User {
+    /// 发件箱记录ID
+    #[serde(default)]
+    pub id: i64,
+    pub exeuser: String,
+    pub flownumber: String,
+    pub flowmsgtype: String,
+    pub flowmsg: String,
+    pub userphone: String,
+    pub robotcode: String,
+    /// 来源公司/数据库,用于路由钉钉应用
+    #[serde(default)]
+    pub source: Option<String>,
+    /// 消息模板参数(JSON对象),用于卡片消息链接等
+    #[serde(default)]
+    pub params: Option<String>,
+    /// 附件路径(`sampleFile`/`sampleImageMsg`)
+    #[serde(default)]
+    pub attachment_path: Option<String>,
+    /// 附件文件名,为空时取附件路径中的文件名
+    #[serde(default)]
+    pub attachment_name: Option<String>,
+    /// 附件内容(varbinary),优先于附件路径
+    #[serde(default, skip_serializing, deserialize_with = "deserialize_bytes")]
+    pub attachment: Option<Vec<u8>>,
+    /// 邮箱(邮件渠道),多个地址以逗号分隔
+    #[serde(default)]
+    pub email: Option<String>,
+    /// 优先级,为空时取消息类型的默认优先级
+    #[serde(default)]
+    pub priority: Option<u8>,
+    /// 记录状态
+    #[serde(default)]
+    pub status: Option<String>,
+    /// 已调用发送接口的次数
+    #[serde(default)]
+    pub attempts: i32,
+    /// 发送渠道
+    #[serde(default)]
+    pub channel: Option<String>,
+    /// 机器人消息的`processQueryKey`,用于查询已读状态与撤回
+    #[serde(default)]
+    pub process_query_key: Option<String>
+}
+
+impl User {
+    //初始化用户实例
+    pub fn new(
+        exeuser: String,
+        flownumber: String,
+        flowmsgtype: String,
+        flowmsg: String,
+        userphone: String,
+        robotcode: String
+    ) -> User {
+        User {
+            id: 0,
+            exeuser,
+            flownumber,
+            flowmsgtype,
+            flowmsg,
+            userphone,
+            robotcode,
+            source: None,
+            params: None,
+            attachment_path: None,
+            attachment_name: None,
+            attachment: None,
+            email: None,
+            priority: None,
+            status: None,
+            attempts: 0,
+            channel: None,
+            process_query_key: None
+        }
+    }
+
+    /// 按消息模板类型生成消息参数(JSON)
+    pub fn msg_param(&self) -> String {
+        let param = match self.flowmsgtype.as_str() {
+            "sampleText" => serde_json::json!({ "content": self.flowmsg }),
+            _ => {
+                serde_json::json!({
+                    "title": self.flownumber,
+                    "text": self.flowmsg
+                })
+            }
+        };
+        param.to_string()
+    }
+
+    /// 附件消息的媒体类型,非附件消息返回`None`
+    pub fn media_type(&self) -> Option<&'static str> {
+        match self.flowmsgtype.as_str() {
+            "sampleFile" => Some("file"),
+            "sampleImageMsg" => Some("image"),
+            _ => None
+        }
+    }
+
+    /// 读取附件,返回文件名与内容
+    pub async fn load_attachment(&self) -> Result<(String, Vec<u8>)> {
+        let path = self.attachment_path.as_deref().filter(|v| !v.is_empty());
+        let name = self
+            .attachment_name
+            .clone()
+            .filter(|v| !v.is_empty())
+            .or_else(|| path.and_then(|v| Path::new(v).file_name()).map(|v| v.to_string_lossy().into_owned()))
+            .unwrap_or_else(|| self.flownumber.clone());
+        if let Some(data) = &self.attachment {
+            return Ok((name, data.clone()));
+        }
+        match path {
+            Some(path) => {
+                let data = tokio::fs::read(path)
+                    .await
+                    .map_err(|e| Error::custom(format!("读取附件失败, path: {}, {}", path, e)))?;
+                Ok((name, data))
+            },
+            None => Err(Error::custom(format!("{}消息缺少附件", self.flowmsgtype)))
+        }
+    }
+
+    /// 生成附件消息参数(JSON)
+    pub fn media_msg_param(&self, media_id: &str, file_name: &str) -> String {
+        let param = match self.flowmsgtype.as_str() {
+            "sampleImageMsg" => serde_json::json!({ "photoURL": media_id }),
+            _ => {
+                let file_type = Path::new(file_name)
+                    .extension()
+                    .map(|v| v.to_string_lossy().to_lowercase())
+                    .unwrap_or_default();
+                serde_json::json!({
+                    "mediaId": media_id,
+                    "fileName": file_name,
+                    "fileType": file_type
+                })
+            }
+        };
+        param.to_string()
+    }
+}
+
+//反序列化varbinary字段
+fn deserialize_bytes<'de, D: Deserializer<'de>>(
+    deserializer: D
+) -> std::result::Result<Option<Vec<u8>>, D::Error> {
+    struct BytesVisitor;
+
+    impl<'de> Visitor<'de> for BytesVisitor {
+        type Value = Option<Vec<u8>>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result { f.write_str("bytes") }
+        fn visit_none<E>(self) -> std::result::Result<Self::Value, E> { Ok(None) }
+        fn visit_unit<E>(self) -> std::result::Result<Self::Value, E> { Ok(None) }
+        fn visit_some<D: Deserializer<'de>>(
+            self,
+            deserializer: D
+        ) -> std::result::Result<Self::Value, D::Error> {
+            deserializer.deserialize_bytes(self)
+        }
+        fn visit_bytes<E>(self, v: &[u8]) -> std::result::Result<Self::Value, E> { Ok(Some(v.to_vec())) }
+        fn visit_byte_buf<E>(self, v: Vec<u8>) -> std::result::Result<Self::Value, E> { Ok(Some(v)) }
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> std::result::Result<Self::Value, A::Error> {
+            let mut data = Vec::with_capacity(seq.size_hint().unwrap_or_default());
+            while let Some(v) = seq.next_element()? {
+                data.push(v);
+            }
+            Ok(Some(data))
+        }
+    }
+
+    deserializer.deserialize_option(BytesVisitor)
+}
@@ -0,0 +1,81 @@
+#![allow(dead_code)]
+
+mod mock;
+use mock::{Endpoint, Fault, MockDingTalk, MOBILE};
+use sendmsg::{
+    alert, config::HttpConfig, dingtalk::{DDUserid, DDWebhookRobot}, http::HttpClient, outbox::Undeliverable, Error
+};
+
+fn row(id: i64, exeuser: &str, userphone: &str) -> Undeliverable {
+    Undeliverable {
+        id,
+        exeuser: exeuser.to_owned(),
+        userphone: userphone.to_owned(),
+        flownumber: format!("EBS2024052500000{}", id),
+        errmsg: Some(format!("未找到钉钉用户, mobile: {}", userphone))
+    }
+}
+
+#[test]
+fn render() {
+    let rows = vec![row(1, "苏宁绿", MOBILE), row(2, "王五", "0571-88888888"), row(3, "苏宁绿", MOBILE)];
+    let (title, text) = alert::render(&rows);
+    assert_eq!(title, "钉钉消息无法送达(2人)");
+    assert!(text.contains("共3条消息未送达"));
+    assert!(text.contains(&format!("- **苏宁绿** {}: 2条, 如EBS20240525000001", MOBILE)));
+    assert!(text.contains("- **王五** 0571-88888888: 1条"));
+
+    let rows: Vec<_> = (0..60).map(|i| row(i, &format!("user{}", i), "13800000000")).collect();
+    let (_, text) = alert::render(&rows);
+    assert!(!text.contains("user50"));
+    assert!(text.contains("...等60人"));
+}
+
+#[tokio::test]
+async fn webhook() {
+    let mock = MockDingTalk::start().await;
+    mock.set_webhook_secret("SECmock");
+    let http = HttpClient::new(&HttpConfig::default()).unwrap();
+
+    DDWebhookRobot::new(http.clone(), mock.webhook_url(), Some("SECmock".to_owned()))
+        .send_markdown("标题", "### 内容")
+        .await
+        .unwrap();
+    let messages = mock.webhook_messages();
+    assert_eq!(messages[0]["msgtype"], "markdown");
+    assert_eq!(messages[0]["markdown"]["title"], "标题");
+
+    //未加签
+    match DDWebhookRobot::new(http, mock.webhook_url(), None).send_markdown("标题", "内容").await {
+        Err(Error::DingTalk {
+            errcode: 310000,
+            ..
+        }) => {},
+        rv => panic!("unexpected: {:?}", rv)
+    }
+}
+
+#[tokio::test]
+async fn undeliverable() {
+    let mock = MockDingTalk::start().await;
+    let api = mock.api();
+
+    //errcode为0但未返回userid
+    mock.fail_next(Endpoint::GetByMobile, Fault::errcode(0, "ok"));
+    let err =
+        DDUserid::new(&api, "token".to_owned(), "13800000000".to_owned()).get_userid().await.unwrap_err();
+    assert!(matches!(err, Error::UserNotFound(..)), "{:?}", err);
+    assert!(err.is_undeliverable());
+
+    assert!(Error::DingTalk {
+        errcode: 60121,
+        errmsg: "找不到该用户".to_owned()
+    }
+    .is_undeliverable());
+    assert!(sendmsg::phone::normalize("0571-88888888").unwrap_err().is_undeliverable());
+    assert!(!Error::DingTalk {
+        errcode: 88,
+        errmsg: "系统繁忙".to_owned()
+    }
+    .is_undeliverable());
+}
@@ -0,0 +1,64 @@
+#![allow(dead_code)]
+
+mod mock;
+use mock::MOBILE;
+use sendmsg::{
+    app::AppRegistry, config::{AppConfig, DingTalkConfig, HttpConfig}, dingtalk::DDApi, http::HttpClient, User
+};
+
+fn app_cfg(name: &str, robots: &[&str], sources: &[&str], default: bool) -> AppConfig {
+    AppConfig {
+        name: name.to_owned(),
+        appkey: format!("{}_key", name),
+        appsecret: format!("{}_secret", name),
+        robots: robots.iter().map(|v| v.to_string()).collect(),
+        sources: sources.iter().map(|v| v.to_string()).collect(),
+        default,
+        agent_id: None
+    }
+}
+
+fn api() -> DDApi { DDApi::new(&DingTalkConfig::default(), HttpClient::new(&HttpConfig::default()).unwrap()) }
+
+fn user(robotcode: &str, source: Option<&str>) -> User {
+    let mut user = User::new(
+        "苏宁绿".to_owned(),
+        "EBS20240525000001".to_owned(),
+        "sampleMarkdown".to_owned(),
+        "您有待办任务需要处理".to_owned(),
+        MOBILE.to_owned(),
+        robotcode.to_owned()
+    );
+    user.source = source.map(|v| v.to_owned());
+    user
+}
+
+#[test]
+fn route() {
+    let apps = AppRegistry::new(&api(), &[
+        app_cfg("a", &["robot_a1", "robot_a2"], &["DB_A"], true),
+        app_cfg("b", &[], &["DB_B"], false)
+    ])
+    .unwrap();
+
+    //按机器人编码
+    let (app, robot) = apps.route(&user("robot_a2", Some("DB_B"))).unwrap();
+    assert_eq!((app.name(), robot.as_str()), ("a", "robot_a2"));
+    //按来源,未配置机器人时使用appkey
+    let (app, robot) = apps.route(&user("", Some("db_b"))).unwrap();
+    assert_eq!((app.name(), robot.as_str()), ("b", "b_key"));
+    //默认应用
+    let (app, robot) = apps.route(&user("unknown", Some("DB_C"))).unwrap();
+    assert_eq!((app.name(), robot.as_str()), ("a", "robot_a1"));
+}
+
+#[test]
+fn route_without_default() {
+    let api = api();
+    let apps =
+        AppRegistry::new(&api, &[app_cfg("a", &[], &["DB_A"], false), app_cfg("b", &[], &["DB_B"], false)])
+            .unwrap();
+    assert!(apps.route(&user("", None)).is_err());
+    assert!(AppRegistry::new(&api, &[app_cfg("a", &[], &[], true), app_cfg("b", &[], &[], true)]).is_err());
+    assert!(AppRegistry::new(&api, &[app_cfg("a", &[], &[], false), app_cfg("a", &[], &[], false)]).is_err());
+}
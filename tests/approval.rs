@@ -0,0 +1,328 @@
+#![allow(dead_code)]
+
+mod mock;
+use arc_swap::ArcSwap;
+use mock::{Endpoint, MockDingTalk, MOBILE};
+use sendmsg::{
+    app::AppRegistry, approval::{Approvals, CardCallback, Command, Reply, Todo}, card::{self, ActionCard}, channel::Channels, config::{AppConfig, ApprovalConfig, CardConfig, InteractiveCardConfig}, dingtalk, http::HttpClient, outbox::Outbox, sender::Delivery, server, shutdown, template::Templates, User
+};
+use serde_json::{json, Value};
+use std::{
+    sync::Arc, time::{Duration, SystemTime, UNIX_EPOCH}
+};
+
+const APPKEY: &str = "dingmockappkey";
+const APPSECRET: &str = "mocksecret";
+const CARD_SECRET: &str = "mockcardsecret";
+
+fn approvals(mock: &MockDingTalk) -> (Approvals, Arc<ArcSwap<Delivery>>) {
+    let apps = AppRegistry::new(&mock.api(), &[AppConfig {
+        name: "mock".to_owned(),
+        appkey: APPKEY.to_owned(),
+        appsecret: APPSECRET.to_owned(),
+        robots: vec![],
+        sources: vec![],
+        default: true,
+        agent_id: None
+    }])
+    .unwrap();
+    let delivery = Delivery::new(
+        apps,
+        ActionCard::new(&CardConfig {
+            interactive: Some(InteractiveCardConfig {
+                template_id: "mock_template.schema".to_owned(),
+                callback_route_key: "sendmsg".to_owned()
+            }),
+            ..Default::default()
+        })
+        .unwrap(),
+        Templates::default(),
+        Channels::new(&Default::default(), HttpClient::new(&Default::default()).unwrap()).unwrap(),
+        4
+    );
+    //数据库不可达
+    let pool = mssql::Pool::builder()
+        .connect_timeout(1)
+        .build("server=tcp:127.0.0.1,1;user=sa;password=mock;TrustServerCertificate=true")
+        .unwrap();
+    let cfg = ApprovalConfig {
+        enabled: true,
+        assignee_sql: "SELECT mobile FROM wf_task WHERE flownumber = @P1".to_owned(),
+        approve_sql: "UPDATE wf_task SET state = 1 WHERE flownumber = @P1".to_owned(),
+        reject_sql: "UPDATE wf_task SET state = 2 WHERE flownumber = @P1".to_owned(),
+        card_secret: Some(CARD_SECRET.to_owned()),
+        todo_sql: "SELECT flownumber, title FROM wf_task WHERE assignee_mobile = @P1".to_owned(),
+        todo_url: "https://erp.example.com/flow/approve?flownumber={flownumber}".to_owned(),
+        todo_page_size: 2,
+        ..Default::default()
+    };
+    let outbox = Outbox::new(pool, "test".to_owned(), Duration::from_secs(300));
+    let delivery = Arc::new(ArcSwap::from_pointee(delivery));
+    (Approvals::new(outbox, delivery.clone(), cfg).unwrap(), delivery)
+}
+
+fn now() -> String { SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis().to_string() }
+
+#[test]
+fn parse() {
+    assert_eq!(
+        Command::parse("  同意 EBS20240525000001").unwrap(),
+        Some(Command::Approve {
+            flownumber: "EBS20240525000001".to_owned(),
+            comment: String::new()
+        })
+    );
+    assert_eq!(
+        Command::parse("驳回 EBS20240525000001  金额有误 请核对").unwrap(),
+        Some(Command::Reject {
+            flownumber: "EBS20240525000001".to_owned(),
+            reason: "金额有误 请核对".to_owned()
+        })
+    );
+    assert!(Command::parse("同意").is_err());
+    assert_eq!(
+        Command::parse("待办").unwrap(),
+        Some(Command::Todo {
+            page: 1
+        })
+    );
+    assert_eq!(
+        Command::parse("todo 3").unwrap(),
+        Some(Command::Todo {
+            page: 3
+        })
+    );
+    assert!(Command::parse("待办 0").is_err());
+    assert!(Command::parse("待办 x").is_err());
+    assert_eq!(Command::parse("你好").unwrap(), None);
+    assert_eq!(Command::parse("").unwrap(), None);
+}
+
+#[tokio::test]
+async fn callback() {
+    let mock = MockDingTalk::start().await;
+    mock.add_app(APPKEY, APPSECRET);
+    let approvals = Arc::new(approvals(&mock).0);
+
+    //时间戳过期或签名无效
+    let ts = now();
+    assert!(approvals.verify(APPKEY, &ts, &dingtalk::webhook_sign(APPSECRET, &ts)).is_some());
+    assert!(approvals.verify(APPKEY, &ts, &dingtalk::webhook_sign("wrong", &ts)).is_none());
+    assert!(approvals.verify(APPKEY, &ts, "not-base64!").is_none());
+    assert!(approvals.verify("unknown", &ts, &dingtalk::webhook_sign(APPSECRET, &ts)).is_none());
+    let expired = "1716600000000";
+    assert!(approvals.verify(APPKEY, expired, &dingtalk::webhook_sign(APPSECRET, expired)).is_none());
+
+    let listener = server::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/dingtalk/robot", listener.local_addr().unwrap());
+    let (trigger, shutdown) = shutdown::channel();
+    let task =
+        tokio::spawn(server::serve(listener, server::robot_router(approvals, "/dingtalk/robot"), shutdown));
+    let client = httprequest::Client::new();
+    let post = |content: &str, sign: Option<&str>| {
+        let ts = now();
+        let sign = sign.map(str::to_owned).unwrap_or_else(|| dingtalk::webhook_sign(APPSECRET, &ts));
+        client.post(&url).header("timestamp", ts).header("sign", sign).json(&json!({
+            "msgtype": "text",
+            "text": { "content": content },
+            "senderStaffId": "manager4220",
+            "senderNick": "苏宁绿",
+            "robotCode": APPKEY,
+            "conversationType": "1"
+        }))
+    };
+
+    let resp = post("同意 EBS20240525000001", Some("invalid")).send().await.unwrap();
+    assert_eq!(resp.status(), 401);
+
+    let reply = |resp: httprequest::Response| {
+        async move {
+            assert_eq!(resp.status(), 200);
+            let rv: Value = resp.json().await.unwrap();
+            assert_eq!(rv["msgtype"], "text");
+            rv["text"]["content"].as_str().unwrap().to_owned()
+        }
+    };
+    assert!(reply(post("你好", None).send().await.unwrap()).await.starts_with("支持的指令"));
+    assert!(reply(post("驳回", None).send().await.unwrap()).await.starts_with("无效的请求, 缺少流程号"));
+    //驳回须填写原因,不访问数据库
+    assert_eq!(reply(post("驳回 EBS20240525000001", None).send().await.unwrap()).await, "驳回须填写原因");
+    //数据库不可达
+    assert_eq!(
+        reply(post("同意 EBS20240525000001", None).send().await.unwrap()).await,
+        "处理失败, 请稍后重试"
+    );
+
+    trigger.trigger();
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn todo_pages() {
+    let mock = MockDingTalk::start().await;
+    let (approvals, _) = approvals(&mock);
+    assert_eq!(approvals.todo_reply(&[], 1), Reply::Text("您没有待办流程".to_owned()));
+
+    let todos: Vec<Todo> = (1..=5)
+        .map(|i| {
+            Todo {
+                flownumber: format!("EBS2024052500000{}", i),
+                title: Some(format!("采购申请{}", i)),
+                created_at: None
+            }
+        })
+        .collect();
+    let text = |reply: Reply| {
+        match reply {
+            Reply::Markdown {
+                text,
+                ..
+            } => text,
+            reply => panic!("{:?}", reply)
+        }
+    };
+    let page = text(approvals.todo_reply(&todos, 1));
+    assert!(page.starts_with("#### 待办流程 (共5条, 第1/3页)"), "{}", page);
+    assert!(page.contains(
+        "1. [EBS20240525000001](dingtalk://dingtalkclient/page/link?url=https%3A%2F%2Ferp.example.com"
+    ));
+    assert!(page.contains("EBS20240525000001&pc_slide=true) 采购申请1\n"));
+    assert!(page.contains("2. [EBS20240525000002]"));
+    assert!(!page.contains("EBS20240525000003"));
+    assert!(page.ends_with("发送 \"待办 2\" 查看下一页"));
+    //超出页数时显示最后一页
+    let page = text(approvals.todo_reply(&todos, 9));
+    assert!(page.contains("第3/3页"));
+    assert!(page.contains("5. [EBS20240525000005]"));
+    assert!(!page.contains("查看下一页"));
+}
+
+#[tokio::test]
+async fn todo_callback() {
+    let mock = MockDingTalk::start().await;
+    mock.add_app(APPKEY, APPSECRET);
+    mock.add_user(MOBILE, "manager4220");
+    let (approvals, delivery) = approvals(&mock);
+    let app = delivery.load().apps().by_robot(APPKEY).unwrap().clone();
+    let msg = |userid: &str| {
+        serde_json::from_value(json!({
+            "msgtype": "text",
+            "text": { "content": " 待办 " },
+            "senderStaffId": userid,
+            "robotCode": APPKEY
+        }))
+        .unwrap()
+    };
+    assert_eq!(
+        approvals.handle(&app, &msg("unknown")).await,
+        Reply::Text("通讯录中未找到您的账号".to_owned())
+    );
+    //数据库不可达
+    assert_eq!(
+        approvals.handle(&app, &msg("manager4220")).await,
+        Reply::Text("查询失败, 请稍后重试".to_owned())
+    );
+    assert_eq!(mock.calls(Endpoint::UserGet), 2);
+}
+
+#[test]
+fn card_command() {
+    let callback = |value: Value| {
+        CardCallback {
+            out_track_id: "sendmsg_42_1716616252000".to_owned(),
+            user_id: "manager4220".to_owned(),
+            value: value.to_string()
+        }
+    };
+    let cb = callback(
+        json!({ "cardPrivateData": { "actionIds": ["reject"], "params": { "remark": " 金额有误 " } } })
+    );
+    assert_eq!(cb.command("EBS20240525000001").unwrap(), Command::Reject {
+        flownumber: "EBS20240525000001".to_owned(),
+        reason: "金额有误".to_owned()
+    });
+    let cb = callback(json!({ "cardPrivateData": { "actionIds": ["approve"] } }));
+    assert!(matches!(cb.command("EBS20240525000001").unwrap(), Command::Approve { .. }));
+    assert!(callback(json!({ "cardPrivateData": { "actionIds": ["print"] } })).command("EBS1").is_err());
+    assert!(callback(json!({})).command("EBS1").is_err());
+
+    //钉钉回调字段为content时兼容
+    let cb: CardCallback = serde_json::from_value(json!({
+        "outTrackId": "sendmsg_42_1716616252000",
+        "userId": "manager4220",
+        "content": r#"{"cardPrivateData":{"actionIds":["approve"],"params":{}}}"#
+    }))
+    .unwrap();
+    assert!(cb.command("EBS1").is_ok());
+}
+
+#[tokio::test]
+async fn card_callback() {
+    let mock = MockDingTalk::start().await;
+    mock.add_app(APPKEY, APPSECRET);
+    mock.add_user(MOBILE, "manager4220");
+    let (approvals, delivery) = approvals(&mock);
+    let approvals = Arc::new(approvals);
+
+    //发送互动卡片
+    let mut user = User::new(
+        "苏宁绿".to_owned(),
+        "EBS20240525000001".to_owned(),
+        card::INTERACTIVE_CARD.to_owned(),
+        "采购申请 金额: 1000".to_owned(),
+        MOBILE.to_owned(),
+        APPKEY.to_owned()
+    );
+    user.id = 42;
+    let outcome = delivery.load().send(&user).await;
+    assert!(outcome.result.unwrap().starts_with("mock_pqk_"));
+    let cards = mock.cards();
+    assert_eq!(cards.len(), 1);
+    assert_eq!(cards[0]["cardTemplateId"], "mock_template.schema");
+    assert_eq!(cards[0]["callbackRouteKey"], "sendmsg");
+    assert_eq!(cards[0]["receiverUserIdList"], json!(["manager4220"]));
+    assert_eq!(cards[0]["cardData"]["cardParamMap"]["title"], "EBS20240525000001");
+    assert_eq!(cards[0]["cardData"]["cardParamMap"]["status"], "pending");
+    let out_track_id = cards[0]["outTrackId"].as_str().unwrap().to_owned();
+    assert_eq!(card::parse_out_track_id(&out_track_id), Some(42));
+
+    let ts = now();
+    assert!(approvals.verify_card(&ts, &dingtalk::webhook_sign(CARD_SECRET, &ts)));
+    assert!(!approvals.verify_card(&ts, "not-base64!"));
+    assert!(!approvals.verify_card(&ts, &dingtalk::webhook_sign(APPSECRET, &ts)));
+
+    let listener = server::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/dingtalk/card", listener.local_addr().unwrap());
+    let (trigger, shutdown) = shutdown::channel();
+    let task =
+        tokio::spawn(server::serve(listener, server::card_router(approvals, "/dingtalk/card"), shutdown));
+    let client = httprequest::Client::new();
+    let post = |out_track_id: &str, sign: Option<&str>| {
+        let ts = now();
+        let sign = sign.map(str::to_owned).unwrap_or_else(|| dingtalk::webhook_sign(CARD_SECRET, &ts));
+        client.post(&url).header("timestamp", ts).header("sign", sign).json(&json!({
+            "outTrackId": out_track_id,
+            "userId": "manager4220",
+            "value": json!({ "cardPrivateData": { "actionIds": ["approve"], "params": {} } }).to_string()
+        }))
+    };
+
+    let resp = post(&out_track_id, Some("invalid")).send().await.unwrap();
+    assert_eq!(resp.status(), 401);
+
+    let message = |resp: httprequest::Response| {
+        async move {
+            assert_eq!(resp.status(), 200);
+            let rv: Value = resp.json().await.unwrap();
+            assert!(rv["cardData"]["cardParamMap"].get("status").is_none());
+            rv["cardData"]["cardParamMap"]["message"].as_str().unwrap().to_owned()
+        }
+    };
+    assert!(message(post("unknown", None).send().await.unwrap()).await.starts_with("无效的outTrackId"));
+    //数据库不可达,不更新卡片
+    assert_eq!(message(post(&out_track_id, None).send().await.unwrap()).await, "处理失败, 请稍后重试");
+    assert!(mock.card_updates().is_empty());
+
+    trigger.trigger();
+    task.await.unwrap().unwrap();
+}
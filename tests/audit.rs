@@ -0,0 +1,100 @@
+#![allow(dead_code)]
+
+mod mock;
+use mock::{Endpoint, Fault, MockDingTalk, MOBILE};
+use sendmsg::{
+    app::AppRegistry, audit::{self, AuditContext}, card::ActionCard, channel::Channels, config::{AppConfig, CardConfig, HttpConfig}, dingtalk::ApiVersion, http::HttpClient, sender::Delivery, template::Templates, User
+};
+
+const APPKEY: &str = "dingmockappkey";
+const APPSECRET: &str = "mocksecret";
+const FLOWNUMBER: &str = "EBS20240525000001_20240525135052";
+
+#[tokio::test]
+async fn record_exchanges() {
+    let mock = MockDingTalk::start().await;
+    mock.add_app(APPKEY, APPSECRET);
+    mock.add_user(MOBILE, "manager4220");
+    let (sink, mut rx) = audit::channel(16);
+    let http = HttpClient::new(&HttpConfig::default()).unwrap().with_audit(sink);
+    let apps = AppRegistry::new(&mock.api_with_client(http.clone()), &[AppConfig {
+        name: "mock".to_owned(),
+        appkey: APPKEY.to_owned(),
+        appsecret: APPSECRET.to_owned(),
+        robots: vec![],
+        sources: vec![],
+        default: true,
+        agent_id: None
+    }])
+    .unwrap();
+    let delivery = Delivery::new(
+        apps,
+        ActionCard::new(&CardConfig::default()).unwrap(),
+        Templates::default(),
+        Channels::new(&Default::default(), http).unwrap(),
+        2
+    );
+    let user = User::new(
+        "苏宁绿".to_owned(),
+        FLOWNUMBER.to_owned(),
+        "sampleText".to_owned(),
+        "您有待办任务需要处理".to_owned(),
+        MOBILE.to_owned(),
+        APPKEY.to_owned()
+    );
+
+    mock.fail_next(Endpoint::BatchSend, Fault::api(429, "Forbidden.AccessDenied.QpsLimit", "触发限流"));
+    let outcome = audit::scope(AuditContext::new(FLOWNUMBER, MOBILE), delivery.send(&user)).await;
+    assert!(outcome.result.is_err());
+    //上下文之外的调用不关联流程
+    let app = &delivery.apps().apps()[0];
+    app.invalidate_token(ApiVersion::Legacy).await;
+    app.access_token().await.unwrap();
+
+    let mut entries = vec![];
+    while let Ok(entry) = rx.try_recv() {
+        entries.push(entry);
+    }
+    assert_eq!(entries.len(), 5);
+
+    let token = &entries[0];
+    assert_eq!(token.endpoint, "/gettoken");
+    assert_eq!(token.status, 200);
+    assert_eq!(token.errcode.as_deref(), Some("0"));
+    assert_eq!(token.flownumber.as_deref(), Some(FLOWNUMBER));
+    let params = token.params.as_deref().unwrap();
+    assert!(params.contains(APPKEY));
+    assert!(!params.contains(APPSECRET), "{}", params);
+
+    let userid = &entries[1];
+    assert_eq!(userid.endpoint, "/topapi/v2/user/getbymobile");
+    assert_eq!(userid.request_id.as_deref(), Some("mock"));
+    assert_eq!(userid.userphone.as_deref(), Some(MOBILE));
+    assert!(!userid.params.as_deref().unwrap().contains("mock_token"));
+
+    let api_token = &entries[2];
+    assert_eq!(api_token.endpoint, "/v1.0/oauth2/accessToken");
+    assert_eq!(api_token.flownumber.as_deref(), Some(FLOWNUMBER));
+    assert!(!api_token.params.as_deref().unwrap().contains(APPSECRET));
+
+    let send = &entries[3];
+    assert_eq!(send.endpoint, "/v1.0/robot/oToMessages/batchSend");
+    assert_eq!(send.status, 429);
+    assert_eq!(send.errcode.as_deref(), Some("Forbidden.AccessDenied.QpsLimit"));
+    assert_eq!(send.errmsg.as_deref(), Some("触发限流"));
+    assert!(send.params.as_deref().unwrap().contains("sampleText"));
+
+    assert_eq!(entries[4].endpoint, "/gettoken");
+    assert!(entries[4].flownumber.is_none());
+}
+
+#[test]
+fn context_phone() {
+    let ctx = AuditContext::new(FLOWNUMBER, "+86 138-0013-8000；13912345678, 8613800138000");
+    assert_eq!(ctx.userphone.as_deref(), Some(format!("{},13912345678", MOBILE).as_str()));
+    assert_eq!(ctx.with_phone("+852 6123 4567").userphone.as_deref(), Some("+852-61234567"));
+    assert_eq!(ctx.with_phone("+852 6123 4567").flownumber.as_deref(), Some(FLOWNUMBER));
+    //均无效时保留原值
+    let ctx = AuditContext::new(FLOWNUMBER, " 0571-88888888 ");
+    assert_eq!(ctx.userphone.as_deref(), Some("0571-88888888"));
+}
@@ -0,0 +1,161 @@
+#![allow(dead_code)]
+
+mod mock;
+use mock::MOBILE;
+use sendmsg::{
+    card::{self, ActionCard}, config::{CardButtonConfig, CardConfig, InteractiveCardConfig, LinkTarget}, User
+};
+use serde_json::Value;
+use std::collections::HashMap;
+
+fn user() -> User {
+    let mut user = User::new(
+        "苏宁绿".to_owned(),
+        "EBS20240525000001".to_owned(),
+        "sampleActionCard".to_owned(),
+        "### 采购申请\n金额: 1000".to_owned(),
+        MOBILE.to_owned(),
+        "dingmockappkey".to_owned()
+    );
+    user.id = 42;
+    user.params = Some(r#"{"formid": "PO 01&02", "amount": 1000}"#.to_owned());
+    user
+}
+
+fn button(title: &str, url: &str, target: Option<LinkTarget>) -> CardButtonConfig {
+    CardButtonConfig {
+        title: title.to_owned(),
+        url: url.to_owned(),
+        target
+    }
+}
+
+#[test]
+fn single_button() {
+    let card = ActionCard::new(&CardConfig {
+        target: LinkTarget::App,
+        buttons: vec![button(
+            "审批 {flownumber}",
+            "https://erp.example.com/approve?no={flownumber}&form={formid}",
+            None
+        )],
+        ..Default::default()
+    })
+    .unwrap();
+    let user = user();
+    assert!(ActionCard::is_card(&user));
+    let (msg_key, param) = card.render(&user).unwrap();
+    assert_eq!(msg_key, "sampleActionCard");
+    let param: Value = serde_json::from_str(&param).unwrap();
+    assert_eq!(param["title"], "EBS20240525000001");
+    assert_eq!(param["text"], "### 采购申请\n金额: 1000");
+    assert_eq!(param["singleTitle"], "审批 EBS20240525000001");
+    assert_eq!(
+        param["singleURL"],
+        card::wrap_link(
+            "https://erp.example.com/approve?no=EBS20240525000001&form=PO%2001%2602",
+            LinkTarget::App
+        )
+    );
+    assert_eq!(
+        param["singleURL"],
+        "dingtalk://dingtalkclient/page/link?url=https%3A%2F%2Ferp.example.com%2Fapprove%3Fno%3DEBS20240525000001%\
+         26form%3DPO%252001%252602&pc_slide=true"
+    );
+}
+
+#[test]
+fn multiple_buttons() {
+    let card = ActionCard::new(&CardConfig {
+        target: LinkTarget::Browser,
+        buttons: vec![
+            button("同意", "https://erp.example.com/approve?id={id}", None),
+            button("查看 {amount}", "https://erp.example.com/view?user={exeuser}", Some(LinkTarget::Direct)),
+        ],
+        ..Default::default()
+    })
+    .unwrap();
+    let (msg_key, param) = card.render(&user()).unwrap();
+    assert_eq!(msg_key, "sampleActionCard2");
+    let param: Value = serde_json::from_str(&param).unwrap();
+    assert_eq!(param["actionTitle1"], "同意");
+    assert!(param["actionURL1"].as_str().unwrap().ends_with("approve%3Fid%3D42&pc_slide=false"));
+    assert_eq!(param["actionTitle2"], "查看 1000");
+    assert_eq!(param["actionURL2"], "https://erp.example.com/view?user=%E8%8B%8F%E5%AE%81%E7%BB%BF");
+}
+
+#[test]
+fn render_template() {
+    let vars = HashMap::from([
+        ("flownumber".to_owned(), "EBS20240525000001".to_owned()),
+        ("params.remark".to_owned(), "a&b".to_owned())
+    ]);
+    assert_eq!(
+        card::render("{{\"no\": \"{flownumber}\"}} {params.remark}", &vars, false).unwrap(),
+        "{\"no\": \"EBS20240525000001\"} a&b"
+    );
+    assert_eq!(card::render("?remark={params.remark}", &vars, true).unwrap(), "?remark=a%26b");
+    assert_eq!(card::placeholders("{{a}} {flownumber}").unwrap(), vec!["flownumber"]);
+    for template in ["{flownumber", "a}b", "{\"a\": 1}", "{}", "{unknown}"] {
+        assert!(card::render(template, &vars, false).is_err(), "{}", template);
+    }
+
+    //params中的参数可直接引用或以params.前缀引用
+    let mut user = user();
+    user.params = Some(r#"{"remark": "同意"}"#.to_owned());
+    let vars = card::variables(&user).unwrap();
+    assert_eq!(vars["remark"], "同意");
+    assert_eq!(vars["params.remark"], "同意");
+}
+
+#[test]
+fn invalid_template() {
+    let card = ActionCard::new(&CardConfig {
+        target: LinkTarget::Direct,
+        buttons: vec![button("审批", "https://erp.example.com/approve?no={unknown}", None)],
+        ..Default::default()
+    })
+    .unwrap();
+    assert!(card.render(&user()).is_err());
+
+    let mut user = user();
+    user.params = Some("[1]".to_owned());
+    assert!(card.render(&user).is_err());
+
+    assert!(ActionCard::new(&CardConfig::default()).unwrap().render(&user).is_err());
+    assert!(ActionCard::new(&CardConfig {
+        target: LinkTarget::App,
+        buttons: (0..6).map(|_| button("a", "b", None)).collect(),
+        ..Default::default()
+    })
+    .is_err());
+}
+
+#[test]
+fn interactive() {
+    let mut user = user();
+    assert!(ActionCard::new(&CardConfig::default()).unwrap().interactive(&user).is_err());
+
+    let card = ActionCard::new(&CardConfig {
+        interactive: Some(InteractiveCardConfig {
+            template_id: "mock_template.schema".to_owned(),
+            callback_route_key: "sendmsg".to_owned()
+        }),
+        ..Default::default()
+    })
+    .unwrap();
+    user.flowmsgtype = card::INTERACTIVE_CARD.to_owned();
+    assert!(ActionCard::is_interactive(&user));
+    let (cfg, params) = card.interactive(&user).unwrap();
+    assert_eq!(cfg.template_id, "mock_template.schema");
+    assert_eq!(params["title"], "EBS20240525000001");
+    assert_eq!(params["content"], "### 采购申请\n金额: 1000");
+    assert_eq!(params["status"], "pending");
+    assert_eq!(params["formid"], "PO 01&02");
+
+    let out_track_id = card::out_track_id(&user);
+    assert!(out_track_id.starts_with("sendmsg_42_"));
+    assert_eq!(card::parse_out_track_id(&out_track_id), Some(42));
+    assert_eq!(card::parse_out_track_id("other_42_1"), None);
+    assert_eq!(card::parse_out_track_id("sendmsg_x_1"), None);
+}
@@ -0,0 +1,314 @@
+#![allow(dead_code)]
+
+mod mock;
+use httprequest::StatusCode;
+use mock::{Endpoint, Fault, MockDingTalk, MOBILE};
+use sendmsg::{
+    app::{AppRegistry, DDApp}, card::ActionCard, channel::Channels, config::{AppConfig, CardConfig, HttpConfig}, dingtalk::{self, ApiVersion, DDRobotReadStatus, DDRobotRecall}, http::HttpClient, sender::Delivery, template::Templates, Error, User
+};
+use std::time::Duration;
+
+const APPKEY: &str = "dingmockappkey";
+const APPSECRET: &str = "mocksecret";
+const USERID: &str = "manager4220";
+
+async fn setup() -> (MockDingTalk, DDApp) {
+    let mock = MockDingTalk::start().await;
+    mock.add_app(APPKEY, APPSECRET);
+    mock.add_user(MOBILE, USERID);
+    let app = DDApp::new(&mock.api(), &app_cfg());
+    (mock, app)
+}
+
+fn app_cfg() -> AppConfig {
+    AppConfig {
+        name: "mock".to_owned(),
+        appkey: APPKEY.to_owned(),
+        appsecret: APPSECRET.to_owned(),
+        robots: vec![],
+        sources: vec![],
+        default: true,
+        agent_id: None
+    }
+}
+
+/// 只有机器人单聊渠道的投递
+fn delivery(mock: &MockDingTalk) -> Delivery {
+    Delivery::new(
+        AppRegistry::new(&mock.api(), &[app_cfg()]).unwrap(),
+        ActionCard::new(&CardConfig::default()).unwrap(),
+        Templates::default(),
+        Channels::new(&Default::default(), HttpClient::new(&Default::default()).unwrap()).unwrap(),
+        2
+    )
+}
+
+fn user() -> User {
+    User::new(
+        "苏宁绿".to_owned(),
+        "EBS20240525000001_20240525135052".to_owned(),
+        "sampleMarkdown".to_owned(),
+        "您有待办任务需要处理".to_owned(),
+        MOBILE.to_owned(),
+        APPKEY.to_owned()
+    )
+}
+
+#[tokio::test]
+async fn token_cache() {
+    let (mock, app) = setup().await;
+    let token = app.access_token().await.unwrap();
+    assert_eq!(app.access_token().await.unwrap(), token);
+    assert_eq!(mock.calls(Endpoint::GetToken), 1);
+
+    app.invalidate_token(ApiVersion::Legacy).await;
+    assert_ne!(app.access_token().await.unwrap(), token);
+    assert_eq!(mock.calls(Endpoint::GetToken), 2);
+}
+
+#[tokio::test]
+async fn token_errcode() {
+    let (mock, app) = setup().await;
+    mock.fail_next(Endpoint::GetToken, Fault::errcode(88, "系统繁忙"));
+    match app.access_token().await {
+        Err(Error::DingTalk {
+            errcode: 88,
+            ..
+        }) => {},
+        rv => panic!("unexpected: {:?}", rv)
+    }
+    //故障只生效一次
+    assert!(app.access_token().await.is_ok());
+}
+
+#[tokio::test]
+async fn api_token() {
+    let (mock, app) = setup().await;
+    let token = app.token(ApiVersion::V1).await.unwrap();
+    assert_eq!(app.token(ApiVersion::V1).await.unwrap(), token);
+    assert_ne!(app.access_token().await.unwrap(), token);
+    assert_eq!(mock.calls(Endpoint::ApiToken), 1);
+    assert_eq!(mock.calls(Endpoint::GetToken), 1);
+
+    //token失效时重新获取并重试一次
+    mock.expire_tokens();
+    let info = app.user_info(USERID).await.unwrap();
+    assert_eq!(info.unionid, format!("union_{}", USERID));
+    assert_eq!(mock.calls(Endpoint::GetToken), 2);
+    assert_eq!(mock.calls(Endpoint::ApiToken), 1);
+    let rv = app.call(ApiVersion::V1, |access_token| async move { Ok(access_token) }).await.unwrap();
+    assert_eq!(rv, token);
+
+    mock.fail_next(Endpoint::ApiToken, Fault::api(400, "invalidClientSecret", "应用凭证无效"));
+    app.invalidate_token(ApiVersion::V1).await;
+    match app.token(ApiVersion::V1).await {
+        Err(Error::DingTalkApi {
+            code,
+            ..
+        }) => assert_eq!(code, "invalidClientSecret"),
+        rv => panic!("unexpected: {:?}", rv)
+    }
+}
+
+#[test]
+fn api_envelope() {
+    assert!(dingtalk::envelope_error(r#"{"errcode":0,"errmsg":"ok"}"#).is_none());
+    assert!(dingtalk::envelope_error(r#"{"processQueryKey":"abc"}"#).is_none());
+    assert!(dingtalk::envelope_error("<html>Bad Gateway</html>").is_none());
+    match dingtalk::envelope_error(r#"{"errcode":60121,"errmsg":"找不到该用户"}"#) {
+        Some(Error::DingTalk {
+            errcode: 60121,
+            ..
+        }) => {},
+        rv => panic!("unexpected: {:?}", rv)
+    }
+    let err =
+        dingtalk::envelope_error(r#"{"code":"InvalidAuthentication","message":"不合法的access_token"}"#)
+            .unwrap();
+    assert!(err.is_token_invalid());
+
+    let rv: Result<serde_json::Value, _> =
+        ApiVersion::V1.parse((StatusCode::BAD_GATEWAY, "<html>Bad Gateway</html>".to_owned()));
+    match rv {
+        Err(Error::DingTalkApi {
+            code,
+            ..
+        }) => assert_eq!(code, "HTTP502"),
+        rv => panic!("unexpected: {:?}", rv)
+    }
+}
+
+#[tokio::test]
+async fn resolve_userids() {
+    let (mock, app) = setup().await;
+    //均无效时不获取token
+    let rv = app.resolve_userids(&["0571-88888888", ""], 2).await.unwrap();
+    assert!(rv.iter().all(|rv| matches!(rv, Err(Error::InvalidPhone { .. }))), "{:?}", rv);
+    assert_eq!(mock.calls(Endpoint::GetToken), 0);
+
+    let rv = app.resolve_userids(&[MOBILE, "13800000000", "0571-88888888"], 2).await.unwrap();
+    assert_eq!(rv[0].as_deref().unwrap(), USERID);
+    match &rv[1] {
+        Err(Error::DingTalk {
+            errcode: 60121,
+            ..
+        }) => {},
+        rv => panic!("unexpected: {:?}", rv)
+    }
+    assert!(matches!(rv[2], Err(Error::InvalidPhone { .. })), "{:?}", rv[2]);
+    assert_eq!(mock.calls(Endpoint::GetByMobile), 2);
+
+    //token失效时重新获取并重试
+    mock.expire_tokens();
+    let rv = app.resolve_userids(&[MOBILE], 2).await.unwrap();
+    assert_eq!(rv[0].as_deref().unwrap(), USERID);
+    assert_eq!(mock.calls(Endpoint::GetToken), 2);
+    assert_eq!(mock.calls(Endpoint::GetByMobile), 4);
+}
+
+#[tokio::test]
+async fn send_msg() {
+    let (mock, _) = setup().await;
+    let delivery = delivery(&mock);
+    let outcome = delivery.send(&user()).await;
+    let process_query_key = outcome.result.unwrap();
+
+    let sent = mock.sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].process_query_key, process_query_key);
+    assert_eq!(sent[0].robot_code, APPKEY);
+    assert_eq!(sent[0].user_ids, vec![USERID.to_owned()]);
+    assert_eq!(sent[0].msg_key, "sampleMarkdown");
+    assert_eq!(sent[0].msg_param["text"], "您有待办任务需要处理");
+
+    mock.fail_next(Endpoint::BatchSend, Fault::api(429, "Forbidden.AccessDenied.QpsLimit", "触发限流"));
+    match delivery.send(&user()).await.result {
+        Err(Error::DingTalkApi {
+            code,
+            ..
+        }) => assert_eq!(code, "Forbidden.AccessDenied.QpsLimit"),
+        rv => panic!("unexpected: {:?}", rv)
+    }
+    assert_eq!(mock.sent().len(), 1);
+}
+
+#[tokio::test]
+async fn send_msg_token_expired() {
+    let (mock, _) = setup().await;
+    let delivery = delivery(&mock);
+    //发送接口返回token失效时重新获取新版接口token并重试
+    mock.fail_next(Endpoint::BatchSend, Fault::api(401, "InvalidAuthentication", "不合法的access_token"));
+    let outcome = delivery.send(&user()).await;
+    assert!(outcome.result.is_ok(), "{:?}", outcome.result);
+    assert_eq!(mock.calls(Endpoint::BatchSend), 2);
+    assert_eq!(mock.calls(Endpoint::ApiToken), 2);
+    assert_eq!(mock.calls(Endpoint::GetToken), 1);
+    assert_eq!(mock.sent().len(), 1);
+}
+
+#[tokio::test]
+async fn malformed_response() {
+    let (mock, app) = setup().await;
+    mock.fail_next(Endpoint::GetByMobile, Fault::raw(502, "<html>Bad Gateway</html>"));
+    let mut rv = app.resolve_userids(&[MOBILE], 1).await.unwrap();
+    match rv.remove(0) {
+        Err(
+            e @ Error::DingTalkApi {
+                ..
+            }
+        ) => assert!(e.is_unavailable(), "unexpected: {:?}", e),
+        rv => panic!("unexpected: {:?}", rv)
+    }
+}
+
+#[tokio::test]
+async fn latency() {
+    let (mock, app) = setup().await;
+    mock.set_latency(Endpoint::GetToken, Duration::from_millis(200));
+    let now = std::time::Instant::now();
+    app.access_token().await.unwrap();
+    assert!(now.elapsed() >= Duration::from_millis(200));
+}
+
+#[tokio::test]
+async fn request_timeout() {
+    let (mock, _) = setup().await;
+    let api = mock.api_with(&HttpConfig {
+        timeout: 1,
+        ..Default::default()
+    });
+    let app = DDApp::new(&api, &app_cfg());
+    mock.set_latency(Endpoint::GetToken, Duration::from_millis(1500));
+    match app.access_token().await {
+        Err(Error::Http(e)) => assert!(e.is_timeout()),
+        rv => panic!("unexpected: {:?}", rv)
+    }
+}
+
+#[tokio::test]
+async fn recall_and_read_status() {
+    let (mock, app) = setup().await;
+    let pqk = delivery(&mock).send(&user()).await.result.unwrap();
+    let token = app.token(ApiVersion::V1).await.unwrap();
+
+    let status = DDRobotReadStatus::new(app.api(), token.clone(), APPKEY.to_owned(), pqk.clone());
+    let rv = status.get_read_status().await.unwrap();
+    assert_eq!(rv.message_read_info_list.len(), 1);
+    assert_eq!(rv.message_read_info_list[0].read_status, "UNREAD");
+
+    mock.mark_read(&pqk, USERID);
+    let rv = status.get_read_status().await.unwrap();
+    assert_eq!(rv.message_read_info_list[0].read_status, "READ");
+
+    let rv = DDRobotRecall::new(app.api(), token, APPKEY.to_owned(), vec![pqk.clone(), "unknown".to_owned()])
+        .recall()
+        .await
+        .unwrap();
+    assert_eq!(rv.success_result, vec![pqk]);
+    assert!(rv.failed_result.contains_key("unknown"));
+    assert!(mock.sent()[0].recalled);
+}
+
+#[tokio::test]
+async fn send_attachment() {
+    let (mock, app) = setup().await;
+    let mut user = user();
+    user.flowmsgtype = "sampleFile".to_owned();
+    user.attachment_name = Some("审批单.PDF".to_owned());
+    user.attachment = Some(b"%PDF-1.4 mock".to_vec());
+
+    let (file_name, data) = user.load_attachment().await.unwrap();
+    assert_eq!(file_name, "审批单.PDF");
+    let media_id = app.upload_media(user.media_type().unwrap(), &file_name, data.clone()).await.unwrap();
+    //相同内容复用media_id
+    assert_eq!(app.upload_media("file", &file_name, data).await.unwrap(), media_id);
+    assert_eq!(mock.calls(Endpoint::MediaUpload), 1);
+    let media = mock.media();
+    assert_eq!(media[0].media_type, "file");
+    assert!(String::from_utf8_lossy(&media[0].body).contains("%PDF-1.4 mock"));
+
+    delivery(&mock).send(&user).await.result.unwrap();
+    let sent = mock.sent();
+    assert_eq!(mock.calls(Endpoint::MediaUpload), 2);
+    assert_eq!(sent[0].msg_key, "sampleFile");
+    assert_eq!(sent[0].msg_param["mediaId"], mock.media()[1].media_id.as_str());
+    assert_eq!(sent[0].msg_param["fileName"], "审批单.PDF");
+    assert_eq!(sent[0].msg_param["fileType"], "pdf");
+
+    //按路径读取图片
+    let path = std::env::temp_dir().join(format!("sendmsg_attachment_{}.png", std::process::id()));
+    std::fs::write(&path, b"\x89PNG mock").unwrap();
+    let mut user = self::user();
+    user.flowmsgtype = "sampleImageMsg".to_owned();
+    user.attachment_path = Some(path.display().to_string());
+    let (file_name, data) = user.load_attachment().await.unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(file_name.ends_with(".png"));
+    let media_id = app.upload_media(user.media_type().unwrap(), &file_name, data).await.unwrap();
+    assert_eq!(mock.media()[2].media_type, "image");
+    assert_eq!(user.media_msg_param(&media_id, &file_name), format!("{{\"photoURL\":\"{}\"}}", media_id));
+
+    //缺少附件
+    user.attachment_path = None;
+    assert!(user.load_attachment().await.is_err());
+}
@@ -0,0 +1,84 @@
+#![allow(dead_code)]
+
+mod mock;
+use arc_swap::ArcSwap;
+use mock::MockDingTalk;
+use sendmsg::{
+    app::AppRegistry, card::ActionCard, channel::Channels, config::{AppConfig, ServerConfig}, health::{self, Health}, http::HttpClient, outbox::Outbox, sender::Delivery, server, shutdown, template::Templates
+};
+use serde_json::Value;
+use std::{sync::Arc, time::Duration};
+
+const APPKEY: &str = "dingmockappkey";
+
+fn apps(mock: &MockDingTalk, appsecret: &str) -> AppRegistry {
+    AppRegistry::new(&mock.api(), &[AppConfig {
+        name: "mock".to_owned(),
+        appkey: APPKEY.to_owned(),
+        appsecret: appsecret.to_owned(),
+        robots: vec![],
+        sources: vec![],
+        default: true,
+        agent_id: None
+    }])
+    .unwrap()
+}
+
+#[tokio::test]
+async fn check_tokens() {
+    let mock = MockDingTalk::start().await;
+    mock.add_app(APPKEY, "mocksecret");
+
+    let rv = health::check_tokens(&apps(&mock, "mocksecret"), Duration::from_secs(3)).await;
+    assert!(rv.ok);
+    assert_eq!(serde_json::to_value(&rv).unwrap()["apps"]["mock"]["ok"], true);
+
+    let rv = health::check_tokens(&apps(&mock, "wrongsecret"), Duration::from_secs(3)).await;
+    assert!(!rv.ok);
+    let rv = serde_json::to_value(&rv).unwrap();
+    assert!(rv["apps"]["mock"]["error"].as_str().unwrap().contains("40089"));
+}
+
+#[tokio::test]
+async fn endpoints() {
+    let mock = MockDingTalk::start().await;
+    mock.add_app(APPKEY, "mocksecret");
+    //数据库不可达
+    let pool = mssql::Pool::builder()
+        .connect_timeout(1)
+        .build("server=tcp:127.0.0.1,1;user=sa;password=mock;TrustServerCertificate=true")
+        .unwrap();
+    let outbox = Outbox::new(pool.clone(), "test".to_owned(), Duration::from_secs(300));
+    let cfg = ServerConfig {
+        check_timeout: 1,
+        ..Default::default()
+    };
+    let delivery = Delivery::new(
+        apps(&mock, "mocksecret"),
+        ActionCard::new(&Default::default()).unwrap(),
+        Templates::default(),
+        Channels::new(&Default::default(), HttpClient::new(&Default::default()).unwrap()).unwrap(),
+        4
+    );
+    let health = Health::new(pool, outbox, Arc::new(ArcSwap::from_pointee(delivery)), cfg);
+    let listener = server::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let (trigger, shutdown) = shutdown::channel();
+    let task = tokio::spawn(server::serve(listener, server::router(Arc::new(health)), shutdown));
+
+    let resp = httprequest::get(format!("{}/healthz", url)).await.unwrap();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.json::<Value>().await.unwrap()["status"], "ok");
+
+    let resp = httprequest::get(format!("{}/readyz", url)).await.unwrap();
+    assert_eq!(resp.status(), 503);
+    let rv: Value = resp.json().await.unwrap();
+    assert_eq!(rv["ready"], false);
+    assert_eq!(rv["db"]["ok"], false);
+    assert_eq!(rv["outbox"]["ok"], false);
+    assert_eq!(rv["tokens"]["ok"], true);
+    assert_eq!(rv["tokens"]["apps"]["mock"]["ok"], true);
+
+    trigger.trigger();
+    task.await.unwrap().unwrap();
+}
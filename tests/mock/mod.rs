@@ -0,0 +1,703 @@
+//!
+//! 钉钉接口模拟服务
+//!
+//! 支持`gettoken`/`oauth2/accessToken`/`getbymobile`/`user/get`/`media/upload`/`batchSend`/`batchRecall`/`readStatus`、
+//! 互动卡片`interactiveCards/send`/`interactiveCards`、待办`todo/users/{unionId}/tasks`
+//! 以及自定义群机器人`robot/send`,
+//! 可按接口预设错误码、HTTP错误与延迟
+//!
+
+use axum::{
+    body::Bytes, extract::{Path, Query, State}, http::{HeaderMap, StatusCode}, response::{IntoResponse, Response}, routing::{get, post, put}, Json, Router
+};
+use sendmsg::{
+    config::{DingTalkConfig, HttpConfig}, dingtalk::DDApi, http::HttpClient
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    collections::{HashMap, HashSet, VecDeque}, net::SocketAddr, sync::{Arc, Mutex}, time::Duration
+};
+
+/// 测试用手机号,使用虚构号码,不得使用真实员工号码
+pub const MOBILE: &str = "13800138000";
+
+/// 模拟的接口
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Endpoint {
+    GetToken,
+    ApiToken,
+    GetByMobile,
+    UserGet,
+    MediaUpload,
+    BatchSend,
+    BatchRecall,
+    ReadStatus,
+    Webhook,
+    WorkNotice,
+    Sms,
+    InteractiveCardSend,
+    InteractiveCardUpdate,
+    TodoCreate,
+    TodoUpdate,
+    TodoDelete
+}
+
+/// 预设的故障
+#[derive(Debug, Clone)]
+pub enum Fault {
+    /// 旧版接口错误(HTTP 200, `errcode`/`errmsg`)
+    ErrCode(i64, String),
+    /// 新版接口错误(`code`/`message`)
+    Api(u16, String, String),
+    /// 原始HTTP状态与响应体
+    Raw(u16, String)
+}
+
+impl Fault {
+    pub fn errcode(errcode: i64, errmsg: &str) -> Fault { Fault::ErrCode(errcode, errmsg.to_owned()) }
+    pub fn api(status: u16, code: &str, message: &str) -> Fault {
+        Fault::Api(status, code.to_owned(), message.to_owned())
+    }
+    pub fn raw(status: u16, body: &str) -> Fault { Fault::Raw(status, body.to_owned()) }
+
+    fn into_response(self) -> Response {
+        match self {
+            Fault::ErrCode(errcode, errmsg) => {
+                Json(json!({ "errcode": errcode, "errmsg": errmsg, "request_id": "mock" })).into_response()
+            },
+            Fault::Api(status, code, message) => {
+                (status_code(status), Json(json!({ "code": code, "message": message, "requestid": "mock" })))
+                    .into_response()
+            },
+            Fault::Raw(status, body) => (status_code(status), body).into_response()
+        }
+    }
+}
+
+/// 已发送的消息
+#[derive(Debug, Clone)]
+pub struct SentMsg {
+    pub process_query_key: String,
+    pub robot_code: String,
+    pub user_ids: Vec<String>,
+    pub msg_key: String,
+    pub msg_param: Value,
+    pub recalled: bool
+}
+
+/// 钉钉待办
+#[derive(Debug, Clone)]
+pub struct MockTodo {
+    pub id: String,
+    pub unionid: String,
+    /// 创建请求
+    pub body: Value,
+    pub done: bool,
+    pub deleted: bool
+}
+
+/// 已上传的媒体文件
+#[derive(Debug, Clone)]
+pub struct Media {
+    pub media_id: String,
+    pub media_type: String,
+    /// multipart请求体
+    pub body: Vec<u8>
+}
+
+#[derive(Default)]
+struct MockState {
+    /// appkey -> appsecret
+    apps: HashMap<String, String>,
+    /// mobile -> userid
+    users: HashMap<String, String>,
+    tokens: HashSet<String>,
+    faults: HashMap<Endpoint, VecDeque<Fault>>,
+    latency: HashMap<Endpoint, Duration>,
+    calls: HashMap<Endpoint, usize>,
+    sent: Vec<SentMsg>,
+    media: Vec<Media>,
+    /// 群机器人加签密钥
+    webhook_secret: Option<String>,
+    webhook: Vec<Value>,
+    /// 工作通知请求
+    work_notices: Vec<Value>,
+    /// 短信网关请求(请求头, 请求体)
+    sms: Vec<(HeaderMap, Value)>,
+    /// 互动卡片发送请求
+    cards: Vec<Value>,
+    /// 互动卡片更新请求
+    card_updates: Vec<Value>,
+    todos: Vec<MockTodo>,
+    /// (processQueryKey, userid)
+    read: HashSet<(String, String)>,
+    seq: u64
+}
+
+type Shared = Arc<Mutex<MockState>>;
+
+/// 钉钉接口模拟服务
+pub struct MockDingTalk {
+    addr: SocketAddr,
+    state: Shared
+}
+
+impl MockDingTalk {
+    /// 在随机端口启动服务
+    pub async fn start() -> MockDingTalk {
+        let state = Shared::default();
+        let router = Router::new()
+            .route("/gettoken", get(gettoken))
+            .route("/v1.0/oauth2/accessToken", post(api_token))
+            .route("/topapi/v2/user/getbymobile", post(getbymobile))
+            .route("/topapi/v2/user/get", post(user_get))
+            .route("/media/upload", post(media_upload))
+            .route("/v1.0/robot/oToMessages/batchSend", post(batch_send))
+            .route("/v1.0/robot/otoMessages/batchRecall", post(batch_recall))
+            .route("/v1.0/robot/oToMessages/readStatus", get(read_status))
+            .route("/robot/send", post(webhook_send))
+            .route("/topapi/message/corpconversation/asyncsend_v2", post(work_notice))
+            .route("/sms/send", post(sms_send))
+            .route("/v1.0/im/interactiveCards/send", post(interactive_card_send))
+            .route("/v1.0/im/interactiveCards", put(interactive_card_update))
+            .route("/v1.0/todo/users/:unionid/tasks", post(todo_create))
+            .route("/v1.0/todo/users/:unionid/tasks/:id", put(todo_update).delete(todo_delete))
+            .with_state(state.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.expect("serve") });
+        MockDingTalk {
+            addr,
+            state
+        }
+    }
+    /// 服务地址
+    pub fn url(&self) -> String { format!("http://{}", self.addr) }
+    /// 指向模拟服务的接口
+    pub fn api(&self) -> DDApi { self.api_with(&HttpConfig::default()) }
+    /// 指向模拟服务的接口,使用指定的HTTP客户端配置
+    pub fn api_with(&self, http: &HttpConfig) -> DDApi {
+        self.api_with_client(HttpClient::new(http).expect("http client"))
+    }
+    /// 指向模拟服务的接口,使用指定的HTTP客户端
+    pub fn api_with_client(&self, http: HttpClient) -> DDApi {
+        let cfg = DingTalkConfig {
+            oapi_url: self.url(),
+            api_url: self.url()
+        };
+        DDApi::new(&cfg, http)
+    }
+    /// 注册应用
+    pub fn add_app(&self, appkey: &str, appsecret: &str) {
+        self.state.lock().unwrap().apps.insert(appkey.to_owned(), appsecret.to_owned());
+    }
+    /// 注册用户
+    pub fn add_user(&self, mobile: &str, userid: &str) {
+        self.state.lock().unwrap().users.insert(mobile.to_owned(), userid.to_owned());
+    }
+    /// 预设下一次调用的故障,可多次调用按顺序生效
+    pub fn fail_next(&self, endpoint: Endpoint, fault: Fault) {
+        self.state.lock().unwrap().faults.entry(endpoint).or_default().push_back(fault);
+    }
+    /// 设置接口延迟
+    pub fn set_latency(&self, endpoint: Endpoint, latency: Duration) {
+        self.state.lock().unwrap().latency.insert(endpoint, latency);
+    }
+    /// 使已颁发的token全部失效
+    pub fn expire_tokens(&self) { self.state.lock().unwrap().tokens.clear(); }
+    /// 标记消息已读
+    pub fn mark_read(&self, process_query_key: &str, userid: &str) {
+        self.state.lock().unwrap().read.insert((process_query_key.to_owned(), userid.to_owned()));
+    }
+    /// 接口调用次数
+    pub fn calls(&self, endpoint: Endpoint) -> usize {
+        self.state.lock().unwrap().calls.get(&endpoint).copied().unwrap_or_default()
+    }
+    /// 已发送的消息
+    pub fn sent(&self) -> Vec<SentMsg> { self.state.lock().unwrap().sent.clone() }
+    /// 群机器人Webhook地址
+    pub fn webhook_url(&self) -> String { format!("{}/robot/send?access_token=mock_webhook", self.url()) }
+    /// 设置群机器人加签密钥
+    pub fn set_webhook_secret(&self, secret: &str) {
+        self.state.lock().unwrap().webhook_secret = Some(secret.to_owned());
+    }
+    /// 群机器人收到的消息
+    pub fn webhook_messages(&self) -> Vec<Value> { self.state.lock().unwrap().webhook.clone() }
+    /// 收到的工作通知
+    pub fn work_notices(&self) -> Vec<Value> { self.state.lock().unwrap().work_notices.clone() }
+    /// 短信网关地址
+    pub fn sms_url(&self) -> String { format!("{}/sms/send", self.url()) }
+    /// 短信网关收到的请求
+    pub fn sms(&self) -> Vec<(HeaderMap, Value)> { self.state.lock().unwrap().sms.clone() }
+    /// 收到的互动卡片发送请求
+    pub fn cards(&self) -> Vec<Value> { self.state.lock().unwrap().cards.clone() }
+    /// 钉钉待办
+    pub fn todos(&self) -> Vec<MockTodo> { self.state.lock().unwrap().todos.clone() }
+    /// 收到的互动卡片更新请求
+    pub fn card_updates(&self) -> Vec<Value> { self.state.lock().unwrap().card_updates.clone() }
+    /// 已上传的媒体文件
+    pub fn media(&self) -> Vec<Media> { self.state.lock().unwrap().media.clone() }
+}
+
+fn status_code(status: u16) -> StatusCode { StatusCode::from_u16(status).expect("status code") }
+
+/// 记录调用、模拟延迟并取出预设故障
+async fn script(state: &Shared, endpoint: Endpoint) -> Option<Response> {
+    let (latency, fault) = {
+        let mut state = state.lock().unwrap();
+        *state.calls.entry(endpoint).or_default() += 1;
+        let fault = state.faults.get_mut(&endpoint).and_then(|v| v.pop_front());
+        (state.latency.get(&endpoint).copied(), fault)
+    };
+    if let Some(latency) = latency {
+        tokio::time::sleep(latency).await;
+    }
+    fault.map(Fault::into_response)
+}
+
+/// 新版接口token校验
+fn check_api_token(state: &MockState, headers: &HeaderMap) -> Option<Response> {
+    let token = headers.get("x-acs-dingtalk-access-token").and_then(|v| v.to_str().ok()).unwrap_or_default();
+    if state.tokens.contains(token) {
+        None
+    } else {
+        Some(Fault::api(401, "InvalidAuthentication", "不合法的access_token").into_response())
+    }
+}
+
+#[derive(Deserialize)]
+struct GetTokenParam {
+    appkey: String,
+    appsecret: String
+}
+
+async fn gettoken(State(state): State<Shared>, Query(param): Query<GetTokenParam>) -> Response {
+    if let Some(resp) = script(&state, Endpoint::GetToken).await {
+        return resp;
+    }
+    let mut state = state.lock().unwrap();
+    if state.apps.get(&param.appkey) != Some(&param.appsecret) {
+        return Fault::errcode(40089, "不合法的corpid或corpsecret").into_response();
+    }
+    state.seq += 1;
+    let token = format!("mock_token_{}", state.seq);
+    state.tokens.insert(token.clone());
+    Json(json!({ "errcode": 0, "errmsg": "ok", "access_token": token, "expires_in": 7200 })).into_response()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiTokenBody {
+    app_key: String,
+    app_secret: String
+}
+
+async fn api_token(State(state): State<Shared>, Json(body): Json<ApiTokenBody>) -> Response {
+    if let Some(resp) = script(&state, Endpoint::ApiToken).await {
+        return resp;
+    }
+    let mut state = state.lock().unwrap();
+    if state.apps.get(&body.app_key) != Some(&body.app_secret) {
+        return Fault::api(400, "invalidClientSecret", "应用凭证无效").into_response();
+    }
+    state.seq += 1;
+    let token = format!("mock_api_token_{}", state.seq);
+    state.tokens.insert(token.clone());
+    Json(json!({ "accessToken": token, "expireIn": 7200 })).into_response()
+}
+
+#[derive(Deserialize)]
+struct GetByMobileParam {
+    access_token: String,
+    mobile: String
+}
+
+async fn getbymobile(State(state): State<Shared>, Query(param): Query<GetByMobileParam>) -> Response {
+    if let Some(resp) = script(&state, Endpoint::GetByMobile).await {
+        return resp;
+    }
+    let state = state.lock().unwrap();
+    if !state.tokens.contains(&param.access_token) {
+        return Fault::errcode(40014, "不合法的access_token").into_response();
+    }
+    match state.users.get(&param.mobile) {
+        Some(userid) => {
+            Json(json!({
+                "errcode": 0,
+                "errmsg": "ok",
+                "result": { "userid": userid },
+                "request_id": "mock"
+            }))
+            .into_response()
+        },
+        None => Fault::errcode(60121, "找不到该用户").into_response()
+    }
+}
+
+#[derive(Deserialize)]
+struct UserGetBody {
+    userid: String
+}
+
+async fn user_get(
+    State(state): State<Shared>,
+    Query(param): Query<AccessTokenParam>,
+    Json(body): Json<UserGetBody>
+) -> Response {
+    if let Some(resp) = script(&state, Endpoint::UserGet).await {
+        return resp;
+    }
+    let state = state.lock().unwrap();
+    if !state.tokens.contains(&param.access_token) {
+        return Fault::errcode(40014, "不合法的access_token").into_response();
+    }
+    match state.users.iter().find(|(_, userid)| **userid == body.userid) {
+        Some((mobile, userid)) => {
+            Json(json!({
+                "errcode": 0,
+                "errmsg": "ok",
+                "result": { "userid": userid, "name": userid, "mobile": mobile, "unionid": format!("union_{}", userid) },
+                "request_id": "mock"
+            }))
+            .into_response()
+        },
+        None => Fault::errcode(60121, "找不到该用户").into_response()
+    }
+}
+
+#[derive(Deserialize)]
+struct MediaUploadParam {
+    access_token: String,
+    r#type: String
+}
+
+async fn media_upload(
+    State(state): State<Shared>,
+    Query(param): Query<MediaUploadParam>,
+    body: Bytes
+) -> Response {
+    if let Some(resp) = script(&state, Endpoint::MediaUpload).await {
+        return resp;
+    }
+    let mut state = state.lock().unwrap();
+    if !state.tokens.contains(&param.access_token) {
+        return Fault::errcode(40014, "不合法的access_token").into_response();
+    }
+    state.seq += 1;
+    let media_id = format!("@mock_media_{}", state.seq);
+    state.media.push(Media {
+        media_id: media_id.clone(),
+        media_type: param.r#type.clone(),
+        body: body.to_vec()
+    });
+    Json(json!({
+        "errcode": 0,
+        "errmsg": "ok",
+        "type": param.r#type,
+        "media_id": media_id,
+        "created_at": 1716616252000i64
+    }))
+    .into_response()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BatchSendBody {
+    robot_code: String,
+    user_ids: Vec<String>,
+    msg_key: String,
+    msg_param: String
+}
+
+async fn batch_send(
+    State(state): State<Shared>,
+    headers: HeaderMap,
+    Json(body): Json<BatchSendBody>
+) -> Response {
+    if let Some(resp) = script(&state, Endpoint::BatchSend).await {
+        return resp;
+    }
+    let mut state = state.lock().unwrap();
+    if let Some(resp) = check_api_token(&state, &headers) {
+        return resp;
+    }
+    let msg_param = match serde_json::from_str(&body.msg_param) {
+        Ok(v) => v,
+        Err(_) => {
+            return Fault::api(400, "invalidParameter.msgParam", "msgParam不是合法的JSON").into_response();
+        }
+    };
+    let invalid: Vec<String> =
+        body.user_ids.iter().filter(|id| !state.users.values().any(|v| v == *id)).cloned().collect();
+    state.seq += 1;
+    let process_query_key = format!("mock_pqk_{}", state.seq);
+    state.sent.push(SentMsg {
+        process_query_key: process_query_key.clone(),
+        robot_code: body.robot_code,
+        user_ids: body.user_ids,
+        msg_key: body.msg_key,
+        msg_param,
+        recalled: false
+    });
+    Json(json!({
+        "processQueryKey": process_query_key,
+        "invalidStaffIdList": invalid,
+        "flowControlledStaffIdList": []
+    }))
+    .into_response()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BatchRecallBody {
+    process_query_keys: Vec<String>
+}
+
+async fn batch_recall(
+    State(state): State<Shared>,
+    headers: HeaderMap,
+    Json(body): Json<BatchRecallBody>
+) -> Response {
+    if let Some(resp) = script(&state, Endpoint::BatchRecall).await {
+        return resp;
+    }
+    let mut state = state.lock().unwrap();
+    if let Some(resp) = check_api_token(&state, &headers) {
+        return resp;
+    }
+    let mut success = vec![];
+    let mut failed = HashMap::new();
+    for key in body.process_query_keys {
+        match state.sent.iter_mut().find(|msg| msg.process_query_key == key) {
+            Some(msg) => {
+                msg.recalled = true;
+                success.push(key);
+            },
+            None => {
+                failed.insert(key, "消息不存在".to_owned());
+            }
+        }
+    }
+    Json(json!({ "successResult": success, "failedResult": failed })).into_response()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReadStatusParam {
+    process_query_key: String
+}
+
+async fn read_status(
+    State(state): State<Shared>,
+    headers: HeaderMap,
+    Query(param): Query<ReadStatusParam>
+) -> Response {
+    if let Some(resp) = script(&state, Endpoint::ReadStatus).await {
+        return resp;
+    }
+    let state = state.lock().unwrap();
+    if let Some(resp) = check_api_token(&state, &headers) {
+        return resp;
+    }
+    let msg = match state.sent.iter().find(|msg| msg.process_query_key == param.process_query_key) {
+        Some(msg) => msg,
+        None => return Fault::api(400, "invalidParameter.processQueryKey", "消息不存在").into_response()
+    };
+    let list: Vec<Value> = msg
+        .user_ids
+        .iter()
+        .map(|userid| {
+            let read = state.read.contains(&(msg.process_query_key.clone(), userid.clone()));
+            json!({
+                "name": userid,
+                "userId": userid,
+                "readStatus": if read { "READ" } else { "UNREAD" },
+                "readTimestamp": if read { 1716616252000i64 } else { 0 }
+            })
+        })
+        .collect();
+    Json(json!({ "sendStatus": "SUCCESS", "messageReadInfoList": list })).into_response()
+}
+
+#[derive(Deserialize)]
+struct WebhookParam {
+    access_token: String,
+    timestamp: Option<String>,
+    sign: Option<String>
+}
+
+async fn webhook_send(
+    State(state): State<Shared>,
+    Query(param): Query<WebhookParam>,
+    Json(body): Json<Value>
+) -> Response {
+    if let Some(resp) = script(&state, Endpoint::Webhook).await {
+        return resp;
+    }
+    let mut state = state.lock().unwrap();
+    if param.access_token != "mock_webhook" {
+        return Fault::errcode(300001, "token is not exist").into_response();
+    }
+    if let Some(secret) = &state.webhook_secret {
+        let signed = match (&param.timestamp, &param.sign) {
+            (Some(timestamp), Some(sign)) => sendmsg::dingtalk::webhook_sign(secret, timestamp) == *sign,
+            _ => false
+        };
+        if !signed {
+            return Fault::errcode(310000, "sign not match").into_response();
+        }
+    }
+    state.webhook.push(body);
+    Json(json!({ "errcode": 0, "errmsg": "ok" })).into_response()
+}
+
+#[derive(Deserialize)]
+struct AccessTokenParam {
+    access_token: String
+}
+
+async fn work_notice(
+    State(state): State<Shared>,
+    Query(param): Query<AccessTokenParam>,
+    Json(body): Json<Value>
+) -> Response {
+    if let Some(resp) = script(&state, Endpoint::WorkNotice).await {
+        return resp;
+    }
+    let mut state = state.lock().unwrap();
+    if !state.tokens.contains(&param.access_token) {
+        return Fault::errcode(40014, "不合法的access_token").into_response();
+    }
+    state.seq += 1;
+    let task_id = state.seq;
+    state.work_notices.push(body);
+    Json(json!({ "errcode": 0, "errmsg": "ok", "task_id": task_id, "request_id": "mock" })).into_response()
+}
+
+async fn sms_send(State(state): State<Shared>, headers: HeaderMap, Json(body): Json<Value>) -> Response {
+    if let Some(resp) = script(&state, Endpoint::Sms).await {
+        return resp;
+    }
+    let mut state = state.lock().unwrap();
+    state.sms.push((headers, body));
+    Json(json!({ "code": "OK", "bizId": "mock" })).into_response()
+}
+
+async fn interactive_card_send(
+    State(state): State<Shared>,
+    headers: HeaderMap,
+    Json(body): Json<Value>
+) -> Response {
+    if let Some(resp) = script(&state, Endpoint::InteractiveCardSend).await {
+        return resp;
+    }
+    let mut state = state.lock().unwrap();
+    if let Some(resp) = check_api_token(&state, &headers) {
+        return resp;
+    }
+    state.seq += 1;
+    let process_query_key = format!("mock_pqk_{}", state.seq);
+    state.cards.push(body);
+    Json(json!({ "success": true, "result": { "processQueryKey": process_query_key } })).into_response()
+}
+
+async fn interactive_card_update(
+    State(state): State<Shared>,
+    headers: HeaderMap,
+    Json(body): Json<Value>
+) -> Response {
+    if let Some(resp) = script(&state, Endpoint::InteractiveCardUpdate).await {
+        return resp;
+    }
+    let mut state = state.lock().unwrap();
+    if let Some(resp) = check_api_token(&state, &headers) {
+        return resp;
+    }
+    state.card_updates.push(body);
+    Json(json!({ "success": true, "result": true })).into_response()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OperatorParam {
+    operator_id: String
+}
+
+async fn todo_create(
+    State(state): State<Shared>,
+    Path(unionid): Path<String>,
+    Query(param): Query<OperatorParam>,
+    headers: HeaderMap,
+    Json(body): Json<Value>
+) -> Response {
+    if let Some(resp) = script(&state, Endpoint::TodoCreate).await {
+        return resp;
+    }
+    let mut state = state.lock().unwrap();
+    if let Some(resp) = check_api_token(&state, &headers) {
+        return resp;
+    }
+    if param.operator_id != unionid {
+        return Fault::api(403, "forbidden.operator", "无权限").into_response();
+    }
+    //相同业务ID返回已创建的待办
+    if let Some(todo) = body["sourceId"].as_str().and_then(|source_id| {
+        state.todos.iter().find(|v| v.unionid == unionid && !v.deleted && v.body["sourceId"] == source_id)
+    }) {
+        return Json(json!({ "id": todo.id, "subject": todo.body["subject"], "done": todo.done }))
+            .into_response();
+    }
+    state.seq += 1;
+    let id = format!("mock_todo_{}", state.seq);
+    state.todos.push(MockTodo {
+        id: id.clone(),
+        unionid,
+        body: body.clone(),
+        done: false,
+        deleted: false
+    });
+    Json(json!({ "id": id, "subject": body["subject"], "done": false })).into_response()
+}
+
+/// 更新或删除待办
+fn todo_modify(state: &mut MockState, unionid: &str, id: &str, f: impl FnOnce(&mut MockTodo)) -> Response {
+    match state.todos.iter_mut().find(|v| v.id == id && v.unionid == unionid && !v.deleted) {
+        Some(todo) => {
+            f(todo);
+            Json(json!({ "result": true })).into_response()
+        },
+        None => Fault::api(404, "taskNotExist", "待办不存在").into_response()
+    }
+}
+
+async fn todo_update(
+    State(state): State<Shared>,
+    Path((unionid, id)): Path<(String, String)>,
+    headers: HeaderMap,
+    Json(body): Json<Value>
+) -> Response {
+    if let Some(resp) = script(&state, Endpoint::TodoUpdate).await {
+        return resp;
+    }
+    let mut state = state.lock().unwrap();
+    if let Some(resp) = check_api_token(&state, &headers) {
+        return resp;
+    }
+    todo_modify(&mut state, &unionid, &id, |todo| todo.done = body["done"].as_bool().unwrap_or(todo.done))
+}
+
+async fn todo_delete(
+    State(state): State<Shared>,
+    Path((unionid, id)): Path<(String, String)>,
+    headers: HeaderMap
+) -> Response {
+    if let Some(resp) = script(&state, Endpoint::TodoDelete).await {
+        return resp;
+    }
+    let mut state = state.lock().unwrap();
+    if let Some(resp) = check_api_token(&state, &headers) {
+        return resp;
+    }
+    todo_modify(&mut state, &unionid, &id, |todo| todo.deleted = true)
+}
@@ -0,0 +1,56 @@
+use sendmsg::{phone, Error};
+
+#[test]
+fn normalize() {
+    for phone in [
+        "13800000000",
+        " 138 0000 0000 ",
+        "138-0000-0000",
+        "+8613800000000",
+        "+86 138 0000 0000",
+        "+86-(138)-0000-0000",
+        "008613800000000",
+        "8613800000000"
+    ] {
+        assert_eq!(phone::normalize(phone).unwrap(), "13800000000", "{}", phone);
+    }
+    assert_eq!(phone::normalize("+852 6123 4567").unwrap(), "+852-61234567");
+    assert_eq!(phone::normalize("00853-66123456").unwrap(), "+853-66123456");
+    assert_eq!(phone::normalize("+886 0912 345 678").unwrap(), "+886-912345678");
+}
+
+#[test]
+fn reject_invalid() {
+    for phone in [
+        "",
+        "  ",
+        "0571-88888888",
+        "88888888",
+        "1380000000",
+        "138000000001",
+        "12800000000",
+        "+8612800000000",
+        "+1 415 555 0100",
+        "+852 123",
+        "138O0000000",
+        "13800000000 转 123"
+    ] {
+        match phone::normalize(phone) {
+            Err(Error::InvalidPhone {
+                ..
+            }) => {},
+            rv => panic!("unexpected: {} {:?}", phone, rv)
+        }
+    }
+}
+
+#[test]
+fn split() {
+    assert_eq!(phone::split("13800000000"), vec!["13800000000"]);
+    assert_eq!(phone::split(" 13800000000 ,13900000000；+86 137 0000 0000|"), vec![
+        "13800000000",
+        "13900000000",
+        "+86 137 0000 0000"
+    ]);
+    assert!(phone::split(" , ").is_empty());
+}
@@ -0,0 +1,123 @@
+#![allow(dead_code)]
+
+use sendmsg::{
+    config::PriorityConfig, priority::{Priorities, Scheduler}
+};
+use std::collections::HashMap;
+
+#[test]
+fn levels() {
+    let priorities = Priorities::new(&PriorityConfig {
+        levels: HashMap::from([("sampleActionCard".to_owned(), 5), ("payment'approve".to_owned(), 9)]),
+        reserved_share: 0.25,
+        ..Default::default()
+    })
+    .unwrap();
+    assert_eq!(priorities.level("sampleActionCard"), 5);
+    assert_eq!(priorities.level("sampleText"), 0);
+    assert_eq!(
+        priorities.sql_expr(),
+        "COALESCE(priority, CASE flowmsgtype WHEN N'payment''approve' THEN 9 WHEN N'sampleActionCard' THEN 5 ELSE 0 END)"
+    );
+    assert_eq!(Priorities::new(&PriorityConfig::default()).unwrap().sql_expr(), "COALESCE(priority, 0)");
+
+    let invalid = PriorityConfig {
+        levels: HashMap::from([("sampleText".to_owned(), 10)]),
+        ..Default::default()
+    };
+    assert!(Priorities::new(&invalid).is_err());
+    let invalid = PriorityConfig {
+        reserved_share: 1.0,
+        ..Default::default()
+    };
+    assert!(Priorities::new(&invalid).is_err());
+}
+
+#[test]
+fn reserve() {
+    let mut scheduler = Scheduler::new(0.2);
+    assert_eq!(scheduler.reserve(10), 2);
+    assert_eq!(scheduler.reserve(20), 4);
+
+    //每次只领取1条时,每5次为普通优先级保留1条
+    let mut scheduler = Scheduler::new(0.2);
+    let reserved: u32 = (0..10).map(|_| scheduler.reserve(1)).sum();
+    assert_eq!(reserved, 2);
+
+    let mut scheduler = Scheduler::new(0.0);
+    assert_eq!((0..100).map(|_| scheduler.reserve(3)).sum::<u32>(), 0);
+}
+
+/// 模拟持续写入优先级9的记录时的领取,返回各优先级全部领取完成的轮次
+///
+/// 每轮10秒,写入`burst`条优先级9的记录后领取10条,排序与`claim`一致
+fn simulate(aging: u64, burst: u32, rounds: u32) -> HashMap<u8, u32> {
+    let priorities = Priorities::new(&PriorityConfig {
+        aging,
+        ..Default::default()
+    })
+    .unwrap();
+    let mut scheduler = Scheduler::new(priorities.reserved_share());
+    //(id, 优先级, 写入时间)
+    let mut queue: Vec<(u32, u8, u64)> = vec![];
+    let mut id = 0;
+    for level in [5, 5, 5, 0, 0, 0] {
+        id += 1;
+        queue.push((id, level, 0));
+    }
+    let mut done = HashMap::new();
+    for round in 1..=rounds {
+        let now = round as u64 * 10;
+        for _ in 0..burst {
+            id += 1;
+            queue.push((id, 9, now));
+        }
+        let mut rest = 10;
+        let reserved = scheduler.reserve(10);
+        let mut normal: Vec<usize> =
+            (0..queue.len()).filter(|i| queue[*i].1 == 0).take(reserved as usize).collect();
+        rest -= normal.len();
+        let mut order: Vec<usize> = (0..queue.len()).filter(|i| !normal.contains(i)).collect();
+        order.sort_by_key(|i| {
+            (std::cmp::Reverse(priorities.aged(queue[*i].1, now - queue[*i].2)), queue[*i].0)
+        });
+        normal.extend(order.into_iter().take(rest));
+        normal.sort_unstable_by(|a, b| b.cmp(a));
+        for i in normal {
+            queue.remove(i);
+        }
+        for level in [0, 5] {
+            if !done.contains_key(&level) && queue.iter().all(|v| v.1 != level) {
+                done.insert(level, round);
+            }
+        }
+    }
+    done
+}
+
+#[test]
+fn aging() {
+    let priorities = Priorities::new(&PriorityConfig::default()).unwrap();
+    assert_eq!(priorities.aged(5, 59), 5);
+    assert_eq!(priorities.aged(5, 240), 9);
+    assert_eq!(
+        priorities.order_expr(),
+        "(COALESCE(priority, 0) + DATEDIFF(SECOND, created_at, GETDATE()) / 60)"
+    );
+    let fixed = Priorities::new(&PriorityConfig {
+        aging: 0,
+        ..Default::default()
+    })
+    .unwrap();
+    assert_eq!(fixed.aged(5, 3600), 5);
+    assert_eq!(fixed.order_expr(), "COALESCE(priority, 0)");
+
+    //持续写入的优先级9记录占满领取名额时,优先级5的记录等待提升后仍能领取
+    let done = simulate(60, 10, 100);
+    assert!(done[&0] <= 10, "{:?}", done);
+    assert!(done[&5] <= 30, "{:?}", done);
+    //不提升时优先级5的记录始终得不到领取,优先级0依靠保留名额
+    let done = simulate(0, 10, 100);
+    assert!(done.contains_key(&0), "{:?}", done);
+    assert!(!done.contains_key(&5), "{:?}", done);
+}
@@ -0,0 +1,147 @@
+#![allow(dead_code)]
+
+mod mock;
+use arc_swap::ArcSwap;
+use mock::{MockDingTalk, MOBILE};
+use sendmsg::{
+    config::ReloadConfig, http::HttpClient, reload::Reloader, sender::Delivery, shutdown, Config, User
+};
+use std::{fs, path::PathBuf, sync::Arc, time::Duration};
+
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!("sendmsg_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(path.join("templates")).unwrap();
+        TempDir(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) { let _ = fs::remove_dir_all(&self.0); }
+}
+
+fn write_config(dir: &TempDir, app: &str) -> PathBuf {
+    let path = dir.0.join("sendmsg.toml");
+    let text = format!(
+        r#"
+db_conn_str = "server=tcp:127.0.0.1,1"
+
+[templates]
+dir = "{}"
+
+[[apps]]
+name = "{}"
+appkey = "dingmockappkey"
+appsecret = "mocksecret"
+default = true
+"#,
+        dir.0.join("templates").display(),
+        app
+    );
+    fs::write(&path, text).unwrap();
+    path
+}
+
+fn user() -> User {
+    User::new(
+        "苏宁绿".to_owned(),
+        "EBS20240525000001".to_owned(),
+        "sampleMarkdown".to_owned(),
+        "您有待办任务需要处理".to_owned(),
+        MOBILE.to_owned(),
+        "dingmockappkey".to_owned()
+    )
+}
+
+async fn setup(dir: &TempDir) -> (MockDingTalk, Arc<ArcSwap<Delivery>>, Reloader) {
+    let mock = MockDingTalk::start().await;
+    mock.add_app("dingmockappkey", "mocksecret");
+    mock.add_user(MOBILE, "manager4220");
+    let path = write_config(dir, "a");
+    let api = mock.api();
+    let http = HttpClient::new(&Default::default()).unwrap();
+    let delivery = Delivery::from_config(&Config::load(&path).unwrap(), &api, http.clone()).unwrap();
+    let delivery = Arc::new(ArcSwap::from_pointee(delivery));
+    let reloader = Reloader::new(path, api, http, delivery.clone(), ReloadConfig {
+        enabled: true,
+        debounce: 100
+    });
+    (mock, delivery, reloader)
+}
+
+fn app_name(delivery: &ArcSwap<Delivery>) -> String { delivery.load().apps().apps()[0].name().to_owned() }
+
+#[tokio::test]
+async fn reload() {
+    let dir = TempDir::new("reload");
+    let (_mock, delivery, reloader) = setup(&dir).await;
+    assert_eq!(app_name(&delivery), "a");
+
+    write_config(&dir, "b");
+    fs::write(dir.0.join("templates/sampleMarkdown.md"), "### {flownumber}\n{flowmsg}").unwrap();
+    reloader.reload().unwrap();
+    assert_eq!(app_name(&delivery), "b");
+    let rv = delivery.load().preview(&user()).await.unwrap();
+    assert_eq!(
+        rv["payload"]["msgParam"],
+        serde_json::json!({ "title": "EBS20240525000001", "text": "### EBS20240525000001\n您有待办任务需要处理" })
+            .to_string()
+    );
+
+    //加载失败时保留当前配置
+    //格式错误、未知参数或未转义的花括号
+    for text in ["{flowmsg", "{unknown} {flowmsg}", "```json\n{\"a\": 1}\n```", "{remark}"] {
+        fs::write(dir.0.join("templates/sampleText.txt"), text).unwrap();
+        assert!(reloader.reload().is_err(), "{}", text);
+    }
+    fs::write(dir.0.join("templates/sampleText.txt"), "```json\n{{\"a\": \"{params.remark}\"}}\n```")
+        .unwrap();
+    assert!(reloader.reload().is_ok());
+    fs::remove_file(dir.0.join("templates/sampleText.txt")).unwrap();
+    fs::write(dir.0.join("sendmsg.toml"), "db_conn_str = ").unwrap();
+    assert!(reloader.reload().is_err());
+    assert_eq!(app_name(&delivery), "b");
+}
+
+#[tokio::test]
+async fn watch() {
+    let dir = TempDir::new("watch");
+    let (_mock, delivery, reloader) = setup(&dir).await;
+    let (trigger, shutdown) = shutdown::channel();
+    let template_dir = dir.0.join("templates").display().to_string();
+    let task = tokio::spawn(async move { reloader.run(Some(&template_dir), shutdown).await });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    write_config(&dir, "b");
+    let mut reloaded = false;
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        if app_name(&delivery) == "b" {
+            reloaded = true;
+            break;
+        }
+    }
+    assert!(reloaded);
+
+    trigger.trigger();
+    task.await.unwrap().unwrap();
+}
+
+#[test]
+fn validate() {
+    let dir = TempDir::new("validate");
+    let path = write_config(&dir, "mock");
+    assert!(Config::load(&path).is_ok());
+    //启用HTTP消息写入或聊天审批需要启用HTTP服务
+    for section in ["[ingest]\nenabled = true", "[approval]\nenabled = true"] {
+        let text = fs::read_to_string(&path).unwrap();
+        fs::write(&path, format!("{}\n{}\n", text, section)).unwrap();
+        assert!(Config::load(&path).is_err(), "{}", section);
+        fs::write(&path, format!("{}\n{}\n\n[server]\nenabled = true\n", text, section)).unwrap();
+        assert!(Config::load(&path).is_ok(), "{}", section);
+        fs::write(&path, text).unwrap();
+    }
+}
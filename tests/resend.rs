@@ -0,0 +1,63 @@
+#![allow(dead_code)]
+
+use sendmsg::{
+    outbox::Outbox, resend::{self, ResendFilter}, status::Status, Error
+};
+use std::time::Duration;
+
+#[test]
+fn parse_time() {
+    let t = resend::parse_time("2024-05-25 08:30").unwrap();
+    assert_eq!(t.to_string(), "2024-05-25 08:30:00");
+    assert_eq!(resend::parse_time("2024-05-25").unwrap().to_string(), "2024-05-25 00:00:00");
+    assert_eq!(resend::parse_time(" 2024-05-25T08:30:15 ").unwrap().to_string(), "2024-05-25 08:30:15");
+    assert!(resend::parse_time("25/05/2024").is_err());
+}
+
+#[test]
+fn filter() {
+    let filter = ResendFilter::default();
+    assert!(filter.validate().is_ok());
+    assert_eq!(filter.errcode_pattern(), None);
+
+    let filter = ResendFilter {
+        errcode: Some("Qps_Limit[1]".to_owned()),
+        statuses: vec![Status::Failed, Status::Undeliverable],
+        ..Default::default()
+    };
+    assert!(filter.validate().is_ok());
+    assert_eq!(filter.errcode_pattern().unwrap(), "%Qps[_]Limit[[]1]%");
+
+    //已发送的记录不能重新发送
+    let filter = ResendFilter {
+        statuses: vec![Status::Sent],
+        ..Default::default()
+    };
+    assert!(matches!(filter.validate(), Err(Error::Invalid(..))));
+
+    let filter = ResendFilter {
+        since: Some(resend::parse_time("2024-05-26").unwrap()),
+        until: Some(resend::parse_time("2024-05-25").unwrap()),
+        ..Default::default()
+    };
+    assert!(filter.validate().is_err());
+}
+
+#[tokio::test]
+async fn invalid_filter() {
+    //条件无效时不连接数据库
+    let pool = mssql::Pool::builder()
+        .connect_timeout(1)
+        .build("server=tcp:127.0.0.1,1;user=sa;password=mock;TrustServerCertificate=true")
+        .unwrap();
+    let outbox = Outbox::new(pool, "test".to_owned(), Duration::from_secs(300));
+    let filter = ResendFilter {
+        statuses: vec![],
+        ..Default::default()
+    };
+    let mut out = vec![];
+    let err = resend::resend(&outbox, &filter, 10, None, &mut out).await.unwrap_err();
+    assert!(matches!(err, Error::Invalid(..)), "{:?}", err);
+    assert!(out.is_empty());
+    assert!(outbox.count_resend(&ResendFilter::default()).await.unwrap_err().is_db_unavailable());
+}
@@ -0,0 +1,37 @@
+use sendmsg::secret::{self, SecretKey};
+
+#[test]
+fn encrypt_decrypt() {
+    let key = SecretKey::generate();
+    let value = secret::encrypt(&key, "test-appsecret").unwrap();
+    assert!(value.starts_with("enc:"));
+    assert_eq!(secret::resolve(&value, Some(&key)).unwrap(), "test-appsecret");
+    //每次加密使用随机nonce
+    assert_ne!(secret::encrypt(&key, "test-appsecret").unwrap(), value);
+
+    //密钥可还原
+    let key2 = SecretKey::from_base64(&key.to_base64()).unwrap();
+    assert_eq!(secret::decrypt(&key2, &value).unwrap(), "test-appsecret");
+
+    //密钥不匹配或未提供密钥
+    assert!(secret::resolve(&value, Some(&SecretKey::generate())).is_err());
+    assert!(secret::resolve(&value, None).is_err());
+    assert!(SecretKey::from_base64("c2hvcnQ=").is_err());
+}
+
+#[test]
+fn resolve_reference() {
+    std::env::set_var("SENDMSG_TEST_APPSECRET", "from_env");
+    assert_eq!(secret::resolve("env:SENDMSG_TEST_APPSECRET", None).unwrap(), "from_env");
+    assert!(secret::resolve("env:SENDMSG_TEST_NOT_EXISTS", None).is_err());
+
+    let path = std::env::temp_dir().join(format!("sendmsg_secret_{}", std::process::id()));
+    std::fs::write(&path, "from_file\n").unwrap();
+    let value = format!("secret://file{}", path.display());
+    assert!(secret::is_reference(&value));
+    assert_eq!(secret::resolve(&value, None).unwrap(), "from_file");
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(!secret::is_reference("plain"));
+    assert_eq!(secret::resolve("plain", None).unwrap(), "plain");
+}
@@ -0,0 +1,229 @@
+#![allow(dead_code)]
+
+mod mock;
+use mock::{Endpoint, Fault, MockDingTalk, MOBILE};
+use sendmsg::{
+    app::AppRegistry, card::ActionCard, channel::Channels, config::{AppConfig, CardConfig, ChainConfig, Channel, FailoverConfig, FailoverOn, SmsConfig}, http::HttpClient, sender::Delivery, template::Templates, Error, User
+};
+use std::collections::HashMap;
+
+const APPKEY: &str = "dingmockappkey";
+const USERID: &str = "manager4220";
+
+async fn setup() -> (MockDingTalk, Delivery) { setup_with(|_| FailoverConfig::default()).await }
+
+async fn setup_with(failover: impl FnOnce(&MockDingTalk) -> FailoverConfig) -> (MockDingTalk, Delivery) {
+    let mock = MockDingTalk::start().await;
+    mock.add_app(APPKEY, "mocksecret");
+    mock.add_user(MOBILE, USERID);
+    let apps = AppRegistry::new(&mock.api(), &[AppConfig {
+        name: "mock".to_owned(),
+        appkey: APPKEY.to_owned(),
+        appsecret: "mocksecret".to_owned(),
+        robots: vec![],
+        sources: vec![],
+        default: true,
+        agent_id: Some(1001)
+    }])
+    .unwrap();
+    let channels = Channels::new(&failover(&mock), HttpClient::new(&Default::default()).unwrap()).unwrap();
+    let delivery = Delivery::new(
+        apps,
+        ActionCard::new(&CardConfig::default()).unwrap(),
+        Templates::default(),
+        channels,
+        2
+    );
+    (mock, delivery)
+}
+
+fn user(flowmsgtype: &str, mobile: &str) -> User {
+    let mut user = User::new(
+        "苏宁绿".to_owned(),
+        "EBS20240525000001".to_owned(),
+        flowmsgtype.to_owned(),
+        "您有待办任务需要处理".to_owned(),
+        mobile.to_owned(),
+        APPKEY.to_owned()
+    );
+    user.id = 7;
+    user
+}
+
+#[tokio::test]
+async fn preview() {
+    let (mock, delivery) = setup().await;
+
+    let rv = delivery.preview(&user("sampleText", MOBILE)).await.unwrap();
+    assert_eq!(rv["id"], 7);
+    assert_eq!(rv["app"], "mock");
+    assert!(rv["url"].as_str().unwrap().ends_with("/v1.0/robot/oToMessages/batchSend"));
+    assert_eq!(rv["payload"]["robotCode"], APPKEY);
+    assert_eq!(rv["payload"]["userIds"][0], USERID);
+    assert_eq!(rv["payload"]["msgKey"], "sampleText");
+    assert_eq!(rv["payload"]["msgParam"], r#"{"content":"您有待办任务需要处理"}"#);
+    assert!(rv["payload"].get("accessToken").is_none());
+
+    //附件不上传
+    let mut file = user("sampleFile", MOBILE);
+    file.attachment_name = Some("审批单.pdf".to_owned());
+    file.attachment = Some(b"%PDF".to_vec());
+    let rv = delivery.preview(&file).await.unwrap();
+    assert!(rv["payload"]["msgParam"].as_str().unwrap().contains("@dry-run"));
+
+    //接收人无法解析
+    assert!(delivery.preview(&user("sampleText", "13800000000")).await.is_err());
+
+    assert_eq!(mock.calls(Endpoint::BatchSend), 0);
+    assert_eq!(mock.calls(Endpoint::MediaUpload), 0);
+    assert_eq!(mock.calls(Endpoint::GetByMobile), 3);
+}
+
+#[tokio::test]
+async fn multiple_recipients() {
+    let (mock, delivery) = setup().await;
+    mock.add_user("13912345678", "user2");
+    mock.add_user("+852-61234567", "user3");
+
+    //无效号码不调用接口,未找到的用户跳过,重复的用户只发送一次
+    let phones = "+86 138-0013-8000；13912345678, 0571-88888888 | 13800000000,+852 6123 4567,8613800138000";
+    let rv = delivery.preview(&user("sampleText", phones)).await.unwrap();
+    assert_eq!(rv["payload"]["userIds"], serde_json::json!([USERID, "user2", "user3"]));
+    assert_eq!(mock.calls(Endpoint::GetByMobile), 5);
+
+    //全部无效
+    let err = delivery.preview(&user("sampleText", "0571-88888888")).await.unwrap_err();
+    assert!(matches!(err, Error::InvalidPhone { .. }), "{:?}", err);
+    assert_eq!(mock.calls(Endpoint::GetByMobile), 5);
+
+    let phones = (0..21).map(|i| format!("139000000{:02}", i)).collect::<Vec<_>>().join(",");
+    assert!(delivery.preview(&user("sampleText", &phones)).await.is_err());
+}
+
+#[tokio::test]
+async fn token_expired() {
+    let (mock, delivery) = setup().await;
+    assert!(delivery.send(&user("sampleText", MOBILE)).await.result.is_ok());
+
+    //token失效时重新获取对应版本的token并重试
+    mock.expire_tokens();
+    let outcome = delivery.send(&user("sampleText", MOBILE)).await;
+    assert!(outcome.result.is_ok(), "{:?}", outcome.result);
+    assert_eq!(mock.sent().len(), 2);
+    assert_eq!(mock.calls(Endpoint::GetToken), 2);
+    assert_eq!(mock.calls(Endpoint::ApiToken), 2);
+    assert_eq!(mock.calls(Endpoint::GetByMobile), 3);
+    assert_eq!(mock.calls(Endpoint::BatchSend), 3);
+}
+
+#[tokio::test]
+async fn read_and_recall() {
+    let (mock, delivery) = setup().await;
+    mock.add_user("13912345678", "user2");
+    let mut user = user("sampleText", &format!("{},13912345678", MOBILE));
+    let outcome = delivery.send(&user).await;
+    user.channel = Some(outcome.channel.as_str().to_owned());
+    user.process_query_key = Some(outcome.result.unwrap());
+
+    assert_eq!(delivery.read_status(&user).await.unwrap(), (0, 2));
+    let pqk = user.process_query_key.clone().unwrap();
+    mock.mark_read(&pqk, USERID);
+    mock.mark_read(&pqk, "user2");
+    assert_eq!(delivery.read_status(&user).await.unwrap(), (2, 2));
+
+    delivery.recall(&user).await.unwrap();
+    assert!(mock.sent()[0].recalled);
+
+    //不是机器人单聊消息
+    let mut notice = user.clone();
+    notice.channel = Some("work_notice".to_owned());
+    assert!(delivery.recall(&notice).await.is_err());
+    notice.channel = Some("robot".to_owned());
+    notice.process_query_key = Some("unknown".to_owned());
+    assert!(delivery.recall(&notice).await.is_err());
+    assert_eq!(mock.calls(Endpoint::BatchRecall), 2);
+}
+
+#[tokio::test]
+async fn failover() {
+    let (mock, delivery) = setup_with(|mock| {
+        FailoverConfig {
+            chains: vec![ChainConfig {
+                flowmsgtypes: vec![],
+                channels: vec![Channel::Robot, Channel::WorkNotice, Channel::Sms],
+                on: vec![FailoverOn::Undeliverable, FailoverOn::Unavailable]
+            }],
+            email: None,
+            sms: Some(SmsConfig {
+                url: mock.sms_url(),
+                params: HashMap::from([
+                    ("phone".to_owned(), "{mobile}".to_owned()),
+                    ("content".to_owned(), "{flownumber}: {flowmsg}".to_owned())
+                ]),
+                headers: HashMap::from([("x-api-key".to_owned(), "mock".to_owned())])
+            })
+        }
+    })
+    .await;
+
+    //机器人消息服务不可用,切换到工作通知
+    mock.fail_next(Endpoint::BatchSend, Fault::api(503, "ServiceUnavailable", "服务不可用"));
+    let outcome = delivery.send(&user("sampleMarkdown", MOBILE)).await;
+    assert_eq!(outcome.channel, Channel::WorkNotice);
+    assert!(outcome.result.is_ok(), "{:?}", outcome);
+    assert_eq!(outcome.trail.len(), 1);
+    assert!(outcome.trail[0].starts_with("robot: "));
+    let notices = mock.work_notices();
+    assert_eq!(notices[0]["agent_id"], 1001);
+    assert_eq!(notices[0]["userid_list"], USERID);
+    assert_eq!(notices[0]["msg"]["markdown"]["title"], "EBS20240525000001");
+
+    //未匹配到钉钉账号,切换到短信
+    let outcome = delivery.send(&user("sampleText", "13800000000")).await;
+    assert_eq!(outcome.channel, Channel::Sms);
+    assert!(outcome.result.is_ok(), "{:?}", outcome);
+    assert_eq!(outcome.trail.len(), 2);
+    let sms = mock.sms();
+    assert_eq!(sms[0].0["x-api-key"], "mock");
+    assert_eq!(sms[0].1["phone"], "13800000000");
+    assert_eq!(sms[0].1["content"], "EBS20240525000001: 您有待办任务需要处理");
+
+    //最后一个渠道失败,记录各渠道的失败原因
+    mock.fail_next(Endpoint::Sms, Fault::raw(500, "gateway error"));
+    let outcome = delivery.send(&user("sampleText", "13800000000")).await;
+    assert!(
+        matches!(
+            outcome.result,
+            Err(Error::Gateway {
+                status: 500,
+                ..
+            })
+        ),
+        "{:?}",
+        outcome
+    );
+    assert_eq!(outcome.errmsg().split("; ").count(), 3);
+
+    //不满足切换条件
+    mock.fail_next(Endpoint::BatchSend, Fault::api(400, "param.invalid", "参数错误"));
+    let outcome = delivery.send(&user("sampleText", MOBILE)).await;
+    assert_eq!(outcome.channel, Channel::Robot);
+    assert!(outcome.result.is_err());
+    assert_eq!(mock.calls(Endpoint::WorkNotice), 1);
+}
+
+#[tokio::test]
+async fn invalid_chain() {
+    let http = HttpClient::new(&Default::default()).unwrap();
+    let cfg = FailoverConfig {
+        chains: vec![ChainConfig {
+            flowmsgtypes: vec!["sampleText".to_owned()],
+            channels: vec![Channel::Robot, Channel::Email],
+            on: vec![FailoverOn::Any]
+        }],
+        ..Default::default()
+    };
+    assert!(Channels::new(&cfg, http.clone()).is_err());
+    let channels = Channels::new(&FailoverConfig::default(), http).unwrap();
+    assert_eq!(channels.chain("sampleText").0, &[Channel::Robot]);
+}
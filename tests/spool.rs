@@ -0,0 +1,159 @@
+#![allow(dead_code)]
+
+mod mock;
+use mock::MOBILE;
+use sendmsg::{
+    config::IngestConfig, ingest::Ingest, outbox::{NewMessage, Outbox}, server, shutdown, spool::Spool, Error
+};
+use serde_json::{json, Value};
+use std::{fs, path::PathBuf, sync::Arc, time::Duration};
+
+fn spool_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("sendmsg_spool_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn msg(flownumber: &str) -> NewMessage {
+    serde_json::from_value(json!({
+        "exeuser": "苏宁绿",
+        "flownumber": flownumber,
+        "flowmsgtype": "sampleText",
+        "flowmsg": "您有待办任务需要处理",
+        "userphone": MOBILE,
+        "params": { "billno": "PO-001" }
+    }))
+    .unwrap()
+}
+
+fn unreachable_pool() -> mssql::Pool {
+    mssql::Pool::builder()
+        .connect_timeout(1)
+        .build("server=tcp:127.0.0.1,1;user=sa;password=mock;TrustServerCertificate=true")
+        .unwrap()
+}
+
+fn rejected(spool: &Spool) -> usize {
+    fs::read_to_string(spool.rejected_path()).map(|v| v.lines().count()).unwrap_or_default()
+}
+
+#[tokio::test]
+async fn replay() {
+    let dir = spool_dir("replay");
+    let spool = Spool::open(&dir).unwrap();
+    assert!(spool.is_empty().await);
+    for flownumber in ["F1", "F2", "F3"] {
+        spool.append(&msg(flownumber)).await.unwrap();
+    }
+    //不完整的最后一行
+    fs::OpenOptions::new()
+        .append(true)
+        .open(spool.path())
+        .map(|mut f| std::io::Write::write_all(&mut f, b"{\"exe"))
+        .unwrap()
+        .unwrap();
+    assert_eq!(spool.len().await.unwrap(), 4);
+
+    //第二条写入失败时移到rejected,第三条数据库不可达时停止回放并保留未回放的消息
+    let pool = unreachable_pool();
+    let mut replayed = vec![];
+    let rv = spool
+        .replay(|msg| {
+            replayed.push(msg.flownumber.clone());
+            let pool = pool.clone();
+            async move {
+                match msg.flownumber.as_str() {
+                    "F2" => Err(Error::custom("conversion failed")),
+                    "F3" => pool.get().await.map(drop).map_err(Error::from),
+                    _ => Ok(())
+                }
+            }
+        })
+        .await;
+    assert!(rv.unwrap_err().is_db_unavailable());
+    assert_eq!(replayed, ["F1", "F2", "F3"]);
+    assert_eq!(spool.len().await.unwrap(), 2);
+    assert_eq!(rejected(&spool), 1);
+
+    let mut replayed = vec![];
+    let rv = spool
+        .replay(|msg| {
+            replayed.push(msg.flownumber);
+            async { Ok(()) }
+        })
+        .await
+        .unwrap();
+    assert_eq!(rv, 1);
+    assert_eq!(replayed, ["F3"]);
+    assert!(spool.is_empty().await);
+    //不完整的行
+    assert_eq!(rejected(&spool), 2);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn hold() {
+    let dir = spool_dir("hold");
+    let spool = Arc::new(Spool::open(&dir).unwrap());
+    spool.append(&msg("F1")).await.unwrap();
+
+    //写入方持有期间回放等待,仍可写入缓存
+    let guard = spool.hold().await;
+    let task = tokio::spawn({
+        let spool = spool.clone();
+        async move { spool.replay(|_| async { Ok(()) }).await }
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!task.is_finished());
+    spool.append(&msg("F2")).await.unwrap();
+    drop(guard);
+    assert_eq!(task.await.unwrap().unwrap(), 2);
+    assert!(spool.is_empty().await);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn ingest_db_unavailable() {
+    let dir = spool_dir("ingest");
+    //数据库不可达
+    let pool = unreachable_pool();
+    let outbox = Outbox::new(pool, "test".to_owned(), Duration::from_secs(300));
+    let ingest = Arc::new(Ingest::new(outbox, Spool::open(&dir).unwrap(), IngestConfig::default()));
+    let listener = server::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/messages", listener.local_addr().unwrap());
+    let router = server::ingest_router(ingest.clone(), Some("mock_token".to_owned()));
+    let (trigger, shutdown) = shutdown::channel();
+    let task = tokio::spawn(server::serve(listener, router, shutdown));
+    let client = httprequest::Client::new();
+
+    let resp = client.post(&url).json(&msg("F1")).send().await.unwrap();
+    assert_eq!(resp.status(), 401);
+
+    let mut body = serde_json::to_value(msg("F1")).unwrap();
+    body["idempotency_key"] = "erp:F1".into();
+    let resp = client.post(&url).bearer_auth("mock_token").json(&body).send().await.unwrap();
+    assert_eq!(resp.status(), 202);
+    let rv: Value = resp.json().await.unwrap();
+    assert_eq!(rv["status"], "spooled");
+    assert_eq!(rv["idempotency_key"], "erp:F1");
+
+    //缓存中有待回放的消息时直接写入缓存,未指定幂等键时自动生成
+    let resp = client.post(&url).bearer_auth("mock_token").json(&msg("F2")).send().await.unwrap();
+    assert_eq!(resp.status(), 202);
+    let rv: Value = resp.json().await.unwrap();
+    assert!(rv["idempotency_key"].as_str().unwrap().starts_with("spool:test:"));
+
+    let mut body = serde_json::to_value(msg("F3")).unwrap();
+    body["userphone"] = "".into();
+    let resp = client.post(&url).bearer_auth("mock_token").json(&body).send().await.unwrap();
+    assert_eq!(resp.status(), 400);
+
+    assert_eq!(ingest.spool().len().await.unwrap(), 2);
+    //数据库仍不可达,回放失败时保留缓存
+    assert!(ingest.replay().await.unwrap_err().is_db_unavailable());
+    assert_eq!(ingest.spool().len().await.unwrap(), 2);
+
+    trigger.trigger();
+    task.await.unwrap().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,41 @@
+#![allow(dead_code)]
+
+use sendmsg::{status::Status, Error};
+
+#[test]
+fn transitions() {
+    use Status::*;
+    let path = [Queued, Claimed, Sending, Sent, Read, Recalled];
+    for pair in path.windows(2) {
+        assert_eq!(pair[0].transition(pair[1]).unwrap(), pair[1]);
+    }
+    assert!(Sent.can_transition(Recalled));
+    assert!(Sending.can_transition(Failed));
+    assert!(Failed.can_transition(Dead));
+    assert!(Undeliverable.can_transition(Queued));
+    //租约到期重新领取
+    assert!(Sending.can_transition(Claimed));
+
+    //无效的状态变更
+    for (from, to) in
+        [(Queued, Sent), (Claimed, Sent), (Sent, Failed), (Read, Queued), (Dead, Queued), (Recalled, Read)]
+    {
+        let err = from.transition(to).unwrap_err();
+        assert!(matches!(err, Error::InvalidTransition { .. }), "{:?}", err);
+    }
+    assert_eq!(Queued.transition(Sent).unwrap_err().to_string(), "无效的状态变更, queued → sent");
+
+    assert_eq!(Status::sources(Claimed), [Queued, Claimed, Sending]);
+    assert_eq!(Status::sources(Queued), [Claimed, Failed, Undeliverable]);
+    assert!(Dead.is_final() && Recalled.is_final());
+    assert!(!Sent.is_final());
+}
+
+#[test]
+fn parse() {
+    for status in Status::ALL {
+        assert_eq!(status.as_str().parse::<Status>().unwrap(), status);
+        assert_eq!(serde_json::to_value(status).unwrap(), status.as_str());
+    }
+    assert!("unknown".parse::<Status>().is_err());
+}
@@ -0,0 +1,40 @@
+#![cfg(unix)]
+
+use sendmsg::systemd;
+use std::{os::unix::net::UnixDatagram, time::Duration};
+
+#[test]
+fn notify() {
+    //未由systemd启动时忽略
+    std::env::remove_var("NOTIFY_SOCKET");
+    assert!(!systemd::enabled());
+    systemd::ready();
+
+    let path = std::env::temp_dir().join(format!("sendmsg_notify_{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let sock = UnixDatagram::bind(&path).unwrap();
+    sock.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+    std::env::set_var("NOTIFY_SOCKET", &path);
+    assert!(systemd::enabled());
+
+    let mut buf = [0u8; 256];
+    let mut recv = || {
+        let len = sock.recv(&mut buf).unwrap();
+        String::from_utf8_lossy(&buf[..len]).into_owned()
+    };
+    systemd::ready();
+    assert_eq!(recv(), "READY=1\n");
+    systemd::status("queued: 3, in-flight: 1");
+    assert_eq!(recv(), "STATUS=queued: 3, in-flight: 1\n");
+    systemd::watchdog();
+    assert_eq!(recv(), "WATCHDOG=1\n");
+    systemd::stopping();
+    assert_eq!(recv(), "STOPPING=1\n");
+
+    std::env::set_var("WATCHDOG_USEC", "30000000");
+    std::env::set_var("WATCHDOG_PID", std::process::id().to_string());
+    assert_eq!(systemd::watchdog_timeout(), Some(Duration::from_secs(30)));
+
+    std::env::remove_var("NOTIFY_SOCKET");
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,127 @@
+#![allow(dead_code)]
+
+mod mock;
+use mock::{Endpoint, MockDingTalk, MOBILE};
+use sendmsg::{
+    app::DDApp, config::AppConfig, dingtalk::{DDTodoCreate, DDTodoDetailUrl, DDTodoTask}, todo::{self, PendingFlow, TrackedTodo}, Error
+};
+
+const APPKEY: &str = "dingmockappkey";
+const APPSECRET: &str = "mocksecret";
+
+fn flow(flownumber: &str, mobile: &str) -> PendingFlow {
+    PendingFlow {
+        flownumber: flownumber.to_owned(),
+        mobile: mobile.to_owned(),
+        subject: format!("审批 {}", flownumber),
+        ..Default::default()
+    }
+}
+
+fn tracked(id: i64, flownumber: &str, mobile: &str) -> TrackedTodo {
+    TrackedTodo {
+        id,
+        flownumber: flownumber.to_owned(),
+        mobile: mobile.to_owned(),
+        app: "mock".to_owned(),
+        unionid: "union_manager4220".to_owned(),
+        task_id: format!("task_{}", id),
+        status: "open".to_owned()
+    }
+}
+
+#[test]
+fn plan() {
+    let pending = vec![
+        flow("EBS20240525000001", MOBILE),
+        //手机号格式不同视为同一审批人
+        flow("EBS20240525000001", "+86 138-0013-8000"),
+        flow("EBS20240525000001", "13912345678"),
+        flow("EBS20240525000002", MOBILE),
+    ];
+    let open = vec![
+        tracked(1, "EBS20240525000001", MOBILE),
+        tracked(2, "EBS20240525000003", MOBILE),
+        tracked(3, "EBS20240525000002", "13912345678"),
+    ];
+    let plan = todo::plan(&pending, &open, false);
+    let create: Vec<(&str, &str)> =
+        plan.create.iter().map(|v| (v.flownumber.as_str(), v.mobile.as_str())).collect();
+    assert_eq!(create, vec![("EBS20240525000001", "13912345678"), ("EBS20240525000002", MOBILE)]);
+    let complete: Vec<i64> = plan.complete.iter().map(|v| v.id).collect();
+    assert_eq!(complete, vec![2, 3]);
+
+    //没有待办流程时默认不完成已有待办,避免查询异常返回空结果时误完成
+    let plan = todo::plan(&[], &open, false);
+    assert!(plan.create.is_empty());
+    assert!(plan.complete.is_empty());
+    let plan = todo::plan(&[], &open, true);
+    assert!(plan.create.is_empty());
+    assert_eq!(plan.complete.len(), 3);
+}
+
+#[tokio::test]
+async fn create_and_complete() {
+    let mock = MockDingTalk::start().await;
+    mock.add_app(APPKEY, APPSECRET);
+    mock.add_user(MOBILE, "manager4220");
+    let app = DDApp::new(&mock.api(), &AppConfig {
+        name: "mock".to_owned(),
+        appkey: APPKEY.to_owned(),
+        appsecret: APPSECRET.to_owned(),
+        robots: vec![],
+        sources: vec![],
+        default: true,
+        agent_id: None
+    });
+
+    let info = app.user_info("manager4220").await.unwrap();
+    assert_eq!(info.mobile, MOBILE);
+    assert_eq!(info.unionid, "union_manager4220");
+    let err = app.user_info("unknown").await.unwrap_err();
+    assert!(err.is_undeliverable(), "{:?}", err);
+
+    let token = app.access_token().await.unwrap();
+    let url = "https://erp.example.com/flow/approve?flownumber=EBS20240525000001".to_owned();
+    let create = |unionid: &str, source_id: &str| {
+        DDTodoCreate::new(
+            app.api(),
+            token.clone(),
+            unionid.to_owned(),
+            source_id.to_owned(),
+            "审批 EBS20240525000001".to_owned(),
+            "采购申请 金额: 1000".to_owned(),
+            Some(1716616252000),
+            Some(DDTodoDetailUrl {
+                app_url: url.clone(),
+                pc_url: url.clone()
+            })
+        )
+    };
+    let first = create(&info.unionid, &format!("EBS20240525000001_{}", MOBILE)).create().await.unwrap();
+    let second = create(&info.unionid, &format!("EBS20240525000002_{}", MOBILE)).create().await.unwrap();
+    assert_ne!(first, second);
+    //相同业务ID不重复创建
+    let retry = create(&info.unionid, &format!("EBS20240525000001_{}", MOBILE)).create().await.unwrap();
+    assert_eq!(retry, first);
+    assert_eq!(mock.todos().len(), 2);
+    let todos = mock.todos();
+    assert_eq!(todos[0].body["sourceId"], format!("EBS20240525000001_{}", MOBILE));
+    assert_eq!(todos[0].unionid, "union_manager4220");
+    assert_eq!(todos[0].body["executorIds"], serde_json::json!(["union_manager4220"]));
+    assert_eq!(todos[0].body["dueTime"], 1716616252000i64);
+    assert_eq!(todos[0].body["detailUrl"]["pcUrl"], url);
+
+    DDTodoTask::new(app.api(), token.clone(), info.unionid.clone(), &first).done().await.unwrap();
+    DDTodoTask::new(app.api(), token.clone(), info.unionid.clone(), &second).delete().await.unwrap();
+    let todos = mock.todos();
+    assert!(todos[0].done && !todos[0].deleted);
+    assert!(todos[1].deleted);
+
+    //待办不存在
+    let err =
+        DDTodoTask::new(app.api(), token.clone(), info.unionid.clone(), &second).done().await.unwrap_err();
+    assert!(matches!(err, Error::DingTalkApi { ref code, .. } if code == "taskNotExist"), "{:?}", err);
+    assert_eq!(mock.calls(Endpoint::TodoUpdate), 2);
+    assert_eq!(mock.calls(Endpoint::TodoDelete), 1);
+}